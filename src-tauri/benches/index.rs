@@ -0,0 +1,94 @@
+//! Compares `Indexer::index` against the original byte-at-a-time scan.
+//!
+//! Run with `cargo bench --bench index`. Set `INDEX_BENCH_MB` to change the
+//! size of the generated log (default 256 MiB).
+
+use log_analysis_lib::indexer::Indexer;
+use std::io::{BufWriter, Write};
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 3;
+
+/// The single-threaded scan `Indexer::index` used before chunked scanning.
+fn byte_scan(data: &[u8]) -> Vec<usize> {
+    let total_size = data.len();
+    let mut offsets = Vec::new();
+    if total_size == 0 {
+        return offsets;
+    }
+
+    offsets.push(0);
+    for (i, &byte) in data.iter().enumerate() {
+        if byte == b'\n' && i + 1 < total_size {
+            offsets.push(i + 1);
+        }
+    }
+    offsets
+}
+
+fn write_sample_log(path: &std::path::Path, target_bytes: usize) {
+    let mut out = BufWriter::new(std::fs::File::create(path).unwrap());
+    let mut written = 0;
+    let mut i = 0usize;
+    while written < target_bytes {
+        let line = format!(
+            "01-02 03:04:05.{:03} {:5} {:5} I ActivityManager: Start proc {}:com.example.app{}/u0a{}\n",
+            i % 1000,
+            1000 + i % 5000,
+            2000 + i % 7000,
+            i,
+            i % 97,
+            i % 300,
+        );
+        out.write_all(line.as_bytes()).unwrap();
+        written += line.len();
+        i += 1;
+    }
+    out.flush().unwrap();
+}
+
+fn best_of<F: FnMut() -> usize>(mut f: F) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut lines = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        lines = f();
+        best = best.min(start.elapsed());
+    }
+    (best, lines)
+}
+
+fn report(name: &str, size: usize, (elapsed, lines): (Duration, usize)) {
+    let mib = size as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:<12} {:>10.2?}  {:>9.1} MiB/s  {} lines",
+        name,
+        elapsed,
+        mib / elapsed.as_secs_f64(),
+        lines
+    );
+}
+
+fn main() {
+    let mb: usize = std::env::var("INDEX_BENCH_MB")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(256);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bench.log");
+    write_sample_log(&path, mb * 1024 * 1024);
+    let data = std::fs::read(&path).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let baseline = best_of(|| byte_scan(&data).len());
+    let chunked = best_of(|| {
+        let mut indexer = Indexer::new(path.clone()).unwrap();
+        runtime.block_on(indexer.index(|_| {}));
+        indexer.line_count()
+    });
+
+    assert_eq!(baseline.1, chunked.1, "line counts differ");
+    report("byte scan", data.len(), baseline);
+    report("index", data.len(), chunked);
+}
@@ -1,11 +1,58 @@
-use std::process::Stdio;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
-use tauri::{AppHandle, Emitter};
-use serde::Serialize;
+use crate::adb_client::AdbClient;
+use crate::capture::CaptureBuffer;
+use crate::document::{Document, DocumentId};
+use crate::event_tags::EventTags;
+use crate::filter::{FilterEngine, FilterOutcome, FilterRun};
+use crate::logcat_binary::{Decoder, LogId};
+use crate::processes::{self, ProcessMap};
+use crate::timestamp;
+use serde::{Deserialize, Serialize};
+use std::process::{ExitStatus, Stdio};
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use crate::filter::FilterEngine;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Runtime};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+use tokio::sync::{oneshot, Mutex, Notify};
+
+/// Lines received from the device. `first_index` is the position of the
+/// first entry of `lines` in the current (filtered) view; anything already
+/// shown from that position on should be replaced. `line_count` is the
+/// number of lines captured so far, visible or not.
+#[derive(Clone, Serialize)]
+pub struct AdbLines {
+    pub document_id: DocumentId,
+    pub first_index: usize,
+    pub lines: Vec<String>,
+    pub line_count: usize,
+}
+
+#[derive(Clone, Serialize)]
+pub struct AdbError {
+    pub document_id: DocumentId,
+    pub message: String,
+}
+
+/// Sent each time a logcat session ends.
+#[derive(Clone, Serialize)]
+pub struct AdbExited {
+    pub document_id: DocumentId,
+    /// The exit code of `adb logcat`, or `None` when the process was killed
+    /// by a signal or logcat ran through the ADB server.
+    pub status: Option<i32>,
+    pub stderr: String,
+    /// Whether the capture waits for the device and resumes.
+    pub reconnecting: bool,
+}
+
+/// Sent when a capture ends on its own rather than through `stop`: logcat
+/// was asked to dump and exit, or reconnecting gave up.
+#[derive(Clone, Serialize)]
+pub struct AdbStopped {
+    pub document_id: DocumentId,
+    pub error: Option<String>,
+}
 
 #[derive(Clone, Serialize)]
 pub struct AdbLine {
@@ -13,68 +60,1512 @@ pub struct AdbLine {
     pub timestamp: u64,
 }
 
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the device's processes are listed while streaming, to catch
+/// those that start without a `Start proc` line.
+const PROCESS_LIST_INTERVAL: Duration = Duration::from_secs(30);
+const PROCESS_LIST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const BUFFERS: &[&str] = &[
+    "main", "system", "radio", "events", "crash", "kernel", "security", "stats", "default", "all",
+];
+/// The `-v` formats `logcat::parse_line` reads. `raw` and `color` are left
+/// out since their lines carry no level or tag to parse.
+const FORMATS: &[&str] = &[
+    "brief",
+    "process",
+    "tag",
+    "thread",
+    "time",
+    "threadtime",
+    "long",
+];
+const MODIFIERS: &[&str] = &[
+    "uid",
+    "year",
+    "zone",
+    "UTC",
+    "epoch",
+    "monotonic",
+    "usec",
+    "nsec",
+    "printable",
+    "descriptive",
+];
+const CONFLICTING_MODIFIERS: &[(&str, &str)] = &[
+    ("epoch", "monotonic"),
+    ("usec", "nsec"),
+    ("year", "epoch"),
+    ("year", "monotonic"),
+    ("zone", "epoch"),
+    ("zone", "monotonic"),
+];
+
+/// Where logcat starts printing: the most recent `count` lines, or the
+/// lines since a `MM-DD hh:mm:ss.mmm`, `YYYY-MM-DD hh:mm:ss.mmm` or
+/// `seconds.mmm` time.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum StartPoint {
+    Count(u32),
+    Time(String),
+}
+
+/// The `adb logcat` options a capture runs with.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LogcatOptions {
+    /// Buffers to read (`-b`); logcat's default set when empty.
+    #[serde(default)]
+    pub buffers: Vec<String>,
+    /// Output format (`-v`), `threadtime` when unset.
+    pub format: Option<String>,
+    /// Format modifiers such as `uid`, `year` or `epoch`, each passed as
+    /// another `-v`.
+    #[serde(default)]
+    pub modifiers: Vec<String>,
+    pub start: Option<StartPoint>,
+    /// Print the lines from `start` and exit (`-t`) instead of streaming on
+    /// from there (`-T`).
+    #[serde(default)]
+    pub dump: bool,
+    /// `tag:priority` filterspecs, such as `ActivityManager:I` or `*:S`.
+    #[serde(default)]
+    pub filterspecs: Vec<String>,
+    /// Read `logger_entry` records (`-B`) instead of text and decode them,
+    /// which keeps the uid, nanosecond timestamps, log ids and multi-line
+    /// messages intact. Takes no format or modifiers.
+    #[serde(default)]
+    pub binary: bool,
+}
+
+impl LogcatOptions {
+    /// Validates the options and builds the arguments that follow `logcat`.
+    pub fn args(&self) -> Result<Vec<String>, String> {
+        let mut args = Vec::new();
+
+        for buffer in &self.buffers {
+            if !BUFFERS.contains(&buffer.as_str()) {
+                return Err(format!(
+                    "Unknown logcat buffer '{}'; expected one of {}",
+                    buffer,
+                    BUFFERS.join(", ")
+                ));
+            }
+            args.extend(["-b".to_string(), buffer.clone()]);
+        }
+        if self.buffers.len() > 1 && self.buffers.iter().any(|b| b == "all") {
+            return Err("The 'all' buffer cannot be combined with other buffers".to_string());
+        }
+
+        if self.binary {
+            if self.format.is_some() || !self.modifiers.is_empty() {
+                return Err("Binary mode does not take a format or format modifiers".to_string());
+            }
+            args.push("-B".to_string());
+        } else {
+            let format = self.format.as_deref().unwrap_or("threadtime");
+            if !FORMATS.contains(&format) {
+                return Err(format!(
+                    "Unsupported logcat format '{}'; expected one of {}",
+                    format,
+                    FORMATS.join(", ")
+                ));
+            }
+            args.extend(["-v".to_string(), format.to_string()]);
+
+            for modifier in &self.modifiers {
+                if !MODIFIERS.contains(&modifier.as_str()) {
+                    return Err(format!(
+                        "Unsupported logcat format modifier '{}'; expected one of {}",
+                        modifier,
+                        MODIFIERS.join(", ")
+                    ));
+                }
+                args.extend(["-v".to_string(), modifier.clone()]);
+            }
+            for (a, b) in CONFLICTING_MODIFIERS {
+                if self.modifiers.iter().any(|m| m == a) && self.modifiers.iter().any(|m| m == b) {
+                    return Err(format!(
+                        "The '{}' and '{}' format modifiers cannot be combined",
+                        a, b
+                    ));
+                }
+            }
+        }
+
+        let flag = if self.dump { "-t" } else { "-T" };
+        match &self.start {
+            Some(StartPoint::Count(0)) => {
+                return Err("The start point must be at least one line".to_string());
+            }
+            Some(StartPoint::Count(count)) => args.extend([flag.to_string(), count.to_string()]),
+            Some(StartPoint::Time(time)) => {
+                if !is_start_time(time) {
+                    return Err(format!(
+                        "Invalid start time '{}'; expected 'MM-DD hh:mm:ss.mmm', \
+                         'YYYY-MM-DD hh:mm:ss.mmm' or 'seconds.mmm'",
+                        time
+                    ));
+                }
+                args.extend([flag.to_string(), time.clone()]);
+            }
+            None if self.dump => {
+                return Err("Dumping and exiting requires a start point".to_string());
+            }
+            None => {}
+        }
+
+        for spec in &self.filterspecs {
+            if !is_filterspec(spec) {
+                return Err(format!(
+                    "Invalid filterspec '{}'; expected tag:priority with a priority of \
+                     V, D, I, W, E, F or S",
+                    spec
+                ));
+            }
+            args.push(spec.clone());
+        }
+
+        Ok(args)
+    }
+}
+
+impl LogcatOptions {
+    /// The options to resume with after logcat exits: from the time of the
+    /// last line captured, so lines still in the device's buffer are not
+    /// captured twice. Formats without wall-clock times resume from the
+    /// original start point.
+    fn resumed(&self, since: Option<&str>) -> LogcatOptions {
+        let mut options = self.clone();
+        if let Some(since) = since.filter(|_| !self.modifiers.iter().any(|m| m == "monotonic")) {
+            options.start = Some(StartPoint::Time(since.to_string()));
+        }
+        options
+    }
+}
+
+fn is_start_time(time: &str) -> bool {
+    leading_time(time) == Some(time.len())
+}
+
+/// The length of the timestamp `s` starts with, in a form `-T` accepts.
+fn leading_time(s: &str) -> Option<usize> {
+    let bytes = s.as_bytes();
+    timestamp::parse_dated(bytes)
+        .or_else(|| timestamp::parse_undated(bytes))
+        .or_else(|| timestamp::parse_epoch(bytes))
+        .map(|(_, len)| len)
+}
+
+/// The timestamp a logcat line starts with, including `long` headers.
+fn line_time(line: &str) -> Option<&str> {
+    let s = line.trim_start();
+    let s = s.strip_prefix("[ ").unwrap_or(s).trim_start();
+    leading_time(s).map(|len| &s[..len])
+}
+
+/// How a capture recovers when `adb logcat` exits, e.g. because the cable
+/// came loose or the device rebooted.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReconnectPolicy {
+    /// Delay before the first attempt to resume, doubled after each attempt
+    /// that fails.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Attempts in a row that may fail before the capture gives up; 0 never
+    /// resumes.
+    pub max_attempts: u32,
+    /// How long one attempt waits for the device to come back.
+    pub wait_timeout_ms: u64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff_ms: 500,
+            max_backoff_ms: 10_000,
+            max_attempts: 10,
+            wait_timeout_ms: 60_000,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// The delay before the given attempt, counting from 1.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u64
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u64::MAX);
+        Duration::from_millis(
+            self.initial_backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+}
+
+/// `tag:priority`, where the tag may be `*`. A bare tag means verbose.
+fn is_filterspec(spec: &str) -> bool {
+    let (tag, priority) = spec.rsplit_once(':').unwrap_or((spec, "V"));
+    !tag.is_empty()
+        && !tag.contains(char::is_whitespace)
+        && matches!(priority, "V" | "D" | "I" | "W" | "E" | "F" | "S")
+}
+
+/// Where a capture stores its lines and keeps its filtered view.
+struct Capture {
+    document_id: DocumentId,
+    lines_buffer: Arc<Mutex<CaptureBuffer>>,
+    filtered_indices: Arc<Mutex<Option<Vec<usize>>>>,
+    filtered_context: Arc<Mutex<Option<Vec<bool>>>>,
+    active_filter: Arc<Mutex<Option<FilterEngine>>>,
+    processes: Arc<Mutex<Option<Arc<ProcessMap>>>>,
+}
+
+impl Capture {
+    /// Moves `batch` into the buffer, extends the filtered view and emits
+    /// the update for the frontend.
+    async fn ingest<R: Runtime>(&self, app: &AppHandle<R>, batch: &mut Vec<String>) {
+        let mut buffer = self.lines_buffer.lock().await;
+        let first_new = buffer.len();
+        if batch.iter().any(|line| line.contains("Start proc ")) {
+            self.update_processes(|map| map.scan(batch)).await;
+        }
+        if let Err(message) = buffer.push(batch) {
+            let _ = app.emit(
+                "adb-error",
+                AdbError {
+                    document_id: self.document_id,
+                    message,
+                },
+            );
+        }
+
+        let engine = self.active_filter.lock().await;
+        let mut filtered = self.filtered_indices.lock().await;
+        let mut context = self.filtered_context.lock().await;
+        let (first_index, indices): (usize, Vec<usize>) = match (engine.as_ref(), filtered.as_mut())
+        {
+            (Some(engine), Some(filtered)) => {
+                let first_index =
+                    append_filtered(engine, &buffer, filtered, context.as_mut(), first_new);
+                (first_index, filtered[first_index..].to_vec())
+            }
+            _ => (first_new, (first_new..buffer.len()).collect()),
+        };
+        let lines = indices
+            .into_iter()
+            .filter_map(|i| buffer.get(i).map(|line| line.into_owned()))
+            .collect();
+
+        let _ = app.emit(
+            "adb-new-lines",
+            AdbLines {
+                document_id: self.document_id,
+                first_index,
+                lines,
+                line_count: buffer.len(),
+            },
+        );
+    }
+
+    /// The time a process listing taken now is from: that of the last
+    /// captured line that has one, looking back over at most a few hundred
+    /// lines, or before everything while nothing is captured yet.
+    async fn listing_time(&self) -> Option<i64> {
+        let buffer = self.lines_buffer.lock().await;
+        if buffer.is_empty() {
+            return Some(i64::MIN);
+        }
+        (buffer.len().saturating_sub(500)..buffer.len())
+            .rev()
+            .find_map(|i| timestamp::parse_line_timestamp(buffer.get(i)?.as_bytes()))
+    }
+
+    /// Applies `update` to the process map and, if it changed anything,
+    /// hands the new map to the active filter.
+    async fn update_processes(&self, update: impl FnOnce(&mut ProcessMap) -> bool) {
+        let mut processes = self.processes.lock().await;
+        let mut map = processes.as_deref().cloned().unwrap_or_default();
+        if !update(&mut map) {
+            return;
+        }
+        let map = Arc::new(map);
+        *processes = Some(Arc::clone(&map));
+        if let Some(engine) = self.active_filter.lock().await.as_mut() {
+            engine.set_processes(map);
+        }
+    }
+}
+
+/// A device known to the ADB server, as listed by `adb devices -l`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AdbDevice {
+    pub serial: String,
+    /// `device` when usable; otherwise e.g. `offline`, `unauthorized` or
+    /// `no permissions`.
+    pub state: String,
+    pub model: Option<String>,
+    pub product: Option<String>,
+}
+
+/// Lists the devices attached to the ADB server by running `adb devices`,
+/// which starts the server if it is not running.
+pub async fn list_devices() -> Result<Vec<AdbDevice>, String> {
+    let output = Command::new("adb")
+        .arg("devices")
+        .arg("-l")
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run adb: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "adb devices failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(parse_devices(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses the output of `adb devices -l`.
+pub fn parse_devices(output: &str) -> Vec<AdbDevice> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with("List of devices") && !line.starts_with('*'))
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let serial = words.next()?.to_string();
+            let mut device = AdbDevice {
+                serial,
+                state: String::new(),
+                model: None,
+                product: None,
+            };
+
+            // The state runs up to the first property or explanation, as in
+            // `no permissions (user in plugdev group; ...)`.
+            let mut state = Vec::new();
+            let mut in_state = true;
+            for word in words {
+                in_state &= !word.starts_with('(') && !word.contains(':');
+                if in_state {
+                    state.push(word);
+                    continue;
+                }
+                match word.split_once(':') {
+                    Some(("model", value)) => device.model = Some(value.to_string()),
+                    Some(("product", value)) => device.product = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+
+            device.state = state.join(" ");
+            Some(device)
+        })
+        .collect()
+}
+
 pub struct AdbManager {
-    child: Option<tokio::process::Child>,
+    /// Dropping this stops the running capture.
+    stop: Option<oneshot::Sender<()>>,
+    client: AdbClient,
+}
+
+impl Default for AdbManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AdbManager {
     pub fn new() -> Self {
-        Self { child: None }
+        Self::with_client(AdbClient::default())
+    }
+
+    /// A manager that talks to the ADB server through `client`.
+    pub fn with_client(client: AdbClient) -> Self {
+        Self { stop: None, client }
     }
 
-    pub async fn start_streaming(
+    /// Starts `adb logcat` with the given options on the device with the
+    /// given `serial`, or the only one attached, and stores every line it
+    /// prints in the document's ADB buffer. While the document has an active
+    /// filter, its filtered offsets and context flags are kept up to date
+    /// with the buffer indices of the lines it shows, so the filters can be
+    /// changed at any time without losing lines.
+    ///
+    /// Logcat, `ps` and waiting for the device go through the ADB server.
+    /// Only while no server is listening does the capture run the `adb`
+    /// executable instead, which starts one.
+    ///
+    /// When logcat exits, the capture waits for the device as `reconnect`
+    /// allows and resumes after a session marker line. The document's
+    /// `is_adb_active` stays true until the capture ends. In binary mode,
+    /// events are rendered with the current `event_tags`. `Start proc`
+    /// lines and a `ps -A` listing every `PROCESS_LIST_INTERVAL`, taken
+    /// alongside the stream and again after each reconnect, keep the
+    /// document's process map current.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn start_streaming<R: Runtime>(
         &mut self,
-        app: AppHandle,
-        filters: Option<FilterEngine>,
-        lines_buffer: Arc<Mutex<Vec<String>>>,
+        app: AppHandle<R>,
+        document_id: DocumentId,
+        document: &Document,
+        serial: Option<String>,
+        logcat: LogcatOptions,
+        reconnect: ReconnectPolicy,
+        event_tags: Arc<Mutex<Arc<EventTags>>>,
     ) -> Result<(), String> {
         self.stop();
 
-        let mut child = Command::new("adb")
-            .arg("logcat")
-            .arg("-v")
-            .arg("time")
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|e| format!("Failed to start adb: {}", e))?;
-
-        let stdout = child.stdout.take().ok_or("Failed to capture stdout")?;
-        self.child = Some(child);
+        let stream = Stream {
+            app,
+            client: self.client.clone(),
+            capture: Capture {
+                document_id,
+                lines_buffer: Arc::clone(&document.adb_buffer),
+                filtered_indices: Arc::clone(&document.filtered_offsets),
+                filtered_context: Arc::clone(&document.filtered_context),
+                active_filter: Arc::clone(&document.active_filter),
+                processes: Arc::clone(&document.processes),
+            },
+            serial,
+            logcat,
+            reconnect,
+            event_tags,
+            relist: Notify::new(),
+        };
+        let output = stream.spawn(&stream.logcat).await?;
+        let is_active = Arc::clone(&document.is_adb_active);
+        *is_active.lock().await = true;
 
+        let stream = Arc::new(stream);
+        let (stop, stopped) = oneshot::channel();
+        self.stop = Some(stop);
         tokio::spawn(async move {
-            let mut reader = BufReader::new(stdout).lines();
-            let mut batch = Vec::new();
-            let mut last_emit = std::time::Instant::now();
-
-            while let Ok(Some(line)) = reader.next_line().await {
-                let should_add = if let Some(ref engine) = filters {
-                    engine.matches(&line)
-                } else {
-                    true
+            // Listing is slow, so it runs beside the stream rather than
+            // holding up reading logcat's output.
+            let listing = tokio::spawn({
+                let stream = Arc::clone(&stream);
+                async move { stream.list_processes().await }
+            });
+            // Stopping drops `run`, and with it any adb process it spawned.
+            let error = tokio::select! {
+                error = stream.run(output) => Some(error),
+                _ = stopped => None,
+            };
+            listing.abort();
+            let Some(error) = error else {
+                return;
+            };
+            *is_active.lock().await = false;
+            let _ = stream.app.emit(
+                "adb-stopped",
+                AdbStopped {
+                    document_id: stream.capture.document_id,
+                    error,
+                },
+            );
+        });
+
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.stop = None;
+    }
+}
+
+fn adb_command(serial: Option<&str>) -> Command {
+    let mut command = Command::new("adb");
+    if let Some(serial) = serial {
+        command.arg("-s").arg(serial);
+    }
+    command.stdin(Stdio::null()).kill_on_drop(true);
+    command
+}
+
+fn spawn_logcat(serial: Option<&str>, options: &LogcatOptions) -> Result<Child, String> {
+    let args = options.args()?;
+    let mut command = adb_command(serial);
+    if options.binary {
+        // `exec-out` passes the bytes through without a terminal that could
+        // rewrite newlines.
+        command.arg("exec-out");
+    }
+    command
+        .arg("logcat")
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to start adb: {}", e))
+}
+
+/// Where the output of one logcat session comes from.
+enum Output {
+    /// An `exec:logcat` service on the ADB server.
+    Server(TcpStream),
+    /// An `adb logcat` process, while no server is listening.
+    Process(Child),
+}
+
+/// What one logcat session did before it ended. The status and stderr are
+/// only known for an `adb` process.
+struct Session {
+    lines: usize,
+    status: Option<ExitStatus>,
+    stderr: String,
+}
+
+/// A running capture: the logcat sessions of one document.
+struct Stream<R: Runtime> {
+    app: AppHandle<R>,
+    client: AdbClient,
+    capture: Capture,
+    serial: Option<String>,
+    logcat: LogcatOptions,
+    reconnect: ReconnectPolicy,
+    event_tags: Arc<Mutex<Arc<EventTags>>>,
+    /// Asks for the processes to be listed again now.
+    relist: Notify,
+}
+
+impl<R: Runtime> Stream<R> {
+    /// Streams from `output` and from every logcat started after it ends,
+    /// until logcat finishes a dump or reconnecting gives up. Returns why
+    /// the capture ended, if that was an error.
+    async fn run(&self, mut output: Output) -> Option<String> {
+        let mut position = Position::default();
+        let mut attempts = 0;
+        let mut number = 1;
+
+        loop {
+            let session = self.read(output, &mut position).await;
+            if session.lines > 0 {
+                attempts = 0;
+            }
+
+            let reconnecting = !self.logcat.dump && self.reconnect.max_attempts > 0;
+            let _ = self.app.emit(
+                "adb-exited",
+                AdbExited {
+                    document_id: self.capture.document_id,
+                    status: session.status.and_then(|s| s.code()),
+                    stderr: session.stderr.clone(),
+                    reconnecting,
+                },
+            );
+            if !reconnecting {
+                let failed = session.status.is_some_and(|s| !s.success());
+                return failed.then(|| describe_exit(&session));
+            }
+
+            let options = self.logcat.resumed(position.since.as_deref());
+            output = match self.resume(&mut attempts, &options).await {
+                Ok(output) => output,
+                Err(e) => return Some(e),
+            };
+            if matches!(options.start, Some(StartPoint::Time(_))) {
+                position.skip_replayed();
+            }
+            self.relist.notify_one();
+            number += 1;
+            let marker = format!(
+                "--------- session {}: resumed after {}",
+                number,
+                describe_exit(&session)
+            );
+            self.capture.ingest(&self.app, &mut vec![marker]).await;
+        }
+    }
+
+    /// Starts logcat with `options` through the server, or as an `adb`
+    /// process while no server is listening.
+    async fn spawn(&self, options: &LogcatOptions) -> Result<Output, String> {
+        let args = options.args()?;
+        if self.client.is_reachable().await {
+            let stream = self.client.logcat(self.serial.as_deref(), &args).await?;
+            return Ok(Output::Server(stream));
+        }
+        spawn_logcat(self.serial.as_deref(), options).map(Output::Process)
+    }
+
+    /// Captures what `output` prints until it ends, keeping `position` at
+    /// the last line captured.
+    async fn read(&self, output: Output, position: &mut Position) -> Session {
+        let mut child = match output {
+            Output::Server(stream) => {
+                let (lines, _) = self.read_output(stream, position).await;
+                return Session {
+                    lines,
+                    status: None,
+                    stderr: String::new(),
                 };
+            }
+            Output::Process(child) => child,
+        };
+
+        let stderr = child.stderr.take().map(|mut stderr| {
+            tokio::spawn(async move {
+                let mut bytes = Vec::new();
+                let _ = stderr.read_to_end(&mut bytes).await;
+                String::from_utf8_lossy(&bytes).trim().to_string()
+            })
+        });
+
+        let (lines, decoded) = match child.stdout.take() {
+            Some(stdout) => self.read_output(stdout, position).await,
+            None => (0, true),
+        };
+        if !decoded {
+            let _ = child.start_kill();
+        }
+
+        let status = child.wait().await.ok();
+        let stderr = match stderr {
+            Some(task) => task.await.unwrap_or_default(),
+            None => String::new(),
+        };
+        Session {
+            lines,
+            status,
+            stderr,
+        }
+    }
+
+    /// Captures logcat's text or binary output. Returns the number of lines
+    /// and whether all of the output could be decoded.
+    async fn read_output<T: AsyncRead + Unpin>(
+        &self,
+        output: T,
+        position: &mut Position,
+    ) -> (usize, bool) {
+        if self.logcat.binary {
+            self.read_binary(output, position).await
+        } else {
+            (self.read_text(output, position).await, true)
+        }
+    }
+
+    async fn read_text<T: AsyncRead + Unpin>(&self, output: T, position: &mut Position) -> usize {
+        let mut reader = BufReader::new(output).lines();
+        let mut batch = Batch::default();
+
+        loop {
+            // Wake up now and then so the last lines from a quiet device
+            // still get stored and shown.
+            let next = tokio::time::timeout(FLUSH_INTERVAL, reader.next_line()).await;
+            let ended = matches!(next, Ok(Ok(None)) | Ok(Err(_)));
+            if let Ok(Ok(Some(line))) = next {
+                if position.keep(&line, line_time(&line)) {
+                    batch.lines.push(line);
+                }
+            }
 
-                if should_add {
-                    let mut buffer = lines_buffer.lock().await;
-                    buffer.push(line.clone());
-                    batch.push(line);
+            if batch.is_due(ended) {
+                batch.flush(self).await;
+            }
+            if ended {
+                return batch.flushed;
+            }
+        }
+    }
+
+    /// Decodes `logcat -B` output into lines, with a divider line wherever
+    /// the entries switch buffers. Stops early, returning false, if the
+    /// output cannot be decoded.
+    async fn read_binary<T: AsyncRead + Unpin>(
+        &self,
+        mut output: T,
+        position: &mut Position,
+    ) -> (usize, bool) {
+        let mut decoder = Decoder::default();
+        let mut chunk = vec![0u8; 64 * 1024];
+        let mut batch = Batch::default();
+        let mut seen: Vec<LogId> = Vec::new();
+        let mut current = None;
+
+        loop {
+            let next = tokio::time::timeout(FLUSH_INTERVAL, output.read(&mut chunk)).await;
+            let read = match next {
+                Ok(Ok(n)) => n,
+                Ok(Err(_)) => 0,
+                Err(_) => {
+                    if batch.is_due(false) {
+                        batch.flush(self).await;
+                    }
+                    continue;
                 }
+            };
+            let ended = read == 0;
 
-                // Batch emissions every 100ms or 100 lines to reduce IPC overhead
-                if batch.len() >= 100 || (last_emit.elapsed().as_millis() >= 100 && !batch.is_empty()) {
-                    let _ = app.emit("adb-new-lines", &batch);
-                    batch.clear();
-                    last_emit = std::time::Instant::now();
+            match decoder.feed(&chunk[..read]) {
+                Ok(entries) => {
+                    let tags = Arc::clone(&*self.event_tags.lock().await);
+                    for entry in entries {
+                        let time = format!("{}.{:03}", entry.sec, entry.nsec / 1_000_000);
+                        let mut lines = entry.to_lines(&tags);
+                        lines.retain(|line| position.keep(line, Some(&time)));
+                        if lines.is_empty() {
+                            continue;
+                        }
+                        if let Some(id) = entry.log_id.filter(|&id| current != Some(id)) {
+                            let verb = if seen.contains(&id) {
+                                "switch to"
+                            } else {
+                                seen.push(id);
+                                "beginning of"
+                            };
+                            batch
+                                .lines
+                                .push(format!("--------- {} {}", verb, id.name()));
+                            current = Some(id);
+                        }
+                        batch.lines.extend(lines);
+                    }
+                }
+                Err(message) => {
+                    let _ = self.app.emit(
+                        "adb-error",
+                        AdbError {
+                            document_id: self.capture.document_id,
+                            message: format!("Failed to decode binary logcat output: {}", message),
+                        },
+                    );
+                    batch.flush(self).await;
+                    return (batch.flushed, false);
                 }
             }
+
+            if batch.is_due(ended) {
+                batch.flush(self).await;
+            }
+            if ended {
+                return (batch.flushed, true);
+            }
+        }
+    }
+
+    /// Waits for the device and starts logcat again, backing off between
+    /// attempts. Fails once the policy's attempts are used up.
+    async fn resume(&self, attempts: &mut u32, options: &LogcatOptions) -> Result<Output, String> {
+        options.args()?;
+        loop {
+            if *attempts >= self.reconnect.max_attempts {
+                return Err(format!(
+                    "Gave up reconnecting to the device after {} attempts",
+                    attempts
+                ));
+            }
+            *attempts += 1;
+            tokio::time::sleep(self.reconnect.backoff(*attempts)).await;
+
+            let resumed = match self.wait_for_device().await {
+                Ok(()) => self.spawn(options).await,
+                Err(e) => Err(e),
+            };
+            match resumed {
+                Ok(output) => return Ok(output),
+                Err(message) => {
+                    let _ = self.app.emit(
+                        "adb-error",
+                        AdbError {
+                            document_id: self.capture.document_id,
+                            message,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Lists the device's processes into the process map every
+    /// `PROCESS_LIST_INTERVAL` and whenever `relist` is notified. A failed
+    /// listing is skipped quietly; `Start proc` lines still
+    /// name the apps.
+    async fn list_processes(&self) {
+        loop {
+            // Lines captured while `ps` runs may already be from processes
+            // it lists, so the time is taken before.
+            let time = self.capture.listing_time().await;
+            if let Ok(listing) = tokio::time::timeout(PROCESS_LIST_TIMEOUT, self.ps()).await {
+                self.capture
+                    .update_processes(|map| map.observe(&listing, time))
+                    .await;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(PROCESS_LIST_INTERVAL) => {}
+                _ = self.relist.notified() => {}
+            }
+        }
+    }
+
+    /// Runs `ps -A`, or plain `ps` on devices from before Android 8 where
+    /// that already lists every process.
+    async fn ps(&self) -> String {
+        let reachable = self.client.is_reachable().await;
+        let mut listing = String::new();
+        for command in ["ps -A", "ps"] {
+            let output = if reachable {
+                self.client.shell(self.serial.as_deref(), command).await
+            } else {
+                shell_command(self.serial.as_deref(), command).await
+            };
+            let Ok(output) = output else {
+                break;
+            };
+            listing = output;
+            if processes::parse_ps(&listing).len() > 1 {
+                break;
+            }
+        }
+        listing
+    }
+
+    /// Waits until the device is online again, through the server's
+    /// `wait-for` service or `adb wait-for-device` while no server is
+    /// listening.
+    async fn wait_for_device(&self) -> Result<(), String> {
+        let timeout = Duration::from_millis(self.reconnect.wait_timeout_ms);
+        let serial = self.serial.as_deref();
+        let waited = if self.client.is_reachable().await {
+            tokio::time::timeout(timeout, self.client.wait_for_device(serial)).await
+        } else {
+            tokio::time::timeout(timeout, wait_for_device_command(serial)).await
+        };
+        waited.map_err(|_| "Timed out waiting for the device".to_string())?
+    }
+}
+
+/// Runs `command` in the device's shell through the `adb` executable.
+async fn shell_command(serial: Option<&str>, command: &str) -> Result<String, String> {
+    let output = adb_command(serial)
+        .arg("shell")
+        .args(command.split_whitespace())
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run adb: {}", e))?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+async fn wait_for_device_command(serial: Option<&str>) -> Result<(), String> {
+    let output = adb_command(serial)
+        .arg("wait-for-device")
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run adb: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "adb wait-for-device failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Lines read but not yet stored.
+struct Batch {
+    lines: Vec<String>,
+    last_emit: std::time::Instant,
+    flushed: usize,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self {
+            lines: Vec::new(),
+            last_emit: std::time::Instant::now(),
+            flushed: 0,
+        }
+    }
+}
+
+/// How far a capture has got: the time of the last line captured and the
+/// lines captured at that time. Logcat resumed with `-T <time>` prints the
+/// lines from that time on again, so those are skipped until the first
+/// line that was not captured yet.
+#[derive(Debug, Default)]
+struct Position {
+    since: Option<String>,
+    /// The lines from the last one with the time `since` on, including the
+    /// untimed lines that continue an entry.
+    tail: Vec<String>,
+    /// How many lines of `tail` a resumed logcat has printed again so far,
+    /// while it is still replaying them.
+    replayed: Option<usize>,
+}
+
+impl Position {
+    /// Whether to capture `line`, whose timestamp is `time`. Divider lines
+    /// are always captured.
+    fn keep(&mut self, line: &str, time: Option<&str>) -> bool {
+        if line.starts_with("--------- ") {
+            return true;
+        }
+        if let Some(replayed) = self.replayed {
+            if self.tail.get(replayed).is_some_and(|seen| seen == line) {
+                self.replayed = (replayed + 1 < self.tail.len()).then_some(replayed + 1);
+                return false;
+            }
+            self.replayed = None;
+        }
+
+        match time {
+            Some(time) if self.since.as_deref() != Some(time) => {
+                self.since = Some(time.to_string());
+                self.tail.clear();
+                self.tail.push(line.to_string());
+            }
+            _ if self.since.is_some() => self.tail.push(line.to_string()),
+            _ => {}
+        }
+        true
+    }
+
+    /// Starts skipping the lines a logcat resumed from `since` prints again.
+    fn skip_replayed(&mut self) {
+        self.replayed = (!self.tail.is_empty()).then_some(0);
+    }
+}
+
+impl Batch {
+    /// Batch emissions every 100ms or 100 lines to reduce IPC overhead.
+    fn is_due(&self, ended: bool) -> bool {
+        !self.lines.is_empty()
+            && (ended || self.lines.len() >= 100 || self.last_emit.elapsed() >= FLUSH_INTERVAL)
+    }
+
+    async fn flush<R: Runtime>(&mut self, stream: &Stream<R>) {
+        self.flushed += self.lines.len();
+        stream.capture.ingest(&stream.app, &mut self.lines).await;
+        self.last_emit = std::time::Instant::now();
+    }
+}
+
+fn describe_exit(session: &Session) -> String {
+    let status = match session.status {
+        Some(status) => match status.code() {
+            Some(code) => format!("adb logcat exited with status {}", code),
+            None => "adb logcat was killed".to_string(),
+        },
+        None => "logcat exited".to_string(),
+    };
+    match session.stderr.lines().last() {
+        Some(last) => format!("{}: {}", status, last),
+        None => status,
+    }
+}
+
+/// Filters the captured lines, or only those at `within` when the engine
+/// narrows the result they came from. Returns buffer indices.
+pub fn filter_buffer(
+    engine: &FilterEngine,
+    buffer: &CaptureBuffer,
+    within: Option<&[usize]>,
+    run: &FilterRun,
+) -> Option<FilterOutcome> {
+    let Some(indices) = within else {
+        return engine.run_filter(0..buffer.len(), |i| buffer.get(i).unwrap_or_default(), run);
+    };
+
+    let mut outcome = engine.run_filter(
+        0..indices.len(),
+        |i| buffer.get(indices[i]).unwrap_or_default(),
+        run,
+    )?;
+    for visible in &mut outcome.visible {
+        *visible = indices[*visible];
+    }
+    Some(outcome)
+}
+
+/// Extends the filtered view with lines from `first_new` on and returns the
+/// first view position that changed. Lines a new match could pull in as
+/// context are re-evaluated along with the new ones.
+fn append_filtered(
+    engine: &FilterEngine,
+    buffer: &CaptureBuffer,
+    filtered: &mut Vec<usize>,
+    context: Option<&mut Vec<bool>>,
+    first_new: usize,
+) -> usize {
+    let redo_from = first_new.saturating_sub(engine.max_context().0);
+    let keep = filtered.partition_point(|&i| i < redo_from);
+    filtered.truncate(keep);
+
+    let tail = engine.filter_by(redo_from..buffer.len(), |i| {
+        buffer.get(i).unwrap_or_default()
+    });
+    filtered.extend(tail.visible);
+    if let Some(context) = context {
+        context.truncate(keep);
+        context.extend(tail.context.unwrap_or_default());
+    }
+    keep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::{FilterField, LogFilter};
+
+    const DEVICES: &str = "\
+* daemon not running; starting now at tcp:5037
+* daemon started successfully
+List of devices attached
+emulator-5554          device product:sdk_gphone64_x86_64 model:sdk_gphone64_x86_64 device:emu64xa transport_id:1
+0A1B2C3D               unauthorized usb:1-4 transport_id:2
+R58M123456             no permissions (user in plugdev group; are your udev rules wrong?); see [http://developer.android.com/tools/device.html] usb:1-2 transport_id:3
+192.168.1.20:5555      offline transport_id:4
+
+";
+
+    #[test]
+    fn parses_device_list() {
+        let devices = parse_devices(DEVICES);
+        assert_eq!(
+            devices,
+            vec![
+                AdbDevice {
+                    serial: "emulator-5554".to_string(),
+                    state: "device".to_string(),
+                    model: Some("sdk_gphone64_x86_64".to_string()),
+                    product: Some("sdk_gphone64_x86_64".to_string()),
+                },
+                AdbDevice {
+                    serial: "0A1B2C3D".to_string(),
+                    state: "unauthorized".to_string(),
+                    model: None,
+                    product: None,
+                },
+                AdbDevice {
+                    serial: "R58M123456".to_string(),
+                    state: "no permissions".to_string(),
+                    model: None,
+                    product: None,
+                },
+                AdbDevice {
+                    serial: "192.168.1.20:5555".to_string(),
+                    state: "offline".to_string(),
+                    model: None,
+                    product: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn builds_logcat_args() {
+        let options = LogcatOptions {
+            buffers: vec!["main".to_string(), "crash".to_string()],
+            modifiers: vec!["uid".to_string(), "year".to_string()],
+            start: Some(StartPoint::Time("2024-01-02 03:04:05.678".to_string())),
+            filterspecs: vec!["ActivityManager:I".to_string(), "*:S".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            options.args().unwrap(),
+            [
+                "-b",
+                "main",
+                "-b",
+                "crash",
+                "-v",
+                "threadtime",
+                "-v",
+                "uid",
+                "-v",
+                "year",
+                "-T",
+                "2024-01-02 03:04:05.678",
+                "ActivityManager:I",
+                "*:S"
+            ]
+        );
+
+        let dump = LogcatOptions {
+            format: Some("long".to_string()),
+            start: Some(StartPoint::Count(100)),
+            dump: true,
+            ..Default::default()
+        };
+        assert_eq!(dump.args().unwrap(), ["-v", "long", "-t", "100"]);
+
+        let binary = LogcatOptions {
+            buffers: vec!["all".to_string()],
+            binary: true,
+            ..Default::default()
+        };
+        assert_eq!(binary.args().unwrap(), ["-b", "all", "-B"]);
+    }
+
+    #[test]
+    fn rejects_invalid_logcat_options() {
+        let invalid = [
+            LogcatOptions {
+                buffers: vec!["mian".to_string()],
+                ..Default::default()
+            },
+            LogcatOptions {
+                buffers: vec!["all".to_string(), "main".to_string()],
+                ..Default::default()
+            },
+            LogcatOptions {
+                format: Some("raw".to_string()),
+                ..Default::default()
+            },
+            LogcatOptions {
+                modifiers: vec!["epoch".to_string(), "monotonic".to_string()],
+                ..Default::default()
+            },
+            LogcatOptions {
+                start: Some(StartPoint::Time("yesterday".to_string())),
+                ..Default::default()
+            },
+            LogcatOptions {
+                start: Some(StartPoint::Count(0)),
+                ..Default::default()
+            },
+            LogcatOptions {
+                dump: true,
+                ..Default::default()
+            },
+            LogcatOptions {
+                filterspecs: vec!["ActivityManager:X".to_string()],
+                ..Default::default()
+            },
+            LogcatOptions {
+                format: Some("threadtime".to_string()),
+                binary: true,
+                ..Default::default()
+            },
+        ];
+        for options in invalid {
+            assert!(options.args().is_err(), "{:?} was accepted", options);
+        }
+    }
+
+    #[test]
+    fn backs_off_up_to_the_limit() {
+        let policy = ReconnectPolicy {
+            initial_backoff_ms: 250,
+            max_backoff_ms: 3_000,
+            ..Default::default()
+        };
+        let delays: Vec<u64> = (1..=6)
+            .map(|attempt| policy.backoff(attempt).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [250, 500, 1_000, 2_000, 3_000, 3_000]);
+        assert_eq!(policy.backoff(100), Duration::from_millis(3_000));
+    }
+
+    #[test]
+    fn resumes_from_the_last_line_time() {
+        assert_eq!(
+            line_time("01-02 03:04:05.678  1234  1250 I ActivityManager: Start proc"),
+            Some("01-02 03:04:05.678")
+        );
+        assert_eq!(
+            line_time("[ 2024-01-02 03:04:05.678  1234: 1250 I/ActivityManager ]"),
+            Some("2024-01-02 03:04:05.678")
+        );
+        assert_eq!(line_time("I/ActivityManager( 1234): Start proc"), None);
+
+        let options = LogcatOptions {
+            start: Some(StartPoint::Count(500)),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.resumed(Some("01-02 03:04:05.678")).args().unwrap(),
+            ["-v", "threadtime", "-T", "01-02 03:04:05.678"]
+        );
+        assert_eq!(
+            options.resumed(None).args().unwrap(),
+            ["-v", "threadtime", "-T", "500"]
+        );
+    }
+
+    #[test]
+    fn skips_lines_replayed_after_resuming() {
+        let captured = [
+            "--------- beginning of main",
+            "01-02 03:04:05.000  1  1 I A: before",
+            "01-02 03:04:06.000  1  1 I A: first at the last time",
+            "01-02 03:04:06.000  1  1 I A: second at the last time",
+            "\tcontinued",
+        ];
+        let mut position = Position::default();
+        for line in captured {
+            assert!(position.keep(line, line_time(line)));
+        }
+        assert_eq!(position.since.as_deref(), Some("01-02 03:04:06.000"));
+        assert_eq!(position.tail.len(), 3);
+
+        // `-T 01-02 03:04:06.000` prints the last three lines again.
+        position.skip_replayed();
+        let resumed = [
+            ("--------- beginning of main", true),
+            (
+                "01-02 03:04:06.000  1  1 I A: first at the last time",
+                false,
+            ),
+            (
+                "01-02 03:04:06.000  1  1 I A: second at the last time",
+                false,
+            ),
+            ("\tcontinued", false),
+            ("01-02 03:04:06.000  1  1 I A: new at the last time", true),
+            ("\tcontinued", true),
+            ("01-02 03:04:07.000  1  1 I A: later", true),
+        ];
+        for (line, kept) in resumed {
+            assert_eq!(position.keep(line, line_time(line)), kept, "{}", line);
+        }
+
+        // Once a line differs, nothing more is skipped.
+        position.skip_replayed();
+        assert!(position.keep(
+            "01-02 03:04:07.000  1  1 I A: not seen",
+            line_time("01-02 03:04:07.000")
+        ));
+        assert!(position.keep(
+            "01-02 03:04:07.000  1  1 I A: later",
+            line_time("01-02 03:04:07.000")
+        ));
+
+        // Without a time to resume from, nothing is tracked or skipped.
+        let mut untimed = Position::default();
+        assert!(untimed.keep("I/A( 1): x", None));
+        untimed.skip_replayed();
+        assert!(untimed.keep("I/A( 1): x", None));
+    }
+
+    fn logcat_line(i: usize, pid: u32, message: &str) -> String {
+        format!(
+            "01-02 03:04:05.{:03}  {:4}  {:4} I Tag: {}",
+            i, pid, pid, message
+        )
+    }
+
+    fn filter(pattern: &str, field: Option<FilterField>, context: usize) -> LogFilter {
+        LogFilter {
+            id: pattern.to_string(),
+            pattern: pattern.to_string(),
+            is_include: true,
+            is_enabled: true,
+            color: None,
+            text_color: None,
+            field,
+            context_before: context,
+            context_after: context,
+        }
+    }
+
+    #[test]
+    fn filters_a_spilled_buffer_with_context_and_processes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut buffer = CaptureBuffer::new(4, dir.path().to_path_buf());
+        let mut lines: Vec<String> = (0..20)
+            .map(|i| match i {
+                3 => logcat_line(i, 600, "Start proc 42:com.app/u0a1 for activity"),
+                5 | 12 | 17 => logcat_line(i, 42, "hello"),
+                _ => logcat_line(i, 100, "other"),
+            })
+            .collect();
+        buffer.push(&mut lines).unwrap();
+        assert!(buffer.spilled_len() > 0);
+
+        let mut engine =
+            FilterEngine::new(vec![filter("com.app", Some(FilterField::Package), 1)]).unwrap();
+        let processes = ProcessMap::collect(buffer.len(), |i| buffer.get(i).unwrap_or_default());
+        engine.set_processes(Arc::new(processes));
+
+        let outcome = filter_buffer(&engine, &buffer, None, &FilterRun::default()).unwrap();
+        assert_eq!(outcome.match_count, 3);
+        assert_eq!(outcome.visible, [4, 5, 6, 11, 12, 13, 16, 17, 18]);
+        assert_eq!(
+            outcome.context,
+            Some(vec![
+                true, false, true, true, false, true, true, false, true
+            ])
+        );
+
+        // Narrowing re-filters only the lines already shown.
+        let plain = FilterEngine::new(vec![filter("hello", None, 0)]).unwrap();
+        let within = [4, 5, 6, 11, 12];
+        let outcome = filter_buffer(&plain, &buffer, Some(&within), &FilterRun::default()).unwrap();
+        assert_eq!(outcome.visible, [5, 12]);
+    }
+
+    #[test]
+    fn appended_matches_pull_in_earlier_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut buffer = CaptureBuffer::new(4, dir.path().to_path_buf());
+        let engine = FilterEngine::new(vec![{
+            let mut fatal = filter("FATAL", None, 0);
+            fatal.context_before = 3;
+            fatal.context_after = 1;
+            fatal
+        }])
+        .unwrap();
+        let push = |buffer: &mut CaptureBuffer, range: std::ops::Range<usize>| {
+            let mut lines: Vec<String> = range
+                .map(|i| {
+                    let message = if i == 11 || i == 14 { "FATAL" } else { "ok" };
+                    logcat_line(i, 100, message)
+                })
+                .collect();
+            buffer.push(&mut lines).unwrap();
+        };
+
+        push(&mut buffer, 0..10);
+        let outcome = filter_buffer(&engine, &buffer, None, &FilterRun::default()).unwrap();
+        let mut filtered = outcome.visible;
+        let mut context = outcome.context.unwrap();
+        assert!(filtered.is_empty());
+
+        // Lines already captured, some of them spilled, become context.
+        push(&mut buffer, 10..13);
+        let changed = append_filtered(&engine, &buffer, &mut filtered, Some(&mut context), 10);
+        assert_eq!(changed, 0);
+        assert_eq!(filtered, [8, 9, 10, 11, 12]);
+        assert_eq!(context, [true, true, true, false, true]);
+
+        push(&mut buffer, 13..15);
+        let changed = append_filtered(&engine, &buffer, &mut filtered, Some(&mut context), 13);
+        assert_eq!(changed, 2);
+        assert_eq!(filtered, [8, 9, 10, 11, 12, 13, 14]);
+        assert_eq!(context, [true, true, true, false, true, true, false]);
+
+        push(&mut buffer, 15..17);
+        append_filtered(&engine, &buffer, &mut filtered, Some(&mut context), 15);
+        assert_eq!(filtered, [8, 9, 10, 11, 12, 13, 14, 15]);
+        assert_eq!(context, [true, true, true, false, true, true, false, true]);
+        assert!(buffer.spilled_len() > 0);
+
+        // The whole buffer filtered at once gives the same view.
+        let full = filter_buffer(&engine, &buffer, None, &FilterRun::default()).unwrap();
+        assert_eq!(full.visible, filtered);
+        assert_eq!(full.context, Some(context));
+    }
+
+    /// Puts a fake `adb` first on `PATH` for the whole test run. It lists
+    /// `DEVICES`, and its `logcat` prints the serial it was given with `-s`
+    /// and its own arguments, then exits.
+    #[cfg(unix)]
+    fn install_fake_adb() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::sync::OnceLock;
+
+        static DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+        DIR.get_or_init(|| {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("adb");
+            std::fs::write(
+                &script,
+                format!(
+                    r#"#!/bin/sh
+serial=none
+if [ "$1" = "-s" ]; then serial=$2; shift 2; fi
+case "$1" in
+devices) [ "$*" = "devices -l" ] || exit 1; cat <<'END'
+{}END
+;;
+logcat)
+    for i in 1 2 3; do
+        echo "01-02 03:04:0$i.000   100   100 I $serial: line $i of $*"
+    done
+;;
+shell) echo "PID NAME";;
+wait-for-device) ;;
+*) exit 1;;
+esac
+"#,
+                    DEVICES
+                ),
+            )
+            .unwrap();
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+            let path = std::env::var_os("PATH").unwrap_or_default();
+            let mut paths = vec![dir.path().to_path_buf()];
+            paths.extend(std::env::split_paths(&path));
+            std::env::set_var("PATH", std::env::join_paths(paths).unwrap());
+            dir
         });
+    }
 
-        Ok(())
+    /// Waits for the capture into `document` to end and returns its lines.
+    async fn captured(document: &Document) -> Vec<String> {
+        for _ in 0..500 {
+            if !*document.is_adb_active.lock().await {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!*document.is_adb_active.lock().await);
+        let buffer = document.adb_buffer.lock().await;
+        (0..buffer.len())
+            .map(|i| buffer.get(i).unwrap().into_owned())
+            .collect()
     }
 
-    pub fn stop(&mut self) {
-        if let Some(mut child) = self.child.take() {
-            let _ = child.start_kill();
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn lists_devices_from_adb_on_path() {
+        install_fake_adb();
+
+        let devices = list_devices().await.unwrap();
+        assert_eq!(devices, parse_devices(DEVICES));
+        assert_eq!(devices[0].serial, "emulator-5554");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn streams_each_device_into_its_own_document() {
+        install_fake_adb();
+        let app = tauri::test::mock_app();
+        // Nothing listens on port 1, so the captures fall back to `adb`.
+        let client = AdbClient::new("127.0.0.1:1".to_string());
+        let no_reconnect = ReconnectPolicy {
+            max_attempts: 0,
+            ..Default::default()
+        };
+
+        let first = Document::adb("first".to_string(), CaptureBuffer::default());
+        let second = Document::adb("second".to_string(), CaptureBuffer::default());
+        let mut first_manager = AdbManager::with_client(client.clone());
+        let mut second_manager = AdbManager::with_client(client);
+        for (manager, id, document, serial) in [
+            (&mut first_manager, 1, &first, "emulator-5554"),
+            (&mut second_manager, 2, &second, "0A1B2C3D"),
+        ] {
+            manager
+                .start_streaming(
+                    app.handle().clone(),
+                    id,
+                    document,
+                    Some(serial.to_string()),
+                    LogcatOptions::default(),
+                    no_reconnect.clone(),
+                    Arc::default(),
+                )
+                .await
+                .unwrap();
+        }
+
+        for (document, serial) in [(&first, "emulator-5554"), (&second, "0A1B2C3D")] {
+            let expected: Vec<String> = (1..=3)
+                .map(|i| {
+                    format!(
+                        "01-02 03:04:0{}.000   100   100 I {}: line {} of logcat -v threadtime",
+                        i, serial, i
+                    )
+                })
+                .collect();
+            assert_eq!(captured(document).await, expected);
         }
     }
 }
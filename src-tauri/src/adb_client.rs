@@ -0,0 +1,280 @@
+use crate::adb::{self, AdbDevice};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Where the ADB server listens unless `ANDROID_ADB_SERVER_PORT` says
+/// otherwise.
+pub const DEFAULT_PORT: u16 = 5037;
+
+/// A client for the ADB server's smart-socket protocol. Each request is sent
+/// on a new connection as a 4-digit hex length and the service name, and
+/// answered with `OKAY` or `FAIL` and a length-prefixed message.
+#[derive(Debug, Clone)]
+pub struct AdbClient {
+    addr: String,
+}
+
+impl Default for AdbClient {
+    fn default() -> Self {
+        let port = std::env::var("ANDROID_ADB_SERVER_PORT")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(DEFAULT_PORT);
+        Self::new(format!("127.0.0.1:{}", port))
+    }
+}
+
+impl AdbClient {
+    pub fn new(addr: String) -> Self {
+        Self { addr }
+    }
+
+    /// Whether a server is listening. The `adb` executable is only needed
+    /// to start one when it is not.
+    pub async fn is_reachable(&self) -> bool {
+        self.connect().await.is_ok()
+    }
+
+    /// Lists the devices attached to the server (`host:devices-l`).
+    pub async fn devices(&self) -> Result<Vec<AdbDevice>, String> {
+        let mut stream = self.connect().await?;
+        request(&mut stream, "host:devices-l").await?;
+        let list = read_message(&mut stream).await?;
+        Ok(adb::parse_devices(&list))
+    }
+
+    /// Switches a new connection to the device with the given `serial`, or
+    /// the only one attached, and opens `service` on it. The returned stream
+    /// carries the service's raw output.
+    pub async fn open(&self, serial: Option<&str>, service: &str) -> Result<TcpStream, String> {
+        let mut stream = self.connect().await?;
+        let transport = match serial {
+            Some(serial) => format!("host:transport:{}", serial),
+            None => "host:transport-any".to_string(),
+        };
+        request(&mut stream, &transport).await?;
+        request(&mut stream, service).await?;
+        Ok(stream)
+    }
+
+    /// Runs `command` in the device's shell and returns what it printed.
+    pub async fn shell(&self, serial: Option<&str>, command: &str) -> Result<String, String> {
+        let mut stream = self.open(serial, &format!("shell:{}", command)).await?;
+        let mut output = Vec::new();
+        stream
+            .read_to_end(&mut output)
+            .await
+            .map_err(|e| format!("Failed to read shell output: {}", e))?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    /// Runs the command made of `args` on the device without a terminal, so
+    /// its output comes through byte for byte.
+    pub async fn exec(&self, serial: Option<&str>, args: &[String]) -> Result<TcpStream, String> {
+        let command: Vec<String> = args.iter().map(|a| shell_quote(a)).collect();
+        self.open(serial, &format!("exec:{}", command.join(" ")))
+            .await
+    }
+
+    /// Streams the output of `logcat` with the given arguments, which
+    /// include `-B` for binary entries.
+    pub async fn logcat(&self, serial: Option<&str>, args: &[String]) -> Result<TcpStream, String> {
+        let mut command = vec!["logcat".to_string()];
+        command.extend(args.iter().cloned());
+        self.exec(serial, &command).await
+    }
+
+    /// Waits until the device with the given `serial`, or any device, is
+    /// online. The server answers once to accept the request and again
+    /// when the device is there.
+    pub async fn wait_for_device(&self, serial: Option<&str>) -> Result<(), String> {
+        let service = match serial {
+            Some(serial) => format!("host-serial:{}:wait-for-any-device", serial),
+            None => "host:wait-for-any-device".to_string(),
+        };
+        let mut stream = self.connect().await?;
+        request(&mut stream, &service).await?;
+        read_status(&mut stream, &service).await
+    }
+
+    async fn connect(&self) -> Result<TcpStream, String> {
+        TcpStream::connect(&self.addr).await.map_err(|e| {
+            format!(
+                "Failed to connect to the ADB server at {}: {}",
+                self.addr, e
+            )
+        })
+    }
+}
+
+/// Sends one request and waits for the server to accept it.
+async fn request(stream: &mut TcpStream, service: &str) -> Result<(), String> {
+    if service.len() > 0xffff {
+        return Err(format!("ADB request too long: {} bytes", service.len()));
+    }
+    stream
+        .write_all(format!("{:04x}{}", service.len(), service).as_bytes())
+        .await
+        .map_err(|e| format!("Failed to send ADB request: {}", e))?;
+    read_status(stream, service).await
+}
+
+/// Reads the server's `OKAY`, or its `FAIL` and reason, for `service`.
+async fn read_status(stream: &mut TcpStream, service: &str) -> Result<(), String> {
+    let mut status = [0u8; 4];
+    stream
+        .read_exact(&mut status)
+        .await
+        .map_err(|e| format!("Failed to read ADB reply: {}", e))?;
+    match &status {
+        b"OKAY" => Ok(()),
+        b"FAIL" => {
+            let message = read_message(stream).await?;
+            Err(format!("ADB server refused {}: {}", service, message))
+        }
+        _ => Err(format!(
+            "Unexpected ADB reply to {}: {:?}",
+            service,
+            String::from_utf8_lossy(&status)
+        )),
+    }
+}
+
+/// Reads a message sent as a 4-digit hex length and that many bytes.
+async fn read_message<R: AsyncRead + Unpin>(stream: &mut R) -> Result<String, String> {
+    let mut length = [0u8; 4];
+    stream
+        .read_exact(&mut length)
+        .await
+        .map_err(|e| format!("Failed to read ADB reply: {}", e))?;
+    let length = std::str::from_utf8(&length)
+        .ok()
+        .and_then(|l| usize::from_str_radix(l, 16).ok())
+        .ok_or_else(|| format!("Invalid ADB reply length: {:?}", length))?;
+
+    let mut message = vec![0u8; length];
+    stream
+        .read_exact(&mut message)
+        .await
+        .map_err(|e| format!("Failed to read ADB reply: {}", e))?;
+    Ok(String::from_utf8_lossy(&message).into_owned())
+}
+
+/// Quotes `arg` for the device's shell when it holds anything but plain
+/// word characters.
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.,:/=@%+*".contains(&b));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// A fake ADB server that answers one connection. `replies` maps each
+    /// expected request to the reply bytes; the connection closes after the
+    /// last one.
+    async fn fake_server(replies: Vec<(&'static str, Vec<u8>)>) -> AdbClient {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            for (expected, reply) in replies {
+                let service = read_message(&mut socket).await.unwrap();
+                assert_eq!(service, expected);
+                socket.write_all(&reply).await.unwrap();
+            }
+        });
+        AdbClient::new(addr)
+    }
+
+    fn okay(payload: &[u8]) -> Vec<u8> {
+        [b"OKAY".as_slice(), payload].concat()
+    }
+
+    fn message(status: &str, text: &str) -> Vec<u8> {
+        format!("{}{:04x}{}", status, text.len(), text).into_bytes()
+    }
+
+    #[tokio::test]
+    async fn lists_devices() {
+        let list = "emulator-5554          device product:sdk_gphone64_x86_64 model:Pixel_7 device:emu64xa transport_id:1\n\
+                    0A1B2C3D               unauthorized usb:1-4 transport_id:2\n";
+        let client = fake_server(vec![("host:devices-l", message("OKAY", list))]).await;
+
+        let devices = client.devices().await.unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].serial, "emulator-5554");
+        assert_eq!(devices[0].model.as_deref(), Some("Pixel_7"));
+        assert_eq!(devices[1].state, "unauthorized");
+    }
+
+    #[tokio::test]
+    async fn runs_shell_and_exec_services() {
+        let client = fake_server(vec![
+            ("host:transport:emulator-5554", okay(b"")),
+            ("shell:getprop ro.product.model", okay(b"Pixel 7\n")),
+        ])
+        .await;
+        let output = client
+            .shell(Some("emulator-5554"), "getprop ro.product.model")
+            .await
+            .unwrap();
+        assert_eq!(output, "Pixel 7\n");
+
+        let client = fake_server(vec![
+            ("host:transport-any", okay(b"")),
+            (
+                "exec:logcat -B -b main -T '01-02 03:04:05.000' 'it'\\''s'",
+                okay(&[0, 1, 2, 255]),
+            ),
+        ])
+        .await;
+        let args = ["-B", "-b", "main", "-T", "01-02 03:04:05.000", "it's"].map(String::from);
+        let mut stream = client.logcat(None, &args).await.unwrap();
+        let mut bytes = Vec::new();
+        stream.read_to_end(&mut bytes).await.unwrap();
+        assert_eq!(bytes, [0, 1, 2, 255]);
+    }
+
+    #[tokio::test]
+    async fn waits_for_devices() {
+        let client = fake_server(vec![(
+            "host-serial:emulator-5554:wait-for-any-device",
+            [okay(b""), okay(b"")].concat(),
+        )])
+        .await;
+        client.wait_for_device(Some("emulator-5554")).await.unwrap();
+
+        let client = fake_server(vec![(
+            "host:wait-for-any-device",
+            [okay(b""), message("FAIL", "more than one device/emulator")].concat(),
+        )])
+        .await;
+        let error = client.wait_for_device(None).await.unwrap_err();
+        assert!(error.contains("more than one device"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn reports_refused_requests() {
+        let client = fake_server(vec![(
+            "host:transport:missing",
+            message("FAIL", "device 'missing' not found"),
+        )])
+        .await;
+        let error = client.shell(Some("missing"), "true").await.unwrap_err();
+        assert!(error.contains("device 'missing' not found"), "{}", error);
+
+        let unreachable = AdbClient::new("127.0.0.1:1".to_string());
+        assert!(!unreachable.is_reachable().await);
+        assert!(unreachable.devices().await.is_err());
+    }
+}
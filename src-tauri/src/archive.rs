@@ -0,0 +1,260 @@
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use zip::ZipArchive;
+
+const COPY_CHUNK_SIZE: usize = 1_048_576;
+const ZIP_MAGIC: &[u8; 4] = b"PK\x03\x04";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub size: u64,
+    pub compressed_size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveListing {
+    pub entries: Vec<ArchiveEntry>,
+    /// The entry opened when the caller does not pick one.
+    pub default_entry: Option<String>,
+}
+
+/// Returns true if `path` starts with a zip local file header.
+pub fn is_zip(path: &Path) -> Result<bool, String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let mut header = [0u8; 4];
+    match file.read_exact(&mut header) {
+        Ok(()) => Ok(&header == ZIP_MAGIC),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(format!("Failed to read file: {}", e)),
+    }
+}
+
+fn open_archive(path: &Path) -> Result<ZipArchive<BufReader<File>>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    ZipArchive::new(BufReader::new(file)).map_err(|e| format!("Failed to read zip archive: {}", e))
+}
+
+/// Lists the file entries of a zip archive in archive order.
+pub fn list_entries(path: &Path) -> Result<ArchiveListing, String> {
+    let mut archive = open_archive(path)?;
+    let mut entries = Vec::with_capacity(archive.len());
+
+    for i in 0..archive.len() {
+        let entry = archive
+            .by_index_raw(i)
+            .map_err(|e| format!("Failed to read zip entry: {}", e))?;
+        if entry.is_dir() {
+            continue;
+        }
+        entries.push(ArchiveEntry {
+            name: entry.name().to_string(),
+            size: entry.size(),
+            compressed_size: entry.compressed_size(),
+        });
+    }
+
+    let default_entry = default_entry(&entries).map(str::to_string);
+    Ok(ArchiveListing {
+        entries,
+        default_entry,
+    })
+}
+
+/// Picks the main log of an archive: the top-level `bugreport-*.txt` that
+/// `adb bugreport` writes, falling back to the largest `.txt` entry.
+pub fn default_entry(entries: &[ArchiveEntry]) -> Option<&str> {
+    let is_txt = |e: &&ArchiveEntry| e.name.ends_with(".txt");
+    let is_bugreport = |e: &&ArchiveEntry| !e.name.contains('/') && e.name.starts_with("bugreport");
+
+    entries
+        .iter()
+        .filter(is_txt)
+        .filter(is_bugreport)
+        .max_by_key(|e| e.size)
+        .or_else(|| entries.iter().filter(is_txt).max_by_key(|e| e.size))
+        .map(|e| e.name.as_str())
+}
+
+/// Extracts `entry` (or the default entry) from the zip archive at `path`
+/// into an anonymous temporary file. Returns the file and the entry name.
+pub async fn extract_to_temp<F>(
+    path: &Path,
+    entry: Option<&str>,
+    progress_callback: &mut F,
+) -> Result<(File, String), String>
+where
+    F: FnMut(f64),
+{
+    let name = match entry {
+        Some(name) => name.to_string(),
+        None => list_entries(path)?
+            .default_entry
+            .ok_or("Archive does not contain a log file")?,
+    };
+
+    let mut archive = open_archive(path)?;
+    let mut reader = archive
+        .by_name(&name)
+        .map_err(|e| format!("Failed to open '{}' in archive: {}", name, e))?;
+    let total_size = reader.size();
+
+    let mut output =
+        tempfile::tempfile().map_err(|e| format!("Failed to create temporary file: {}", e))?;
+    let mut buf = vec![0u8; COPY_CHUNK_SIZE];
+    let mut written = 0u64;
+
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .map_err(|e| format!("Failed to extract '{}': {}", name, e))?;
+        if n == 0 {
+            break;
+        }
+        output
+            .write_all(&buf[..n])
+            .map_err(|e| format!("Failed to write extracted data: {}", e))?;
+        written += n as u64;
+
+        if total_size > 0 {
+            progress_callback(written as f64 / total_size as f64);
+        }
+        tokio::task::yield_now().await;
+    }
+
+    output
+        .flush()
+        .map_err(|e| format!("Failed to write extracted data: {}", e))?;
+    progress_callback(1.0);
+
+    Ok((output, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Seek;
+    use tempfile::NamedTempFile;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    /// Writes a zip archive with the given entries, deflated, into a
+    /// temporary file. Names ending in `/` become directories.
+    fn write_zip(entries: &[(&str, &[u8])]) -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        let mut writer = ZipWriter::new(file.reopen().unwrap());
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, contents) in entries {
+            if name.ends_with('/') {
+                writer.add_directory(*name, options).unwrap();
+            } else {
+                writer.start_file(*name, options).unwrap();
+                writer.write_all(contents).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+        file
+    }
+
+    fn entry(name: &str, size: u64) -> ArchiveEntry {
+        ArchiveEntry {
+            name: name.to_string(),
+            size,
+            compressed_size: size,
+        }
+    }
+
+    #[test]
+    fn detects_zip_files() {
+        let zip = write_zip(&[("log.txt", b"hello\n")]);
+        assert!(is_zip(zip.path()).unwrap());
+
+        let mut text = NamedTempFile::new().unwrap();
+        text.write_all(b"01-02 03:04:05.678 I tag: not a zip\n")
+            .unwrap();
+        assert!(!is_zip(text.path()).unwrap());
+
+        let short = NamedTempFile::new().unwrap();
+        short.as_file().write_all(b"PK").unwrap();
+        assert!(!is_zip(short.path()).unwrap());
+    }
+
+    #[test]
+    fn lists_file_entries_in_archive_order() {
+        let zip = write_zip(&[
+            ("FS/", b""),
+            ("FS/data/anr/traces.txt", &[b'a'; 4000]),
+            ("bugreport-sdk-2024-01-02.txt", &[b'b'; 1000]),
+            ("version.txt", b"2.0"),
+        ]);
+
+        let listing = list_entries(zip.path()).unwrap();
+        let names: Vec<&str> = listing.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "FS/data/anr/traces.txt",
+                "bugreport-sdk-2024-01-02.txt",
+                "version.txt"
+            ]
+        );
+        assert_eq!(listing.entries[0].size, 4000);
+        assert!(listing.entries[0].compressed_size < 4000);
+        assert_eq!(
+            listing.default_entry.as_deref(),
+            Some("bugreport-sdk-2024-01-02.txt")
+        );
+    }
+
+    #[test]
+    fn picks_the_bugreport_or_the_largest_text_file() {
+        let entries = [
+            entry("FS/data/anr/traces.txt", 4000),
+            entry("bugreport-small.txt", 10),
+            entry("bugreport-large.txt", 1000),
+            entry("FS/bugreport-nested.txt", 5000),
+        ];
+        assert_eq!(default_entry(&entries), Some("bugreport-large.txt"));
+
+        let entries = [
+            entry("main_entry.bin", 9000),
+            entry("logs/system.txt", 300),
+            entry("logs/radio.txt", 700),
+        ];
+        assert_eq!(default_entry(&entries), Some("logs/radio.txt"));
+
+        assert_eq!(default_entry(&[entry("image.png", 100)]), None);
+        assert_eq!(default_entry(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn extracts_entries_byte_for_byte() {
+        let log: Vec<u8> = (0..200_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let zip = write_zip(&[("notes.txt", b"short"), ("bugreport-a.txt", &log)]);
+
+        let mut progress = Vec::new();
+        let (mut file, name) = extract_to_temp(zip.path(), None, &mut |p| progress.push(p))
+            .await
+            .unwrap();
+        assert_eq!(name, "bugreport-a.txt");
+        let mut extracted = Vec::new();
+        file.rewind().unwrap();
+        file.read_to_end(&mut extracted).unwrap();
+        assert_eq!(extracted, log);
+        assert_eq!(progress.last(), Some(&1.0));
+        assert!(progress.windows(2).all(|w| w[0] <= w[1]));
+
+        let (mut file, _) = extract_to_temp(zip.path(), Some("notes.txt"), &mut |_| {})
+            .await
+            .unwrap();
+        let mut extracted = String::new();
+        file.rewind().unwrap();
+        file.read_to_string(&mut extracted).unwrap();
+        assert_eq!(extracted, "short");
+
+        let missing = extract_to_temp(zip.path(), Some("missing.txt"), &mut |_| {}).await;
+        assert!(missing.unwrap_err().contains("missing.txt"));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::indexer::Indexer;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use tempfile::NamedTempFile;
+
+/// Lines kept in memory by default before older ones are spilled to disk.
+pub const DEFAULT_MEMORY_LINES: usize = 200_000;
+
+/// Every line of a live capture. The most recent `memory_lines` are kept in
+/// memory; older ones are appended to a temporary file on disk and read back
+/// through an `Indexer`, so the whole session stays addressable by index.
+pub struct CaptureBuffer {
+    memory_lines: usize,
+    spill_dir: PathBuf,
+    /// Declared before `spill_file` so the mapping is dropped before the
+    /// file is deleted.
+    spilled: Option<Indexer>,
+    spill_file: Option<NamedTempFile>,
+    recent: VecDeque<String>,
+}
+
+impl Default for CaptureBuffer {
+    fn default() -> Self {
+        Self::new(usize::MAX, std::env::temp_dir())
+    }
+}
+
+impl CaptureBuffer {
+    /// A buffer holding at most `memory_lines` lines in memory and spilling
+    /// the rest to a file created in `spill_dir`.
+    pub fn new(memory_lines: usize, spill_dir: PathBuf) -> Self {
+        Self {
+            memory_lines: memory_lines.max(1),
+            spill_dir,
+            spilled: None,
+            spill_file: None,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.spilled_len() + self.recent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of lines that live on disk.
+    pub fn spilled_len(&self) -> usize {
+        self.spilled.as_ref().map_or(0, |s| s.line_count())
+    }
+
+    pub fn get(&self, index: usize) -> Option<Cow<'_, str>> {
+        let spilled = self.spilled_len();
+        if index < spilled {
+            return self.spilled.as_ref()?.get_line(index).map(Cow::Owned);
+        }
+        self.recent
+            .get(index - spilled)
+            .map(|line| Cow::Borrowed(line.as_str()))
+    }
+
+    /// Moves `lines` to the end of the buffer and spills the oldest lines if
+    /// that takes it over its memory cap. If spilling fails the lines stay in
+    /// memory, so nothing is lost.
+    pub fn push(&mut self, lines: &mut Vec<String>) -> Result<(), String> {
+        self.recent.extend(lines.drain(..));
+        if self.recent.len() <= self.memory_lines {
+            return Ok(());
+        }
+        let overflow = self.recent.len() - self.memory_lines;
+        self.spill(overflow)
+    }
+
+    fn spill(&mut self, count: usize) -> Result<(), String> {
+        if self.spill_file.is_none() {
+            let file = tempfile::Builder::new()
+                .prefix("adb-capture-")
+                .suffix(".log")
+                .tempfile_in(&self.spill_dir)
+                .map_err(|e| format!("Failed to create spill file: {}", e))?;
+            self.spilled = Some(Indexer::new(file.path().to_path_buf())?);
+            self.spill_file = Some(file);
+        }
+        let (Some(file), Some(spilled)) = (self.spill_file.as_mut(), self.spilled.as_mut()) else {
+            return Ok(());
+        };
+
+        let file = file.as_file_mut();
+        let old_len = file
+            .seek(SeekFrom::End(0))
+            .map_err(|e| format!("Failed to write spill file: {}", e))?;
+        let written = write_lines(&mut *file, self.recent.range(..count))
+            .map_err(|e| format!("Failed to write spill file: {}", e))
+            .and_then(|_| spilled.refresh());
+        if let Err(e) = written {
+            // Drop any partial write so the file keeps matching its index.
+            let _ = file.set_len(old_len);
+            return Err(e);
+        }
+
+        self.recent.drain(..count);
+        Ok(())
+    }
+}
+
+fn write_lines<'a, W, I>(out: W, lines: I) -> std::io::Result<()>
+where
+    W: Write,
+    I: Iterator<Item = &'a String>,
+{
+    let mut out = BufWriter::new(out);
+    for line in lines {
+        out.write_all(line.as_bytes())?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(range: std::ops::Range<usize>) -> Vec<String> {
+        range.map(|i| format!("line {}", i)).collect()
+    }
+
+    fn contents(buffer: &CaptureBuffer) -> Vec<String> {
+        (0..buffer.len())
+            .map(|i| buffer.get(i).unwrap().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_every_line_addressable_across_spills() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut buffer = CaptureBuffer::new(4, dir.path().to_path_buf());
+
+        buffer.push(&mut numbered(0..3)).unwrap();
+        assert_eq!(buffer.spilled_len(), 0);
+        assert_eq!(contents(&buffer), numbered(0..3));
+
+        // The first spill starts from an empty file.
+        let mut lines = numbered(3..6);
+        buffer.push(&mut lines).unwrap();
+        assert!(lines.is_empty());
+        assert_eq!(buffer.len(), 6);
+        assert_eq!(buffer.spilled_len(), 2);
+        assert_eq!(buffer.get(1).as_deref(), Some("line 1"));
+        assert_eq!(buffer.get(2).as_deref(), Some("line 2"));
+        assert_eq!(contents(&buffer), numbered(0..6));
+
+        for end in [7, 20, 21, 50] {
+            let start = buffer.len();
+            buffer.push(&mut numbered(start..end)).unwrap();
+            assert_eq!(buffer.len(), end);
+            assert_eq!(buffer.spilled_len(), end - 4);
+            assert_eq!(contents(&buffer), numbered(0..end));
+        }
+        assert_eq!(buffer.get(50), None);
+    }
+
+    #[test]
+    fn keeps_lines_in_memory_when_spilling_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut buffer = CaptureBuffer::new(2, dir.path().join("missing"));
+
+        assert!(buffer.push(&mut numbered(0..5)).is_err());
+        assert_eq!(buffer.len(), 5);
+        assert_eq!(buffer.spilled_len(), 0);
+        assert_eq!(contents(&buffer), numbered(0..5));
+    }
+}
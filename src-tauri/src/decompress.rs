@@ -0,0 +1,244 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+const COPY_CHUNK_SIZE: usize = 1_048_576;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    /// Identifies the compression format from the first bytes of a file.
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::Gzip)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::Zstd)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::Xz)
+        } else if header.starts_with(b"BZh") {
+            Some(Self::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    /// Reads the magic bytes of `path` and returns its compression format,
+    /// or `None` for plain files.
+    pub fn sniff(path: &Path) -> Result<Option<Self>, String> {
+        let mut file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+        let mut header = [0u8; 6];
+        let mut len = 0;
+        while len < header.len() {
+            match file.read(&mut header[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) => return Err(format!("Failed to read file: {}", e)),
+            }
+        }
+        Ok(Self::detect(&header[..len]))
+    }
+
+    /// Wraps `reader` in a decoder that also handles concatenated streams,
+    /// which is how rotated logs are often appended to an archive.
+    fn decoder<'a, R: Read + Send + 'a>(
+        self,
+        reader: R,
+    ) -> Result<Box<dyn Read + Send + 'a>, String> {
+        Ok(match self {
+            Self::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            Self::Zstd => Box::new(
+                zstd::stream::read::Decoder::new(reader)
+                    .map_err(|e| format!("Failed to start zstd decoder: {}", e))?,
+            ),
+            Self::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)),
+            Self::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        })
+    }
+}
+
+/// Counts the compressed bytes consumed so progress can be reported against
+/// the size of the file on disk.
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Decompresses `path` into an anonymous temporary file and returns it. The
+/// file is removed by the OS once the returned handle and any maps of it
+/// are dropped.
+pub async fn decompress_to_temp<F>(
+    path: &Path,
+    compression: Compression,
+    progress_callback: &mut F,
+) -> Result<File, String>
+where
+    F: FnMut(f64),
+{
+    let source = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let total_size = source
+        .metadata()
+        .map_err(|e| format!("Failed to read file metadata: {}", e))?
+        .len();
+
+    let consumed = Arc::new(AtomicU64::new(0));
+    let counting = CountingReader {
+        inner: BufReader::new(source),
+        count: Arc::clone(&consumed),
+    };
+    let mut decoder = compression.decoder(counting)?;
+
+    let mut output =
+        tempfile::tempfile().map_err(|e| format!("Failed to create temporary file: {}", e))?;
+    let mut buf = vec![0u8; COPY_CHUNK_SIZE];
+
+    loop {
+        let n = decoder
+            .read(&mut buf)
+            .map_err(|e| format!("Failed to decompress {:?} file: {}", compression, e))?;
+        if n == 0 {
+            break;
+        }
+        output
+            .write_all(&buf[..n])
+            .map_err(|e| format!("Failed to write decompressed data: {}", e))?;
+
+        if total_size > 0 {
+            progress_callback(consumed.load(Ordering::Relaxed) as f64 / total_size as f64);
+        }
+        tokio::task::yield_now().await;
+    }
+
+    output
+        .flush()
+        .map_err(|e| format!("Failed to write decompressed data: {}", e))?;
+    progress_callback(1.0);
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Seek;
+    use tempfile::NamedTempFile;
+
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        match compression {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Zstd => zstd::stream::encode_all(data, 0).unwrap(),
+            Compression::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Bzip2 => {
+                let mut encoder =
+                    bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+        }
+    }
+
+    fn write_temp(data: &[u8]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(data).unwrap();
+        file
+    }
+
+    fn sample() -> Vec<u8> {
+        (0..5000)
+            .map(|i| {
+                format!(
+                    "01-02 03:04:05.{:03}   100   200 I Tag: line {}\n",
+                    i % 1000,
+                    i
+                )
+            })
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[test]
+    fn detects_magic_bytes() {
+        let data = sample();
+        for compression in [
+            Compression::Gzip,
+            Compression::Zstd,
+            Compression::Xz,
+            Compression::Bzip2,
+        ] {
+            let file = write_temp(&compress(compression, &data));
+            assert_eq!(Compression::sniff(file.path()), Ok(Some(compression)));
+        }
+
+        for plain in [&data[..], b"", b"BZ", b"\x1f", b"\xfd7zXZ"] {
+            let file = write_temp(plain);
+            assert_eq!(Compression::sniff(file.path()), Ok(None));
+        }
+        assert!(Compression::sniff(Path::new("/nonexistent/log.gz")).is_err());
+    }
+
+    #[tokio::test]
+    async fn round_trips_every_format() {
+        let data = sample();
+        for compression in [
+            Compression::Gzip,
+            Compression::Zstd,
+            Compression::Xz,
+            Compression::Bzip2,
+        ] {
+            // Two streams back to back, as when rotated logs are appended.
+            let mut compressed = compress(compression, &data[..1000]);
+            compressed.extend(compress(compression, &data[1000..]));
+            let file = write_temp(&compressed);
+
+            let mut reported = Vec::new();
+            let mut output =
+                decompress_to_temp(file.path(), compression, &mut |p| reported.push(p))
+                    .await
+                    .unwrap();
+
+            let mut decompressed = Vec::new();
+            output.rewind().unwrap();
+            output.read_to_end(&mut decompressed).unwrap();
+            assert!(decompressed == data, "{:?} did not round-trip", compression);
+            assert_eq!(reported.last(), Some(&1.0));
+            assert!(reported.windows(2).all(|w| w[0] <= w[1]));
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_corrupt_input() {
+        let mut compressed = compress(Compression::Gzip, &sample());
+        let len = compressed.len();
+        compressed[len / 2..].fill(0);
+        let file = write_temp(&compressed);
+
+        let result = decompress_to_temp(file.path(), Compression::Gzip, &mut |_| {}).await;
+        assert!(result
+            .unwrap_err()
+            .starts_with("Failed to decompress Gzip file"));
+    }
+}
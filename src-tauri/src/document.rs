@@ -0,0 +1,325 @@
+use crate::adb::AdbManager;
+use crate::capture::CaptureBuffer;
+use crate::filter::{CancelToken, FilterEngine};
+use crate::follow::FollowManager;
+use crate::indexer::Indexer;
+use crate::merge::MergedView;
+use crate::processes::ProcessMap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+pub type DocumentId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentKind {
+    File,
+    Merged,
+    Adb,
+}
+
+/// One open log: a file, several merged files, or an ADB capture. Each
+/// document keeps its own index, filter results and live sources, so
+/// several can be open side by side.
+pub struct Document {
+    pub kind: DocumentKind,
+    pub title: String,
+    pub indexer: Arc<Mutex<Option<Indexer>>>,
+    /// For merged documents `filtered_offsets` holds positions in the
+    /// merged view rather than byte offsets.
+    pub merged_view: Arc<Mutex<Option<MergedView>>>,
+    pub filtered_offsets: Arc<Mutex<Option<Vec<usize>>>>,
+    /// Parallel to `filtered_offsets` when the filters show context lines:
+    /// whether each visible line is context rather than a match.
+    pub filtered_context: Arc<Mutex<Option<Vec<bool>>>>,
+    pub active_filter: Arc<Mutex<Option<FilterEngine>>>,
+    /// Cancels the filter run in progress, if any.
+    pub filter_run: Arc<Mutex<Option<CancelToken>>>,
+    pub follow_manager: Arc<Mutex<FollowManager>>,
+    pub adb_manager: Arc<Mutex<AdbManager>>,
+    pub adb_buffer: Arc<Mutex<CaptureBuffer>>,
+    pub is_adb_active: Arc<Mutex<bool>>,
+    /// Which process held each pid, kept up to date while a capture
+    /// streams or a followed file grows. File and merged documents have
+    /// none until a filter needs one, as it is built from every line.
+    pub processes: Arc<Mutex<Option<Arc<ProcessMap>>>>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct DocumentInfo {
+    pub document_id: DocumentId,
+    pub kind: DocumentKind,
+    pub title: String,
+}
+
+impl Document {
+    fn new(
+        kind: DocumentKind,
+        title: String,
+        indexer: Option<Indexer>,
+        merged_view: Option<MergedView>,
+    ) -> Self {
+        Self {
+            kind,
+            title,
+            indexer: Arc::new(Mutex::new(indexer)),
+            merged_view: Arc::new(Mutex::new(merged_view)),
+            filtered_offsets: Arc::new(Mutex::new(None)),
+            filtered_context: Arc::new(Mutex::new(None)),
+            active_filter: Arc::new(Mutex::new(None)),
+            filter_run: Arc::new(Mutex::new(None)),
+            follow_manager: Arc::new(Mutex::new(FollowManager::new())),
+            adb_manager: Arc::new(Mutex::new(AdbManager::new())),
+            adb_buffer: Arc::new(Mutex::new(CaptureBuffer::default())),
+            is_adb_active: Arc::new(Mutex::new(false)),
+            processes: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn file(title: String, indexer: Indexer) -> Self {
+        Self::new(DocumentKind::File, title, Some(indexer), None)
+    }
+
+    pub fn merged(title: String, view: MergedView) -> Self {
+        Self::new(DocumentKind::Merged, title, None, Some(view))
+    }
+
+    pub fn adb(title: String, buffer: CaptureBuffer) -> Self {
+        Self {
+            adb_buffer: Arc::new(Mutex::new(buffer)),
+            processes: Arc::new(Mutex::new(Some(Arc::default()))),
+            ..Self::new(DocumentKind::Adb, title, None, None)
+        }
+    }
+
+    /// Stops follow mode, ADB streaming and any filter run and releases the
+    /// mapped file, offsets and buffered lines, even if a background task
+    /// still holds a reference to one of the fields.
+    pub async fn close(&self) {
+        if let Some(run) = self.filter_run.lock().await.take() {
+            run.cancel();
+        }
+        self.follow_manager.lock().await.stop();
+        self.adb_manager.lock().await.stop();
+        *self.is_adb_active.lock().await = false;
+
+        *self.indexer.lock().await = None;
+        *self.merged_view.lock().await = None;
+        *self.filtered_offsets.lock().await = None;
+        *self.filtered_context.lock().await = None;
+        *self.active_filter.lock().await = None;
+        *self.adb_buffer.lock().await = CaptureBuffer::default();
+        *self.processes.lock().await = None;
+    }
+}
+
+/// The set of open documents, keyed by the id handed to the frontend.
+#[derive(Default)]
+pub struct Documents {
+    next_id: DocumentId,
+    open: BTreeMap<DocumentId, Arc<Document>>,
+}
+
+impl Documents {
+    /// Hands out a fresh id. Reserving it before the document exists lets
+    /// progress events be tagged while a file is still being indexed.
+    pub fn reserve_id(&mut self) -> DocumentId {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub fn insert(&mut self, id: DocumentId, document: Document) -> Arc<Document> {
+        let document = Arc::new(document);
+        self.open.insert(id, Arc::clone(&document));
+        document
+    }
+
+    pub fn get(&self, id: DocumentId) -> Result<Arc<Document>, String> {
+        self.open
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| format!("No open document with id {}", id))
+    }
+
+    pub fn remove(&mut self, id: DocumentId) -> Option<Arc<Document>> {
+        self.open.remove(&id)
+    }
+
+    pub fn list(&self) -> Vec<DocumentInfo> {
+        self.open
+            .iter()
+            .map(|(&document_id, doc)| DocumentInfo {
+                document_id,
+                kind: doc.kind,
+                title: doc.title.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb::{LogcatOptions, ReconnectPolicy};
+    use crate::adb_client::AdbClient;
+    use std::io::Write;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    async fn file_document(title: &str) -> (Document, tempfile::NamedTempFile) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "01-02 03:04:05.000   100   100 I Test: hello").unwrap();
+        let mut indexer = Indexer::new(file.path().to_path_buf()).unwrap();
+        indexer.index(|_| {}).await;
+        (Document::file(title.to_string(), indexer), file)
+    }
+
+    /// A fake ADB server that accepts every request. Logcat prints one line
+    /// and then stays open until the client hangs up; shell commands print
+    /// nothing.
+    async fn endless_logcat_server() -> AdbClient {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut len = [0; 4];
+                    while socket.read_exact(&mut len).await.is_ok() {
+                        let len = usize::from_str_radix(std::str::from_utf8(&len).unwrap(), 16);
+                        let mut service = vec![0; len.unwrap()];
+                        socket.read_exact(&mut service).await.unwrap();
+                        socket.write_all(b"OKAY").await.unwrap();
+                        if service.starts_with(b"exec:logcat") {
+                            socket
+                                .write_all(b"01-02 03:04:05.000   100   100 I Test: hello\n")
+                                .await
+                                .unwrap();
+                        } else if service.starts_with(b"shell:") {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        AdbClient::new(addr)
+    }
+
+    /// Waits for background tasks to let go of `field`.
+    async fn released<T>(field: &Arc<T>) -> bool {
+        for _ in 0..500 {
+            if Arc::strong_count(field) == 1 {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn inserts_gets_and_removes_documents() {
+        let mut documents = Documents::default();
+        let (first, _first_file) = file_document("first").await;
+        let (second, _second_file) = file_document("second").await;
+
+        let first_id = documents.reserve_id();
+        let second_id = documents.reserve_id();
+        assert_ne!(first_id, second_id);
+        let first = documents.insert(first_id, first);
+        documents.insert(second_id, second);
+
+        assert!(Arc::ptr_eq(&documents.get(first_id).unwrap(), &first));
+        assert_eq!(documents.get(second_id).unwrap().title, "second");
+        let titles: Vec<String> = documents.list().into_iter().map(|d| d.title).collect();
+        assert_eq!(titles, ["first", "second"]);
+
+        let removed = documents.remove(first_id).unwrap();
+        removed.close().await;
+        assert!(removed.indexer.lock().await.is_none());
+        assert_eq!(
+            documents.get(first_id).err().unwrap(),
+            format!("No open document with id {}", first_id)
+        );
+        assert_eq!(documents.list().len(), 1);
+
+        // Ids are never handed out twice, even after a close.
+        assert!(documents.reserve_id() > second_id);
+    }
+
+    #[tokio::test]
+    async fn removing_an_unknown_id_leaves_the_rest_open() {
+        let mut documents = Documents::default();
+        let (document, _file) = file_document("open").await;
+        let id = documents.reserve_id();
+        documents.insert(id, document);
+
+        assert!(documents.remove(id + 1).is_none());
+        assert!(documents.get(id + 1).is_err());
+        assert!(documents.get(id).is_ok());
+        assert!(documents.get(id).unwrap().indexer.lock().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn closing_stops_follow_and_streaming() {
+        let app = tauri::test::mock_app();
+        let mut documents = Documents::default();
+
+        let (file, _file) = file_document("followed").await;
+        let file_id = documents.reserve_id();
+        let file = documents.insert(file_id, file);
+        file.follow_manager.lock().await.start(
+            app.handle().clone(),
+            file_id,
+            Arc::clone(&file.indexer),
+            Arc::clone(&file.filtered_offsets),
+            Arc::clone(&file.filtered_context),
+            Arc::clone(&file.active_filter),
+            Arc::clone(&file.processes),
+            Arc::clone(&file.filter_run),
+        );
+
+        let capture_id = documents.reserve_id();
+        let capture = documents.insert(
+            capture_id,
+            Document::adb("capture".to_string(), CaptureBuffer::default()),
+        );
+        *capture.adb_manager.lock().await = AdbManager::with_client(endless_logcat_server().await);
+        capture
+            .adb_manager
+            .lock()
+            .await
+            .start_streaming(
+                app.handle().clone(),
+                capture_id,
+                &capture,
+                None,
+                LogcatOptions::default(),
+                ReconnectPolicy::default(),
+                Arc::default(),
+            )
+            .await
+            .unwrap();
+        for _ in 0..500 {
+            if !capture.adb_buffer.lock().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(capture.adb_buffer.lock().await.len(), 1);
+        assert!(*capture.is_adb_active.lock().await);
+        assert!(file.follow_manager.lock().await.is_running());
+        assert!(Arc::strong_count(&capture.adb_buffer) > 1);
+
+        documents.remove(file_id).unwrap().close().await;
+        assert!(!file.follow_manager.lock().await.is_running());
+        assert!(released(&file.indexer).await);
+        assert!(Arc::strong_count(&capture.adb_buffer) > 1);
+
+        documents.remove(capture_id).unwrap().close().await;
+        assert!(released(&capture.adb_buffer).await);
+        assert!(!*capture.is_adb_active.lock().await);
+        assert!(documents.list().is_empty());
+    }
+}
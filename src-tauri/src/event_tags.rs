@@ -0,0 +1,368 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Where Android keeps the tag definitions for the events buffer.
+pub const DEVICE_PATH: &str = "/system/etc/event-log-tags";
+
+/// Type of an event field as declared in `event-log-tags`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventFieldType {
+    Int,
+    Long,
+    String,
+    List,
+    Float,
+}
+
+impl EventFieldType {
+    fn from_code(code: &str) -> Option<Self> {
+        match code.trim() {
+            "1" => Some(Self::Int),
+            "2" => Some(Self::Long),
+            "3" => Some(Self::String),
+            "4" => Some(Self::List),
+            "5" => Some(Self::Float),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EventField {
+    pub name: String,
+    pub field_type: Option<EventFieldType>,
+}
+
+/// One line of `event-log-tags`: `number name (field|type[|unit]),...`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EventTag {
+    pub number: u32,
+    pub name: String,
+    pub fields: Vec<EventField>,
+}
+
+impl EventTag {
+    /// Position of the field called `name`, compared as `field_key` does.
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        let key = field_key(name);
+        self.fields.iter().position(|f| field_key(&f.name) == key)
+    }
+}
+
+/// The event tags of a device, by number and by name.
+#[derive(Debug, Default)]
+pub struct EventTags {
+    tags: HashMap<u32, EventTag>,
+    numbers: HashMap<String, u32>,
+}
+
+impl EventTags {
+    /// Parses an `event-log-tags` file. Comments, `option` lines and lines
+    /// that are not tag definitions are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut tags = Self::default();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let mut parts = line.splitn(3, char::is_whitespace);
+            let (Some(number), Some(name)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let Ok(number) = number.parse() else {
+                continue;
+            };
+            let fields = parts.next().map(parse_fields).unwrap_or_default();
+            tags.insert(EventTag {
+                number,
+                name: name.to_string(),
+                fields,
+            });
+        }
+        tags
+    }
+
+    pub fn insert(&mut self, tag: EventTag) {
+        self.numbers.insert(tag.name.clone(), tag.number);
+        self.tags.insert(tag.number, tag);
+    }
+
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    pub fn get(&self, number: u32) -> Option<&EventTag> {
+        self.tags.get(&number)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&EventTag> {
+        self.numbers.get(name).and_then(|n| self.tags.get(n))
+    }
+
+    /// The value of the field called `field` in an event line with the given
+    /// tag and message. The message may be named, as `render` writes it, or
+    /// positional, as text logcat prints the events buffer.
+    pub fn field_value<'a>(&self, tag: &str, message: &'a str, field: &str) -> Option<&'a str> {
+        let tag = self.by_name(tag)?;
+        let index = tag.field_index(field)?;
+        event_values(tag, message)?.get(index).copied()
+    }
+
+    /// Renders a decoded event as `[Name=value, ...]` when its tag and
+    /// fields are known, and as logcat does otherwise. Returns the tag name,
+    /// or the number if it is unknown, and the message.
+    pub fn render(&self, number: u32, value: &EventValue) -> (String, String) {
+        let Some(tag) = self.get(number) else {
+            return (number.to_string(), value.to_string());
+        };
+        let values = match value {
+            EventValue::List(values) if values.len() == tag.fields.len() => values.as_slice(),
+            _ if tag.fields.len() == 1 => std::slice::from_ref(value),
+            _ => return (tag.name.clone(), value.to_string()),
+        };
+        let fields: Vec<String> = tag
+            .fields
+            .iter()
+            .zip(values)
+            .map(|(field, value)| format!("{}={}", field.name, value))
+            .collect();
+        (tag.name.clone(), format!("[{}]", fields.join(", ")))
+    }
+}
+
+/// A value in the events buffer's binary encoding.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventValue {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    String(String),
+    List(Vec<EventValue>),
+}
+
+impl std::fmt::Display for EventValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Int(v) => write!(f, "{}", v),
+            Self::Long(v) => write!(f, "{}", v),
+            Self::Float(v) => write!(f, "{}", v),
+            Self::String(v) => f.write_str(v),
+            Self::List(values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+        }
+    }
+}
+
+/// Decodes an events-buffer payload: the tag number and one typed value.
+pub fn decode_event(payload: &[u8]) -> Option<(u32, EventValue)> {
+    let number = u32::from_le_bytes(payload.get(..4)?.try_into().ok()?);
+    let mut rest = &payload[4..];
+    let value = decode_value(&mut rest, 0)?;
+    Some((number, value))
+}
+
+/// Lists nest at most this deep in valid events.
+const MAX_DEPTH: usize = 8;
+
+fn decode_value(bytes: &mut &[u8], depth: usize) -> Option<EventValue> {
+    let (&kind, rest) = bytes.split_first()?;
+    *bytes = rest;
+    let value = match kind {
+        0 => EventValue::Int(i32::from_le_bytes(take(bytes, 4)?.try_into().ok()?)),
+        1 => EventValue::Long(i64::from_le_bytes(take(bytes, 8)?.try_into().ok()?)),
+        2 => {
+            let len = u32::from_le_bytes(take(bytes, 4)?.try_into().ok()?) as usize;
+            EventValue::String(String::from_utf8_lossy(take(bytes, len)?).into_owned())
+        }
+        3 if depth < MAX_DEPTH => {
+            let count = *take(bytes, 1)?.first()?;
+            let values = (0..count)
+                .map(|_| decode_value(bytes, depth + 1))
+                .collect::<Option<Vec<_>>>()?;
+            EventValue::List(values)
+        }
+        4 => EventValue::Float(f32::from_le_bytes(take(bytes, 4)?.try_into().ok()?)),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if bytes.len() < len {
+        return None;
+    }
+    let (head, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Some(head)
+}
+
+/// Field names as filters and queries refer to them: lowercase, with runs
+/// of anything but letters and digits turned into `_`, so `Process Name`
+/// is `process_name`.
+pub fn field_key(name: &str) -> String {
+    let mut key = String::with_capacity(name.len());
+    for c in name.trim().chars() {
+        if c.is_ascii_alphanumeric() {
+            key.push(c.to_ascii_lowercase());
+        } else if !key.ends_with('_') {
+            key.push('_');
+        }
+    }
+    key
+}
+
+/// Parses `(name|type[|unit]),...`.
+fn parse_fields(s: &str) -> Vec<EventField> {
+    s.split('(')
+        .filter_map(|part| {
+            let inner = part.split(')').next()?;
+            let mut pieces = inner.split('|');
+            let name = pieces.next()?.trim();
+            (!name.is_empty()).then(|| EventField {
+                name: name.to_string(),
+                field_type: pieces.next().and_then(EventFieldType::from_code),
+            })
+        })
+        .collect()
+}
+
+/// Splits an event message into one value per field of `tag`.
+fn event_values<'a>(tag: &EventTag, message: &'a str) -> Option<Vec<&'a str>> {
+    let Some(inner) = message
+        .trim()
+        .strip_prefix('[')
+        .and_then(|m| m.strip_suffix(']'))
+    else {
+        // Text logcat prints single values without brackets.
+        return (tag.fields.len() == 1).then(|| vec![message.trim()]);
+    };
+
+    let first = tag.fields.first()?;
+    if let Some(mut rest) = inner.strip_prefix(&format!("{}=", first.name)) {
+        // Named: each value runs up to the next field's name.
+        let mut values = Vec::with_capacity(tag.fields.len());
+        for next in &tag.fields[1..] {
+            let end = rest.find(&format!(", {}=", next.name))?;
+            values.push(&rest[..end]);
+            rest = &rest[end + next.name.len() + 3..];
+        }
+        values.push(rest);
+        return Some(values);
+    }
+
+    // Positional: split at the commas outside nested lists.
+    let mut values = Vec::with_capacity(tag.fields.len());
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                values.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    values.push(&inner[start..]);
+    (values.len() == tag.fields.len()).then_some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAGS: &str = "\
+# The entries in this file map a sparse set of log tag numbers to tag names.
+option java_package com.android.server.am
+
+42 answer (to life the universe etc|3)
+30014 am_proc_start (User|1|5),(PID|1|5),(UID|1|5),(Process Name|3),(Type|3),(Component|3)
+30008 am_anr (User|1|5),(pid|1|5),(Package Name|3),(Flags|1|5),(reason|3)
+2718 e
+";
+
+    #[test]
+    fn parses_tag_definitions() {
+        let tags = EventTags::parse(TAGS);
+        assert_eq!(tags.len(), 4);
+
+        let start = tags.by_name("am_proc_start").unwrap();
+        assert_eq!(start.number, 30014);
+        assert_eq!(start.fields.len(), 6);
+        assert_eq!(start.fields[3].name, "Process Name");
+        assert_eq!(start.fields[3].field_type, Some(EventFieldType::String));
+        assert_eq!(start.field_index("process_name"), Some(3));
+        assert!(tags.get(2718).unwrap().fields.is_empty());
+    }
+
+    #[test]
+    fn decodes_and_renders_events() {
+        let tags = EventTags::parse(TAGS);
+        let mut payload = 30014u32.to_le_bytes().to_vec();
+        payload.extend([3, 6]);
+        for int in [0i32, 4321, 10123] {
+            payload.push(0);
+            payload.extend(int.to_le_bytes());
+        }
+        for string in ["com.example, app", "activity", "com.example/.Main"] {
+            payload.push(2);
+            payload.extend((string.len() as u32).to_le_bytes());
+            payload.extend(string.as_bytes());
+        }
+
+        let (number, value) = decode_event(&payload).unwrap();
+        assert_eq!(number, 30014);
+        let (tag, message) = tags.render(number, &value);
+        assert_eq!(tag, "am_proc_start");
+        assert_eq!(
+            message,
+            "[User=0, PID=4321, UID=10123, Process Name=com.example, app, Type=activity, \
+             Component=com.example/.Main]"
+        );
+        assert_eq!(
+            tags.field_value(&tag, &message, "process_name"),
+            Some("com.example, app")
+        );
+        assert_eq!(tags.field_value(&tag, &message, "pid"), Some("4321"));
+
+        let (tag, message) = EventTags::default().render(number, &value);
+        assert_eq!(tag, "30014");
+        assert_eq!(
+            message,
+            "[0,4321,10123,com.example, app,activity,com.example/.Main]"
+        );
+        assert!(decode_event(&payload[..payload.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn reads_fields_of_text_logcat_events() {
+        let tags = EventTags::parse(TAGS);
+        let message = "[0,1234,com.example,952680005,Input dispatching timed out]";
+        assert_eq!(
+            tags.field_value("am_anr", message, "Package Name"),
+            Some("com.example")
+        );
+        assert_eq!(
+            tags.field_value("am_anr", message, "reason"),
+            Some("Input dispatching timed out")
+        );
+        assert_eq!(
+            tags.field_value("answer", "42", "to_life_the_universe_etc"),
+            Some("42")
+        );
+        assert_eq!(tags.field_value("am_anr", message, "missing"), None);
+    }
+}
@@ -1,6 +1,35 @@
+use crate::event_tags::EventTags;
+use crate::logcat::{self, LogLevel, LogLine, LogRecord};
+use crate::processes::{self, ProcessMap};
+use crate::query::{self, Expr};
 use rayon::prelude::*;
-use regex::Regex;
+use regex::{Regex, RegexSet};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A parsed logcat field a filter can be scoped to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterField {
+    Tag,
+    Pid,
+    Tid,
+    Level,
+    Message,
+    /// The package of the process that logged the line: its process name
+    /// up to any `:suffix`.
+    Package,
+    /// The name of the process that logged the line, from the process map
+    /// set with `set_processes`.
+    Process,
+    /// A named field of an events-buffer entry, as declared in the
+    /// `event-log-tags` set with `set_event_tags`.
+    Event(String),
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogFilter {
@@ -10,11 +39,139 @@ pub struct LogFilter {
     pub is_enabled: bool,
     pub color: Option<String>,
     pub text_color: Option<String>,
+    /// Field the pattern is matched against; `None` matches the whole line.
+    /// Lines that don't parse as logcat records have no fields.
+    #[serde(default)]
+    pub field: Option<FilterField>,
+    /// Lines to show before and after each line this include filter hits,
+    /// on top of the engine-wide context.
+    #[serde(default)]
+    pub context_before: usize,
+    #[serde(default)]
+    pub context_after: usize,
+}
+
+/// Lines shown around every match, like grep's `-B` and `-A`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ContextLines {
+    pub before: usize,
+    pub after: usize,
+}
+
+const DEFAULT_MATCH_COLOR: &str = "#fa5feb";
+
+/// The patterns of all filters scoped to one field, compiled together so a
+/// single pass over the field finds every filter that hits.
+struct FieldSet {
+    field: Option<FilterField>,
+    set: RegexSet,
+    /// Index into `FilterEngine::filters` of each pattern in `set`.
+    filters: Vec<usize>,
+}
+
+/// How a line fared against the engine.
+pub struct LineMatch<'a> {
+    pub visible: bool,
+    /// The first include filter that hit a visible line.
+    pub filter: Option<&'a LogFilter>,
+}
+
+/// Lines that passed a filter run, with per-filter match counts over them.
+#[derive(Default)]
+pub struct FilterOutcome {
+    /// Offsets, indices or positions of the visible lines, depending on
+    /// what the caller filtered.
+    pub visible: Vec<usize>,
+    /// When context lines were requested, whether each visible line is
+    /// context around a match rather than a match itself.
+    pub context: Option<Vec<bool>>,
+    pub match_count: usize,
+    pub counts: HashMap<String, usize>,
+}
+
+/// Per-thread state of a filter run.
+struct Scan {
+    matches: Vec<usize>,
+    /// Lines of context before and after each match, when context is on.
+    context: Vec<(u32, u32)>,
+    counts: Vec<usize>,
+    hits: Vec<bool>,
+}
+
+/// Lines scanned between checks for cancellation and progress reports.
+const CHUNK_LINES: usize = 1 << 14;
+
+/// Shared flag that stops a filter run. Clones refer to the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Cancellation and progress reporting for one filter run.
+#[derive(Default)]
+pub struct FilterRun<'a> {
+    cancel: CancelToken,
+    progress: Option<&'a (dyn Fn(f64) + Sync)>,
+    scanned: AtomicUsize,
+}
+
+impl<'a> FilterRun<'a> {
+    /// A run that stops once `cancel` is tripped and reports the fraction of
+    /// lines scanned to `progress` as it goes.
+    pub fn new(cancel: CancelToken, progress: &'a (dyn Fn(f64) + Sync)) -> Self {
+        Self {
+            cancel,
+            progress: Some(progress),
+            scanned: AtomicUsize::new(0),
+        }
+    }
+
+    fn report(&self, lines: usize, total: usize) {
+        if let Some(progress) = self.progress {
+            let scanned = self.scanned.fetch_add(lines, Ordering::Relaxed) + lines;
+            progress(scanned as f64 / total.max(1) as f64);
+        }
+    }
+}
+
+/// A highlighted part of a line. The range is given in bytes and in UTF-16
+/// code units, which is how JavaScript indexes strings.
+#[derive(Debug, Clone, Serialize)]
+pub struct HighlightSpan {
+    pub byte_start: usize,
+    pub byte_end: usize,
+    pub start: usize,
+    pub end: usize,
+    /// `None` for a match of the search query.
+    pub filter_id: Option<String>,
+    pub color: String,
+    pub text_color: Option<String>,
 }
 
 pub struct FilterEngine {
     filters: Vec<(LogFilter, Regex)>,
+    sets: Vec<FieldSet>,
+    has_includes: bool,
     search_regex: Option<Regex>,
+    search_text: String,
+    query: Option<Expr>,
+    query_text: String,
+    context: (usize, usize),
+    min_level: Option<LogLevel>,
+    processes: Arc<ProcessMap>,
+    event_tags: Arc<EventTags>,
 }
 
 impl FilterEngine {
@@ -26,12 +183,87 @@ impl FilterEngine {
                 compiled.push((f, re));
             }
         }
+
+        let mut sets: Vec<FieldSet> = Vec::new();
+        for (i, (f, _)) in compiled.iter().enumerate() {
+            match sets.iter_mut().find(|s| s.field == f.field) {
+                Some(set) => set.filters.push(i),
+                None => sets.push(FieldSet {
+                    field: f.field.clone(),
+                    set: RegexSet::empty(),
+                    filters: vec![i],
+                }),
+            }
+        }
+        for set in &mut sets {
+            set.set = RegexSet::new(set.filters.iter().map(|&i| &compiled[i].0.pattern))
+                .map_err(|e| format!("Invalid regex: {}", e))?;
+        }
+
         Ok(Self {
+            has_includes: compiled.iter().any(|(f, _)| f.is_include),
             filters: compiled,
+            sets,
             search_regex: None,
+            search_text: String::new(),
+            query: None,
+            query_text: String::new(),
+            context: (0, 0),
+            min_level: None,
+            processes: Arc::default(),
+            event_tags: Arc::default(),
         })
     }
 
+    pub fn set_context(&mut self, context: ContextLines) {
+        self.context = (context.before, context.after);
+    }
+
+    /// The most context any match can get, before and after.
+    pub fn max_context(&self) -> (usize, usize) {
+        self.filters
+            .iter()
+            .filter(|(f, _)| f.is_include)
+            .fold(self.context, |(b, a), (f, _)| {
+                (b.max(f.context_before), a.max(f.context_after))
+            })
+    }
+
+    /// Hides records below `level`. Lines that don't parse as records have
+    /// no level and are kept.
+    pub fn set_min_level(&mut self, level: Option<LogLevel>) {
+        self.min_level = level;
+    }
+
+    /// Whether any filter is scoped to the package or process field, which
+    /// need a process map from `set_processes`.
+    pub fn needs_processes(&self) -> bool {
+        [FilterField::Package, FilterField::Process]
+            .iter()
+            .any(|field| {
+                self.filters
+                    .iter()
+                    .any(|(f, _)| f.field.as_ref() == Some(field))
+                    || self.query.as_ref().is_some_and(|q| q.uses_field(field))
+            })
+    }
+
+    fn needs_event_tags(&self) -> bool {
+        self.filters
+            .iter()
+            .any(|(f, _)| matches!(f.field, Some(FilterField::Event(_))))
+            || self.query.as_ref().is_some_and(|q| q.uses_event_fields())
+    }
+
+    pub fn set_processes(&mut self, processes: Arc<ProcessMap>) {
+        self.processes = processes;
+    }
+
+    /// Sets the tag definitions event field filters are resolved against.
+    pub fn set_event_tags(&mut self, event_tags: Arc<EventTags>) {
+        self.event_tags = event_tags;
+    }
+
     pub fn set_search_query(&mut self, query: &str) -> Result<(), String> {
         if query.is_empty() {
             self.search_regex = None;
@@ -40,120 +272,1019 @@ impl FilterEngine {
                 .map_err(|e| format!("Invalid search regex: {}", e))?;
             self.search_regex = Some(re);
         }
+        self.search_text = query.to_string();
         Ok(())
     }
 
-    /// Returns true if the line passes all filter criteria.
-    pub fn matches(&self, line: &str) -> bool {
-        let include_filters: Vec<_> = self.filters.iter().filter(|(f, _)| f.is_include).collect();
-        let exclude_filters: Vec<_> = self.filters.iter().filter(|(f, _)| !f.is_include).collect();
+    /// Sets a boolean query (see `query::parse`) that lines must satisfy on
+    /// top of the filters, like the search query.
+    pub fn set_query(&mut self, query: &str) -> Result<(), String> {
+        self.query = query::parse(query).map_err(|e| format!("Invalid query: {}", e))?;
+        self.query_text = query.trim().to_string();
+        Ok(())
+    }
+
+    /// Whether every line this engine shows is also shown by `previous`, so
+    /// that filtering `previous`'s result gives the same lines as a full
+    /// scan. Only recognises simple cases: added exclusions, dropped
+    /// inclusions, a raised minimum level or a lengthened literal search.
+    /// Context lines could come from outside the previous result, so an
+    /// engine with context never narrows.
+    pub fn narrows(&self, previous: &FilterEngine) -> bool {
+        let same_filter = |a: &LogFilter, b: &LogFilter| {
+            a.is_include == b.is_include && a.field == b.field && a.pattern == b.pattern
+        };
+        let keeps_exclusions = previous
+            .filters
+            .iter()
+            .filter(|(f, _)| !f.is_include)
+            .all(|(old, _)| self.filters.iter().any(|(new, _)| same_filter(old, new)));
+        let narrows_inclusions = !previous.has_includes
+            || (self.has_includes
+                && self
+                    .filters
+                    .iter()
+                    .filter(|(f, _)| f.is_include)
+                    .all(|(new, _)| {
+                        previous
+                            .filters
+                            .iter()
+                            .any(|(old, _)| same_filter(old, new))
+                    }));
+        let narrows_level = match (previous.min_level, self.min_level) {
+            (None, _) => true,
+            (Some(old), Some(new)) => new >= old,
+            (Some(_), None) => false,
+        };
+        let narrows_search = previous.search_regex.is_none()
+            || previous.search_text == self.search_text
+            || (is_literal(&previous.search_text)
+                && is_literal(&self.search_text)
+                && self
+                    .search_text
+                    .to_lowercase()
+                    .contains(&previous.search_text.to_lowercase()));
+        let narrows_query = previous.query.is_none() || previous.query_text == self.query_text;
+        let same_processes = !(self.needs_processes() || previous.needs_processes())
+            || self.processes == previous.processes;
+        let same_event_tags = !(self.needs_event_tags() || previous.needs_event_tags())
+            || Arc::ptr_eq(&self.event_tags, &previous.event_tags);
+
+        self.max_context() == (0, 0)
+            && keeps_exclusions
+            && narrows_inclusions
+            && narrows_level
+            && narrows_search
+            && narrows_query
+            && same_processes
+            && same_event_tags
+    }
+
+    /// Evaluates every filter against the line in one pass per field and
+    /// decides visibility and colour from the hits. `hits` is a scratch
+    /// buffer that is left holding, for each filter, whether it hit.
+    pub fn evaluate(&self, line: &str, hits: &mut Vec<bool>) -> LineMatch<'_> {
+        let parsed = self.parse(line);
+        self.fill_hits(line, parsed.as_ref(), hits);
+        let hidden = LineMatch {
+            visible: false,
+            filter: None,
+        };
+
+        if !self.passes_min_level(parsed.as_ref()) {
+            return hidden;
+        }
 
         // 1. Check Exclusions
-        for (_, re) in exclude_filters {
-            if re.is_match(line) {
-                return false;
-            }
+        if self
+            .filters
+            .iter()
+            .zip(hits.iter())
+            .any(|((f, _), &hit)| hit && !f.is_include)
+        {
+            return hidden;
         }
 
         // 2. Check Search Query
         if let Some(re) = &self.search_regex {
             if !re.is_match(line) {
-                return false;
+                return hidden;
             }
         }
-
-        // 3. Check Inclusions
-        if include_filters.is_empty() {
-            return true;
+        if let Some(expr) = &self.query {
+            if !self.eval(expr, line, parsed.as_ref()) {
+                return hidden;
+            }
         }
 
-        for (_, re) in include_filters {
-            if re.is_match(line) {
-                return true;
-            }
+        // 3. Check Inclusions
+        let filter = self
+            .filters
+            .iter()
+            .zip(hits.iter())
+            .find(|((f, _), &hit)| hit && f.is_include)
+            .map(|((f, _), _)| f);
+        LineMatch {
+            visible: !self.has_includes || filter.is_some(),
+            filter,
         }
+    }
 
-        false
+    /// Returns true if the line passes all filter criteria.
+    pub fn matches(&self, line: &str) -> bool {
+        self.evaluate(line, &mut Vec::new()).visible
     }
 
     /// Returns the color of the first matching filter, if any.
     pub fn get_match_color(&self, line: &str) -> Option<String> {
-        let include_filters: Vec<_> = self.filters.iter().filter(|(f, _)| f.is_include).collect();
-        let exclude_filters: Vec<_> = self.filters.iter().filter(|(f, _)| !f.is_include).collect();
+        self.color_of(&self.evaluate(line, &mut Vec::new()))
+    }
 
-        // 1. Check Exclusions
-        for (_, re) in exclude_filters {
-            if re.is_match(line) {
-                return None;
-            }
+    pub fn color_of(&self, result: &LineMatch) -> Option<String> {
+        if !result.visible {
+            return None;
         }
+        match result.filter {
+            Some(f) => Some(
+                f.color
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_MATCH_COLOR.to_string()),
+            ),
+            // Only return a color if there's actually a search
+            None => (self.search_regex.is_some() || self.query.is_some())
+                .then(|| DEFAULT_MATCH_COLOR.to_string()),
+        }
+    }
 
-        // 2. Check Search Query
-        if let Some(re) = &self.search_regex {
-            if !re.is_match(line) {
-                return None;
-            }
+    pub fn get_match_counts(&self, lines: &[String]) -> HashMap<String, usize> {
+        self.get_match_counts_by(lines.len(), |i| lines[i].as_str())
+    }
+
+    /// Counts matches per filter over `count` lines fetched through `line_at`.
+    pub fn get_match_counts_by<F, S>(&self, count: usize, line_at: F) -> HashMap<String, usize>
+    where
+        F: Fn(usize) -> S + Sync,
+        S: AsRef<str>,
+    {
+        let totals = (0..count)
+            .into_par_iter()
+            .fold(
+                || (vec![0usize; self.filters.len()], Vec::new()),
+                |(mut counts, mut hits), i| {
+                    let line = line_at(i);
+                    let line = line.as_ref();
+                    let parsed = self.parse(line);
+                    self.fill_hits(line, parsed.as_ref(), &mut hits);
+                    add_hits(&mut counts, &hits);
+                    (counts, hits)
+                },
+            )
+            .map(|(counts, _)| counts)
+            .reduce(|| vec![0usize; self.filters.len()], sum_counts);
+
+        self.counts_by_id(totals)
+    }
+
+    /// Filters the lines in `lines`, fetched through `line_at`, in one pass,
+    /// returning the indices of visible lines in order and, over the
+    /// matches, how often each filter hit.
+    ///
+    /// With context configured, lines around each match are included too.
+    /// Matches up to the largest after-context before `lines.start` are
+    /// evaluated so their trailing context carries into the range; lines
+    /// before `lines.start` are never returned.
+    pub fn filter_by<F, S>(&self, lines: Range<usize>, line_at: F) -> FilterOutcome
+    where
+        F: Fn(usize) -> S + Sync,
+        S: AsRef<str>,
+    {
+        self.run_filter(lines, line_at, &FilterRun::default())
+            .unwrap_or_default()
+    }
+
+    /// Like `filter_by`, but gives up with `None` as soon as `run` is
+    /// cancelled.
+    pub fn run_filter<F, S>(
+        &self,
+        lines: Range<usize>,
+        line_at: F,
+        run: &FilterRun,
+    ) -> Option<FilterOutcome>
+    where
+        F: Fn(usize) -> S + Sync,
+        S: AsRef<str>,
+    {
+        if run.cancel.is_cancelled() {
+            return None;
         }
+        let (max_before, max_after) = self.max_context();
+        let has_context = max_before > 0 || max_after > 0;
+        let scan_from = if has_context {
+            lines.start.saturating_sub(max_after)
+        } else {
+            lines.start
+        };
+        let new_scan = || Scan {
+            matches: Vec::new(),
+            context: Vec::new(),
+            counts: vec![0usize; self.filters.len()],
+            hits: Vec::new(),
+        };
+        let total = lines.end.saturating_sub(scan_from);
 
-        // 3. Check Inclusions
-        if include_filters.is_empty() {
-            // Only return a color if there's actually a search or filters
-            return self.search_regex.as_ref().map(|_| "#fa5feb".to_string());
+        let scan = (0..total.div_ceil(CHUNK_LINES))
+            .into_par_iter()
+            .try_fold(new_scan, |mut scan, chunk| {
+                if run.cancel.is_cancelled() {
+                    return None;
+                }
+                let start = scan_from + chunk * CHUNK_LINES;
+                let end = std::cmp::min(start + CHUNK_LINES, lines.end);
+
+                for i in start..end {
+                    let line = line_at(i);
+                    if self.evaluate(line.as_ref(), &mut scan.hits).visible {
+                        scan.matches.push(i);
+                        if has_context {
+                            scan.context.push(self.context_of(&scan.hits));
+                        }
+                        if i >= lines.start {
+                            add_hits(&mut scan.counts, &scan.hits);
+                        }
+                    }
+                }
+                run.report(end - start, total);
+                Some(scan)
+            })
+            .try_reduce(new_scan, |mut a, b| {
+                a.matches.extend(b.matches);
+                a.context.extend(b.context);
+                a.counts = sum_counts(a.counts, b.counts);
+                Some(a)
+            })?;
+
+        let counts = self.counts_by_id(scan.counts);
+        if !has_context {
+            return Some(FilterOutcome {
+                match_count: scan.matches.len(),
+                visible: scan.matches,
+                context: None,
+                counts,
+            });
         }
 
-        for (f, re) in include_filters {
-            if re.is_match(line) {
-                return f.color.clone().or_else(|| Some("#fa5feb".to_string()));
+        let match_count = scan.matches.iter().filter(|&&m| m >= lines.start).count();
+        let (visible, context) = expand_context(lines, &scan.matches, &scan.context);
+        Some(FilterOutcome {
+            visible,
+            context: Some(context),
+            match_count,
+            counts,
+        })
+    }
+
+    /// Context for a match: the engine-wide amount, widened by every include
+    /// filter that hit.
+    fn context_of(&self, hits: &[bool]) -> (u32, u32) {
+        let (before, after) = self
+            .filters
+            .iter()
+            .zip(hits)
+            .filter(|((f, _), &hit)| hit && f.is_include)
+            .fold(self.context, |(b, a), ((f, _), _)| {
+                (b.max(f.context_before), a.max(f.context_after))
+            });
+        (
+            before.min(u32::MAX as usize) as u32,
+            after.min(u32::MAX as usize) as u32,
+        )
+    }
+
+    /// Where the search query and each filter match in `line`, as
+    /// non-overlapping spans in line order. Where matches overlap, the
+    /// search query wins, then filters in list order, the same order that
+    /// picks a row's colour. Filters on pid, tid, level, package or process
+    /// have no position in the line and produce no spans.
+    pub fn highlight_spans(&self, line: &str) -> Vec<HighlightSpan> {
+        const NONE: usize = usize::MAX;
+        // Source 0 is the search query, source i + 1 is filter i.
+        let mut owner = vec![NONE; line.len()];
+        let parsed = self.parse(line);
+        let record = parsed.as_ref().and_then(LogLine::record);
+
+        for (i, (f, re)) in self.filters.iter().enumerate().rev() {
+            let value = match &f.field {
+                None => Some(Cow::Borrowed(line)),
+                Some(field) => record.and_then(|r| self.field_value(r, field)),
+            };
+            let Some(base) = value.as_deref().and_then(|v| offset_within(line, v)) else {
+                continue;
+            };
+            for m in re.find_iter(value.as_deref().unwrap_or_default()) {
+                owner[base + m.start()..base + m.end()].fill(i + 1);
+            }
+        }
+        if let Some(re) = &self.search_regex {
+            for m in re.find_iter(line) {
+                owner[m.start()..m.end()].fill(0);
             }
         }
 
-        None
+        let mut spans = Vec::new();
+        let mut start = 0;
+        while start < owner.len() {
+            let source = owner[start];
+            let end = owner[start..]
+                .iter()
+                .position(|&o| o != source)
+                .map_or(owner.len(), |n| start + n);
+
+            if source != NONE {
+                let (filter_id, color, text_color) = match source {
+                    0 => (None, DEFAULT_MATCH_COLOR.to_string(), None),
+                    i => {
+                        let f = &self.filters[i - 1].0;
+                        (
+                            Some(f.id.clone()),
+                            f.color
+                                .clone()
+                                .unwrap_or_else(|| DEFAULT_MATCH_COLOR.to_string()),
+                            f.text_color.clone(),
+                        )
+                    }
+                };
+                let utf16_start = line[..start].encode_utf16().count();
+                spans.push(HighlightSpan {
+                    byte_start: start,
+                    byte_end: end,
+                    start: utf16_start,
+                    end: utf16_start + line[start..end].encode_utf16().count(),
+                    filter_id,
+                    color,
+                    text_color,
+                });
+            }
+            start = end;
+        }
+        spans
     }
 
-    pub fn get_match_counts(&self, lines: &[String]) -> std::collections::HashMap<String, usize> {
+    fn counts_by_id(&self, counts: Vec<usize>) -> HashMap<String, usize> {
         self.filters
-            .par_iter()
-            .map(|(f, re)| {
-                let count = lines.iter().filter(|l| re.is_match(l)).count();
-                (f.id.clone(), count)
-            })
+            .iter()
+            .zip(counts)
+            .map(|((f, _), n)| (f.id.clone(), n))
             .collect()
     }
 
-    pub fn get_match_counts_from_offsets(
+    /// Multi-threaded batch filtering
+    pub fn filter_indices(&self, lines: &[String]) -> Vec<usize> {
+        self.filter_by(0..lines.len(), |i| lines[i].as_str())
+            .visible
+    }
+
+    /// Parses the line only when a field filter, the query or the minimum
+    /// level needs it, so whole-line filtering stays as cheap as before.
+    fn parse<'a>(&self, line: &'a str) -> Option<LogLine<'a>> {
+        let needed = self.min_level.is_some()
+            || self.filters.iter().any(|(f, _)| f.field.is_some())
+            || self.query.as_ref().is_some_and(Expr::uses_fields);
+        needed.then(|| logcat::parse_line(line))
+    }
+
+    fn fill_hits(&self, line: &str, parsed: Option<&LogLine>, hits: &mut Vec<bool>) {
+        hits.clear();
+        hits.resize(self.filters.len(), false);
+
+        let record = parsed.and_then(LogLine::record);
+        for set in &self.sets {
+            let value = match &set.field {
+                None => Some(Cow::Borrowed(line)),
+                Some(field) => record.and_then(|r| self.field_value(r, field)),
+            };
+            if let Some(value) = value {
+                for i in set.set.matches(&value).iter() {
+                    hits[set.filters[i]] = true;
+                }
+            }
+        }
+    }
+
+    fn passes_min_level(&self, parsed: Option<&LogLine>) -> bool {
+        match (self.min_level, parsed.and_then(LogLine::record)) {
+            (Some(min), Some(record)) => record.level >= min,
+            _ => true,
+        }
+    }
+
+    fn eval(&self, expr: &Expr, line: &str, parsed: Option<&LogLine>) -> bool {
+        match expr {
+            Expr::And(a, b) => self.eval(a, line, parsed) && self.eval(b, line, parsed),
+            Expr::Or(a, b) => self.eval(a, line, parsed) || self.eval(b, line, parsed),
+            Expr::Not(e) => !self.eval(e, line, parsed),
+            Expr::Match { field, regex } => self.field_matches(field.as_ref(), regex, line, parsed),
+            Expr::Level { comparison, level } => parsed
+                .and_then(LogLine::record)
+                .is_some_and(|record| comparison.holds(record.level.cmp(level))),
+        }
+    }
+
+    fn field_matches(
         &self,
-        indexer: &crate::indexer::Indexer,
-        offsets: &[usize],
-    ) -> std::collections::HashMap<String, usize> {
-        self.filters
-            .par_iter()
-            .map(|(f, re)| {
-                let count = offsets
-                    .iter()
-                    .filter(|&&offset| {
-                        let line_data = indexer.get_line_at_offset_raw(offset);
-                        re.is_match(&line_data)
-                    })
-                    .count();
-                (f.id.clone(), count)
+        field: Option<&FilterField>,
+        re: &Regex,
+        line: &str,
+        parsed: Option<&LogLine>,
+    ) -> bool {
+        match field {
+            None => re.is_match(line),
+            Some(field) => parsed
+                .and_then(LogLine::record)
+                .and_then(|record| self.field_value(record, field))
+                .is_some_and(|value| re.is_match(&value)),
+        }
+    }
+
+    fn field_value<'a>(
+        &'a self,
+        record: &'a LogRecord,
+        field: &FilterField,
+    ) -> Option<Cow<'a, str>> {
+        match field {
+            FilterField::Tag => record.tag.as_deref().map(Cow::Borrowed),
+            FilterField::Pid => record.pid.map(|pid| Cow::Owned(pid.to_string())),
+            FilterField::Tid => record.tid.map(|tid| Cow::Owned(tid.to_string())),
+            FilterField::Level => Some(Cow::Owned(record.level.as_char().to_string())),
+            FilterField::Message => Some(Cow::Borrowed(&record.message)),
+            FilterField::Package => self
+                .processes
+                .name(record.pid?, record.timestamp)
+                .map(|name| Cow::Borrowed(processes::package_of(name))),
+            FilterField::Process => self
+                .processes
+                .name(record.pid?, record.timestamp)
+                .map(Cow::Borrowed),
+            FilterField::Event(name) => self
+                .event_tags
+                .field_value(record.tag.as_deref()?, &record.message, name)
+                .map(Cow::Borrowed),
+        }
+    }
+}
+
+/// Merges sorted `matches` and the lines of context around each into one
+/// sorted list of lines within `lines`, flagging the context lines.
+fn expand_context(
+    lines: Range<usize>,
+    matches: &[usize],
+    context: &[(u32, u32)],
+) -> (Vec<usize>, Vec<bool>) {
+    let mut visible = Vec::with_capacity(matches.len());
+    let mut is_context = Vec::with_capacity(matches.len());
+    // First line not yet emitted, and the end of the pending after-context.
+    let mut next = lines.start;
+    let mut after_until = lines.start;
+
+    for (&m, &(before, after)) in matches.iter().zip(context) {
+        let trailing_end = after_until.min(m);
+        while next < trailing_end {
+            visible.push(next);
+            is_context.push(true);
+            next += 1;
+        }
+
+        if m >= lines.start {
+            next = next.max(m.saturating_sub(before as usize));
+            while next < m {
+                visible.push(next);
+                is_context.push(true);
+                next += 1;
+            }
+            visible.push(m);
+            is_context.push(false);
+            next = m + 1;
+        }
+        after_until = after_until.max(m.saturating_add(after as usize + 1).min(lines.end));
+    }
+
+    while next < after_until {
+        visible.push(next);
+        is_context.push(true);
+        next += 1;
+    }
+    (visible, is_context)
+}
+
+/// Whether `pattern` matches only its own text, so a line matching a longer
+/// literal containing it matches it too.
+fn is_literal(pattern: &str) -> bool {
+    regex::escape(pattern) == pattern
+}
+
+fn add_hits(counts: &mut [usize], hits: &[bool]) {
+    for (n, &hit) in counts.iter_mut().zip(hits) {
+        *n += hit as usize;
+    }
+}
+
+fn sum_counts(mut a: Vec<usize>, b: Vec<usize>) -> Vec<usize> {
+    a.iter_mut().zip(b).for_each(|(x, y)| *x += y);
+    a
+}
+
+/// Byte offset of `part` within `line` when `part` borrows from it, as the
+/// tag and message of a parsed record do.
+fn offset_within(line: &str, part: &str) -> Option<usize> {
+    let base = line.as_ptr() as usize;
+    let start = part.as_ptr() as usize;
+    (start >= base && start + part.len() <= base + line.len()).then(|| start - base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(pattern: &str, is_include: bool, field: Option<FilterField>) -> LogFilter {
+        LogFilter {
+            id: pattern.to_string(),
+            pattern: pattern.to_string(),
+            is_include,
+            is_enabled: true,
+            color: None,
+            text_color: None,
+            field,
+            context_before: 0,
+            context_after: 0,
+        }
+    }
+
+    fn engine(filters: Vec<LogFilter>, search: &str, level: Option<LogLevel>) -> FilterEngine {
+        let mut engine = FilterEngine::new(filters).unwrap();
+        engine.set_search_query(search).unwrap();
+        engine.set_min_level(level);
+        engine
+    }
+
+    fn sample_lines() -> Vec<String> {
+        let tags = ["ActivityManager", "WindowManager", "Zygote", "chatty"];
+        let levels = ['V', 'D', 'I', 'W', 'E'];
+        (0..2000)
+            .map(|i| {
+                if i % 97 == 0 {
+                    return format!("\tat com.example.Foo.bar(Foo.java:{})", i);
+                }
+                format!(
+                    "01-02 03:04:05.{:03}  {:4}  {:4} {} {}: message {} with token{}",
+                    i % 1000,
+                    100 + i % 7,
+                    200 + i % 11,
+                    levels[i % levels.len()],
+                    tags[i % tags.len()],
+                    i,
+                    i % 13
+                )
             })
             .collect()
     }
 
-    /// Multi-threaded batch filtering
-    pub fn filter_indices(&self, lines: &[String]) -> Vec<usize> {
-        lines
-            .par_iter()
+    #[test]
+    fn unscoped_filters_match_the_whole_line() {
+        let line = "01-02 03:04:05.678  1234  5678 W Zygote: forked com.example";
+        for (pattern, expected) in [
+            ("03:04", true),
+            ("1234  5678", true),
+            (" W Zygote", true),
+            ("Zygote: forked", true),
+            ("com\\.example$", true),
+            ("com.other", false),
+        ] {
+            let engine = engine(vec![filter(pattern, true, None)], "", None);
+            assert_eq!(engine.matches(line), expected, "{}", pattern);
+        }
+        let excluded = engine(vec![filter("5678", false, None)], "", None);
+        assert!(!excluded.matches(line));
+    }
+
+    #[test]
+    fn scoped_filters_ignore_other_fields() {
+        let tag = Some(FilterField::Tag);
+        let by_tag = engine(vec![filter("Zygote", true, tag.clone())], "", None);
+        assert!(by_tag.matches("01-02 03:04:05.678  1234  5678 I Zygote: forked"));
+        assert!(!by_tag.matches("01-02 03:04:05.678  1234  5678 I Init: Zygote forked"));
+        // Without fields to match, unparsable lines never hit a scoped filter.
+        assert!(!by_tag.matches("Zygote: forked"));
+
+        let hiding_tag = engine(vec![filter("Zygote", false, tag)], "", None);
+        assert!(!hiding_tag.matches("01-02 03:04:05.678  1234  5678 I Zygote: forked"));
+        assert!(hiding_tag.matches("01-02 03:04:05.678  1234  5678 I Init: Zygote forked"));
+        assert!(hiding_tag.matches("Zygote: forked"));
+    }
+
+    #[test]
+    fn min_level_keeps_lines_without_a_level() {
+        let warnings = engine(Vec::new(), "", Some(LogLevel::Warn));
+        assert!(!warnings.matches("01-02 03:04:05.678  1234  5678 I Zygote: forked"));
+        assert!(warnings.matches("01-02 03:04:05.678  1234  5678 W Zygote: forked"));
+        assert!(warnings.matches("01-02 03:04:05.678  1234  5678 E Zygote: forked"));
+        assert!(warnings.matches("\tat com.example.Foo.bar(Foo.java:12)"));
+        assert!(warnings.matches("--------- beginning of main"));
+        assert!(warnings.matches("not logcat output"));
+
+        // Filters still apply to the lines the level lets through.
+        let filtered = engine(vec![filter("Foo", true, None)], "", Some(LogLevel::Warn));
+        assert!(filtered.matches("\tat com.example.Foo.bar(Foo.java:12)"));
+        assert!(!filtered.matches("not logcat output"));
+    }
+
+    #[test]
+    fn counts_each_filter_as_its_own_regex_would() {
+        let lines = sample_lines();
+        let tag = Some(FilterField::Tag);
+        let filters = vec![
+            filter("message 1", true, None),
+            filter("message 1[0-9]", true, None),
+            filter("token1$", false, None),
+            filter("token1", true, None),
+            filter("Manager", true, tag.clone()),
+            filter("Activity", true, tag),
+            filter("Manager", true, None),
+        ];
+        let filters: Vec<LogFilter> = filters
+            .into_iter()
             .enumerate()
-            .filter_map(
-                |(idx, line)| {
-                    if self.matches(line) {
-                        Some(idx)
-                    } else {
-                        None
-                    }
-                },
-            )
+            .map(|(i, f)| LogFilter {
+                id: i.to_string(),
+                ..f
+            })
+            .collect();
+        let counts = engine(filters.clone(), "", None)
+            .get_match_counts_by(lines.len(), |i| lines[i].as_str());
+
+        for f in &filters {
+            let re = Regex::new(&f.pattern).unwrap();
+            let expected = lines
+                .iter()
+                .filter(|line| match &f.field {
+                    None => re.is_match(line),
+                    Some(_) => logcat::parse_line(line)
+                        .record()
+                        .and_then(|r| r.tag.as_deref())
+                        .is_some_and(|t| re.is_match(t)),
+                })
+                .count();
+            assert!(expected > 0, "{}", f.pattern);
+            assert_eq!(counts[&f.id], expected, "{}", f.pattern);
+        }
+    }
+
+    #[test]
+    fn cancelled_runs_return_nothing() {
+        let lines = sample_lines();
+        let previous = engine(
+            vec![filter("Zygote", true, Some(FilterField::Tag))],
+            "",
+            None,
+        );
+        let mut filtered_offsets = Some(
+            previous
+                .filter_by(0..lines.len(), |i| lines[i].as_str())
+                .visible,
+        );
+        let before = filtered_offsets.clone();
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let reported = AtomicUsize::new(0);
+        let progress = |_| {
+            reported.fetch_add(1, Ordering::Relaxed);
+        };
+        let run = FilterRun::new(cancel, &progress);
+
+        let mut next = engine(vec![filter("token3", true, None)], "", None);
+        for context in [
+            ContextLines::default(),
+            ContextLines {
+                before: 2,
+                after: 2,
+            },
+        ] {
+            next.set_context(context);
+            for range in [0..lines.len(), 500..600, 0..0] {
+                // As `apply_filters` does, only a finished run replaces the
+                // stored result.
+                if let Some(outcome) = next.run_filter(range, |i| lines[i].as_str(), &run) {
+                    filtered_offsets = Some(outcome.visible);
+                }
+            }
+        }
+        assert_eq!(filtered_offsets, before);
+        assert_eq!(reported.load(Ordering::Relaxed), 0);
+    }
+
+    /// `expand_context` as (line, is context) pairs.
+    fn expanded(
+        lines: Range<usize>,
+        matches: &[usize],
+        context: &[(u32, u32)],
+    ) -> Vec<(usize, bool)> {
+        let (visible, is_context) = expand_context(lines, matches, context);
+        visible.into_iter().zip(is_context).collect()
+    }
+
+    #[test]
+    fn merges_overlapping_context_windows() {
+        assert_eq!(
+            expanded(0..10, &[3, 5], &[(2, 2), (2, 2)]),
+            vec![
+                (1, true),
+                (2, true),
+                (3, false),
+                (4, true),
+                (5, false),
+                (6, true),
+                (7, true),
+            ]
+        );
+        // A match within the after-context of the one before stays a match,
+        // and the wider window wins.
+        assert_eq!(
+            expanded(0..10, &[3, 4], &[(0, 3), (0, 0)]),
+            vec![(3, false), (4, false), (5, true), (6, true)]
+        );
+        assert_eq!(
+            expanded(0..10, &[2, 8], &[(1, 1), (1, 1)]),
+            vec![
+                (1, true),
+                (2, false),
+                (3, true),
+                (7, true),
+                (8, false),
+                (9, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn clips_context_windows_to_the_lines() {
+        assert_eq!(
+            expanded(0..10, &[0, 9], &[(3, 3), (3, 3)]),
+            vec![
+                (0, false),
+                (1, true),
+                (2, true),
+                (3, true),
+                (6, true),
+                (7, true),
+                (8, true),
+                (9, false),
+            ]
+        );
+        // A match before the range only contributes its after-context, as
+        // when following a file rescans the lines above the appended ones.
+        assert_eq!(
+            expanded(4..10, &[2, 8], &[(1, 3), (1, 3)]),
+            vec![(4, true), (5, true), (7, true), (8, false), (9, true)]
+        );
+        assert_eq!(expanded(4..10, &[1], &[(0, 2)]), vec![]);
+    }
+
+    #[test]
+    fn filters_with_context_lines() {
+        let lines: Vec<String> = [
+            "boot",
+            "a",
+            "FATAL EXCEPTION: main",
+            "at Foo",
+            "b",
+            "c",
+            "FATAL EXCEPTION: worker",
+            "at Bar",
+            "d",
+            "e",
+        ]
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+
+        // The engine-wide context is widened by the filter's own.
+        let mut fatal = filter("FATAL", true, None);
+        fatal.context_after = 1;
+        let mut engine = engine(vec![fatal], "", None);
+        engine.set_context(ContextLines {
+            before: 1,
+            after: 0,
+        });
+        let outcome = engine.filter_by(0..lines.len(), |i| lines[i].as_str());
+        assert_eq!(outcome.match_count, 2);
+        assert_eq!(outcome.visible, vec![1, 2, 3, 5, 6, 7]);
+        assert_eq!(
+            outcome.context,
+            Some(vec![true, false, true, true, false, true])
+        );
+        assert_eq!(outcome.counts["FATAL"], 2);
+    }
+
+    /// The highlighted parts of `line` and the filter each belongs to.
+    fn spans<'a>(engine: &FilterEngine, line: &'a str) -> Vec<(&'a str, Option<String>)> {
+        engine
+            .highlight_spans(line)
+            .into_iter()
+            .map(|s| (&line[s.byte_start..s.byte_end], s.filter_id))
             .collect()
     }
+
+    #[test]
+    fn search_then_filters_in_order_win_overlaps() {
+        let engine = engine(
+            vec![
+                filter("alpha beta", true, None),
+                filter("beta gamma", true, None),
+            ],
+            "beta",
+            None,
+        );
+        let line = "01-02 03:04:05.000   100   200 I Tag: alpha beta gamma";
+        assert_eq!(
+            spans(&engine, line),
+            vec![
+                ("alpha ", Some("alpha beta".to_string())),
+                ("beta", None),
+                (" gamma", Some("beta gamma".to_string())),
+            ]
+        );
+        let search = &engine.highlight_spans(line)[1];
+        assert_eq!(search.color, DEFAULT_MATCH_COLOR);
+        assert_eq!(search.text_color, None);
+    }
+
+    #[test]
+    fn highlights_in_utf16_code_units() {
+        let engine = engine(vec![filter("match", true, None)], "😀", None);
+        let line = "é😀 match";
+        let highlights = engine.highlight_spans(line);
+        let ranges: Vec<_> = highlights
+            .iter()
+            .map(|s| (s.byte_start, s.byte_end, s.start, s.end))
+            .collect();
+        // "é" is one UTF-16 unit in two bytes, the emoji a surrogate pair in
+        // four.
+        assert_eq!(ranges, vec![(2, 6, 1, 3), (7, 12, 4, 9)]);
+    }
+
+    #[test]
+    fn highlights_field_matches_within_the_field() {
+        let line = "01-02 03:04:05.000   100   200 W Wifi: Wifi 100 connected";
+        let tag_start = line.find("Wifi:").unwrap();
+        let message_start = line.rfind("Wifi").unwrap();
+
+        let by_tag = engine(vec![filter("Wifi", true, Some(FilterField::Tag))], "", None);
+        let highlights = by_tag.highlight_spans(line);
+        assert_eq!(highlights.len(), 1);
+        assert_eq!(highlights[0].byte_start, tag_start);
+        assert_eq!(highlights[0].byte_end, tag_start + 4);
+
+        let by_message = engine(
+            vec![filter("Wifi", true, Some(FilterField::Message))],
+            "",
+            None,
+        );
+        let highlights = by_message.highlight_spans(line);
+        assert_eq!(highlights.len(), 1);
+        assert_eq!(highlights[0].byte_start, message_start);
+
+        // Pid and level values are not positions in the line, even where the
+        // same text appears elsewhere in it.
+        for field in [FilterField::Pid, FilterField::Level] {
+            let pattern = if field == FilterField::Pid {
+                "100"
+            } else {
+                "W"
+            };
+            let scoped = engine(vec![filter(pattern, true, Some(field))], "", None);
+            assert!(scoped.matches(line));
+            assert!(scoped.highlight_spans(line).is_empty());
+        }
+    }
+
+    /// Checks that `next` is detected as narrowing `previous` exactly when
+    /// `expected` says so, and that re-filtering the previous result then
+    /// gives the same outcome as a full scan.
+    fn check(lines: &[String], previous: &FilterEngine, next: &FilterEngine, expected: bool) {
+        assert_eq!(next.narrows(previous), expected);
+
+        let all = 0..lines.len();
+        let full = next.filter_by(all.clone(), |i| lines[i].as_str());
+        let before = previous.filter_by(all, |i| lines[i].as_str()).visible;
+        let mut refined = next
+            .run_filter(
+                0..before.len(),
+                |i| lines[before[i]].as_str(),
+                &FilterRun::default(),
+            )
+            .unwrap();
+        for visible in &mut refined.visible {
+            *visible = before[*visible];
+        }
+
+        if expected {
+            assert_eq!(refined.visible, full.visible);
+            assert_eq!(refined.counts, full.counts);
+        } else {
+            assert_ne!(refined.visible, full.visible);
+        }
+    }
+
+    #[test]
+    fn narrowing_changes_match_full_scan() {
+        let lines = sample_lines();
+        let tag = Some(FilterField::Tag);
+        let include_am = filter("Activity", true, tag.clone());
+        let include_wm = filter("Window", true, tag);
+        let exclude = filter("token1$", false, None);
+
+        let base = engine(vec![include_am.clone(), include_wm.clone()], "", None);
+        let added_exclude = engine(
+            vec![include_am.clone(), include_wm.clone(), exclude.clone()],
+            "",
+            None,
+        );
+        check(&lines, &base, &added_exclude, true);
+
+        let dropped_include = engine(vec![include_am.clone()], "", None);
+        check(&lines, &base, &dropped_include, true);
+        check(&lines, &dropped_include, &base, false);
+
+        let raised_level = engine(vec![include_am.clone()], "", Some(LogLevel::Warn));
+        check(&lines, &dropped_include, &raised_level, true);
+        check(&lines, &raised_level, &dropped_include, false);
+
+        let searched = engine(vec![include_am.clone()], "message 1", None);
+        let longer_search = engine(vec![include_am.clone()], "MESSAGE 12", None);
+        check(&lines, &searched, &longer_search, true);
+        check(&lines, &longer_search, &searched, false);
+
+        let regex_search = engine(vec![include_am.clone()], "message 1.", None);
+        let longer_regex = engine(vec![include_am.clone()], "message 1.0", None);
+        assert!(!longer_regex.narrows(&regex_search));
+
+        let unfiltered = engine(vec![exclude.clone()], "", None);
+        check(&lines, &unfiltered, &added_exclude, true);
+        check(&lines, &added_exclude, &unfiltered, false);
+    }
+
+    #[test]
+    fn context_and_queries_fall_back_to_full_scan() {
+        let base = engine(vec![filter("Zygote", true, None)], "", None);
+
+        let mut with_context = engine(vec![filter("Zygote", true, None)], "", None);
+        with_context.set_context(ContextLines {
+            before: 1,
+            after: 0,
+        });
+        assert!(!with_context.narrows(&base));
+
+        let mut queried = engine(vec![filter("Zygote", true, None)], "", None);
+        queried.set_query("pid:101").unwrap();
+        assert!(queried.narrows(&base));
+
+        let mut requeried = engine(vec![filter("Zygote", true, None)], "", None);
+        requeried.set_query("pid:102").unwrap();
+        assert!(!requeried.narrows(&queried));
+    }
+
+    #[test]
+    fn filters_on_event_fields() {
+        let tags = Arc::new(EventTags::parse(
+            "30014 am_proc_start (User|1|5),(PID|1|5),(UID|1|5),(Process Name|3),(Type|3),(Component|3)",
+        ));
+        let lines = [
+            "01-02 03:04:05.678  1000  1200 I am_proc_start: [0,4321,10123,com.example,activity,com.example/.Main]",
+            "01-02 03:04:05.679  1000  1200 I am_proc_start: [0,4322,10124,com.other,service,com.other/.Svc]",
+        ];
+
+        let mut by_field = engine(
+            vec![filter(
+                "example",
+                true,
+                Some(FilterField::Event("process_name".into())),
+            )],
+            "",
+            None,
+        );
+        assert!(!by_field.matches(lines[0]));
+        by_field.set_event_tags(Arc::clone(&tags));
+        assert!(by_field.matches(lines[0]));
+        assert!(!by_field.matches(lines[1]));
+
+        let mut queried = engine(Vec::new(), "", None);
+        queried.set_event_tags(Arc::clone(&tags));
+        queried.set_query("event.type:service").unwrap();
+        assert!(!queried.matches(lines[0]));
+        assert!(queried.matches(lines[1]));
+
+        let mut reloaded = engine(Vec::new(), "", None);
+        reloaded.set_event_tags(Arc::new(EventTags::parse("30014 am_proc_start (Type|3)")));
+        reloaded.set_query("event.type:service").unwrap();
+        assert!(!reloaded.narrows(&queried));
+    }
 }
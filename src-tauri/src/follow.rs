@@ -0,0 +1,272 @@
+use crate::document::DocumentId;
+use crate::filter::{CancelToken, FilterEngine};
+use crate::indexer::{FileChange, Indexer};
+use crate::processes::ProcessMap;
+use crate::IndexProgress;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Runtime};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Lines that appeared at the end of a followed file. `first_index` is the
+/// position of the first entry of `lines` in the current (filtered) view;
+/// anything already shown from that position on should be replaced.
+#[derive(Clone, Serialize)]
+pub struct FollowLines {
+    pub document_id: DocumentId,
+    pub first_index: usize,
+    pub lines: Vec<String>,
+    pub line_count: usize,
+}
+
+#[derive(Clone, Serialize)]
+pub struct FileReindexed {
+    pub document_id: DocumentId,
+    pub line_count: usize,
+    pub visible_count: usize,
+}
+
+#[derive(Clone, Serialize)]
+pub struct FollowError {
+    pub document_id: DocumentId,
+    pub message: String,
+}
+
+pub struct FollowManager {
+    task: Option<JoinHandle<()>>,
+}
+
+impl Default for FollowManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FollowManager {
+    pub fn new() -> Self {
+        Self { task: None }
+    }
+
+    /// Starts polling the opened file for changes. New lines are indexed
+    /// incrementally, scanned into `processes`, run through `active_filter`
+    /// and emitted as `file-new-lines`; truncation or rotation triggers a
+    /// full re-index reported by `file-reindexed`. The re-index is filtered
+    /// as `apply_filters` does, under a token stored in `filter_run`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn start<R: Runtime>(
+        &mut self,
+        app: AppHandle<R>,
+        document_id: DocumentId,
+        indexer: Arc<Mutex<Option<Indexer>>>,
+        filtered_offsets: Arc<Mutex<Option<Vec<usize>>>>,
+        filtered_context: Arc<Mutex<Option<Vec<bool>>>>,
+        active_filter: Arc<Mutex<Option<FilterEngine>>>,
+        processes: Arc<Mutex<Option<Arc<ProcessMap>>>>,
+        filter_run: Arc<Mutex<Option<CancelToken>>>,
+    ) {
+        self.stop();
+
+        self.task = Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+
+                let mut indexer_lock = indexer.lock().await;
+                let Some(current) = indexer_lock.as_mut() else {
+                    break;
+                };
+
+                let change = match current.refresh() {
+                    Ok(change) => change,
+                    Err(e) => {
+                        let _ = app.emit(
+                            "follow-error",
+                            FollowError {
+                                document_id,
+                                message: e,
+                            },
+                        );
+                        break;
+                    }
+                };
+
+                match change {
+                    FileChange::Unchanged => {}
+                    FileChange::Appended { first_changed_line } => {
+                        // Only a map some filter needed is kept up to date.
+                        let mut processes = processes.lock().await;
+                        if let Some(known) = processes.as_ref() {
+                            let appended: Vec<String> = (first_changed_line..current.line_count())
+                                .filter_map(|i| current.get_line(i))
+                                .collect();
+                            let mut map = ProcessMap::clone(known);
+                            if map.scan(&appended) {
+                                *processes = Some(Arc::new(map));
+                            }
+                        }
+
+                        let mut engine = active_filter.lock().await;
+                        if let (Some(engine), Some(map)) = (engine.as_mut(), processes.as_ref()) {
+                            engine.set_processes(Arc::clone(map));
+                        }
+                        let mut filtered_lock = filtered_offsets.lock().await;
+                        let mut context_lock = filtered_context.lock().await;
+                        let update = append_lines(
+                            document_id,
+                            current,
+                            engine.as_ref(),
+                            filtered_lock.as_mut(),
+                            context_lock.as_mut(),
+                            first_changed_line,
+                        );
+                        let _ = app.emit("file-new-lines", update);
+                    }
+                    FileChange::Replaced => {
+                        let mut replacement = match current.reopen() {
+                            Ok(replacement) => replacement,
+                            // The new file may not be readable yet; try again next tick.
+                            Err(_) => continue,
+                        };
+                        replacement
+                            .index(|p| {
+                                let _ = app.emit(
+                                    "indexing-progress",
+                                    IndexProgress {
+                                        document_id,
+                                        progress: p,
+                                    },
+                                );
+                            })
+                            .await;
+
+                        let mut processes = processes.lock().await;
+                        let mut engine_lock = active_filter.lock().await;
+                        let mut filtered_lock = filtered_offsets.lock().await;
+                        let mut context_lock = filtered_context.lock().await;
+
+                        // The next `apply_filters` cancels this run and filters
+                        // the new file itself once it gets the locks.
+                        let cancel = CancelToken::new();
+                        *filter_run.lock().await = Some(cancel.clone());
+                        let engine = engine_lock.take();
+                        let done = crate::run_blocking(&app, document_id, cancel, move |run| {
+                            let mut engine = engine;
+                            // The old map describes the old file. A new one is
+                            // only built, from every line, if the filters need it.
+                            let map = engine.as_ref().filter(|e| e.needs_processes()).map(|_| {
+                                Arc::new(ProcessMap::collect(replacement.line_count(), |i| {
+                                    replacement.get_line(i).unwrap_or_default()
+                                }))
+                            });
+                            if let (Some(engine), Some(map)) = (engine.as_mut(), map.as_ref()) {
+                                engine.set_processes(Arc::clone(map));
+                            }
+                            let outcome = engine
+                                .as_ref()
+                                .and_then(|e| replacement.run_filters(e, run));
+                            (replacement, engine, map, outcome)
+                        })
+                        .await;
+                        let (replacement, engine, map, outcome) = match done {
+                            Ok(done) => done,
+                            Err(e) => {
+                                let _ = app.emit(
+                                    "follow-error",
+                                    FollowError {
+                                        document_id,
+                                        message: e,
+                                    },
+                                );
+                                break;
+                            }
+                        };
+                        *processes = map;
+                        *engine_lock = engine;
+                        *context_lock = outcome.as_ref().and_then(|o| o.context.clone());
+                        *filtered_lock = outcome.map(|o| o.visible);
+
+                        let line_count = replacement.line_count();
+                        let visible_count = filtered_lock.as_ref().map_or(line_count, |o| o.len());
+                        *indexer_lock = Some(replacement);
+
+                        let _ = app.emit(
+                            "file-reindexed",
+                            FileReindexed {
+                                document_id,
+                                line_count,
+                                visible_count,
+                            },
+                        );
+                    }
+                }
+            }
+        }));
+    }
+
+    /// Whether a follow task is polling the file.
+    pub fn is_running(&self) -> bool {
+        self.task.as_ref().is_some_and(|task| !task.is_finished())
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// Extends the filtered view with lines from `first_changed_line` on and
+/// returns them, starting at the view position they now occupy.
+fn append_lines(
+    document_id: DocumentId,
+    indexer: &Indexer,
+    engine: Option<&FilterEngine>,
+    filtered: Option<&mut Vec<usize>>,
+    context: Option<&mut Vec<bool>>,
+    first_changed_line: usize,
+) -> FollowLines {
+    match (engine, filtered) {
+        (Some(engine), Some(filtered)) => {
+            // The old last line may have grown, so drop it and re-evaluate,
+            // along with the lines a new match could pull in as context.
+            let redo_from = first_changed_line.saturating_sub(engine.max_context().0);
+            if let Some(changed_offset) = indexer.line_offset(redo_from) {
+                let keep = filtered.partition_point(|&o| o < changed_offset);
+                filtered.truncate(keep);
+            }
+
+            let first_index = filtered.len();
+            let tail = indexer.apply_filters_from(engine, redo_from);
+            filtered.extend(tail.visible);
+            if let Some(context) = context {
+                context.truncate(first_index);
+                context.extend(tail.context.unwrap_or_default());
+            }
+
+            let lines = (first_index..filtered.len())
+                .filter_map(|i| indexer.get_line_from_offsets(filtered, i))
+                .collect();
+
+            FollowLines {
+                document_id,
+                first_index,
+                lines,
+                line_count: indexer.line_count(),
+            }
+        }
+        _ => FollowLines {
+            document_id,
+            first_index: first_changed_line,
+            lines: (first_changed_line..indexer.line_count())
+                .filter_map(|i| indexer.get_line(i))
+                .collect(),
+            line_count: indexer.line_count(),
+        },
+    }
+}
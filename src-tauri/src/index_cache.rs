@@ -0,0 +1,351 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8; 8] = b"LAIDX\0\0\0";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 8 + 4 + 8 + 8 + 4 + 8 + 8;
+/// Bytes sampled from each end of the file for the content fingerprint.
+const FINGERPRINT_SAMPLE: usize = 64 * 1024;
+/// Total size the cache directory may grow to before the least recently
+/// used cache files are removed.
+const MAX_CACHE_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Identity of an indexed file. A cached index is only reused when every
+/// field matches the file currently on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub mtime_secs: u64,
+    pub mtime_nanos: u32,
+    pub fingerprint: u64,
+}
+
+impl FileStamp {
+    pub fn new(data: &[u8], modified: Option<SystemTime>) -> Self {
+        let mtime = modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+
+        Self {
+            size: data.len() as u64,
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+            fingerprint: fingerprint(data),
+        }
+    }
+}
+
+/// Hashes the head and tail of the file so that in-place rewrites which keep
+/// size and mtime intact are still detected.
+fn fingerprint(data: &[u8]) -> u64 {
+    let head = &data[..data.len().min(FINGERPRINT_SAMPLE)];
+    let tail = &data[data.len().saturating_sub(FINGERPRINT_SAMPLE)..];
+    let hash = fnv1a(FNV_OFFSET, &(data.len() as u64).to_le_bytes());
+    fnv1a(fnv1a(hash, head), tail)
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Location of the cache file for `source` inside `cache_dir`. The name is
+/// derived from the absolute path, and the archive member for entries
+/// opened from a zip, so different files never collide.
+pub fn cache_path(cache_dir: &Path, source: &Path, entry: Option<&str>) -> PathBuf {
+    let absolute = fs::canonicalize(source).unwrap_or_else(|_| source.to_path_buf());
+    let mut key = fnv1a(FNV_OFFSET, absolute.to_string_lossy().as_bytes());
+    if let Some(entry) = entry {
+        key = fnv1a(fnv1a(key, b"!"), entry.as_bytes());
+    }
+    let name = source
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    cache_dir.join(format!("{}-{:016x}.lidx", name, key))
+}
+
+/// Loads cached line offsets. Returns `None` when the cache is missing,
+/// belongs to a different version of the file, or fails validation. A hit
+/// bumps the cache file's mtime, which `prune` treats as its last use.
+pub fn load(cache_file: &Path, stamp: &FileStamp) -> Option<Vec<usize>> {
+    let mut file = File::open(cache_file).ok()?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).ok()?;
+
+    if bytes.len() < HEADER_LEN + 8 || &bytes[..8] != MAGIC {
+        return None;
+    }
+
+    let mut pos = 8;
+    let mut take = |n: usize| {
+        let slice = &bytes[pos..pos + n];
+        pos += n;
+        slice
+    };
+    let version = u32::from_le_bytes(take(4).try_into().ok()?);
+    let cached = FileStamp {
+        size: u64::from_le_bytes(take(8).try_into().ok()?),
+        mtime_secs: u64::from_le_bytes(take(8).try_into().ok()?),
+        mtime_nanos: u32::from_le_bytes(take(4).try_into().ok()?),
+        fingerprint: u64::from_le_bytes(take(8).try_into().ok()?),
+    };
+    let count = u64::from_le_bytes(take(8).try_into().ok()?) as usize;
+
+    if version != VERSION || cached != *stamp {
+        return None;
+    }
+
+    let body = &bytes[HEADER_LEN..bytes.len() - 8];
+    if body.len() != count.checked_mul(8)? {
+        return None;
+    }
+
+    let checksum = u64::from_le_bytes(bytes[bytes.len() - 8..].try_into().ok()?);
+    if fnv1a(FNV_OFFSET, body) != checksum {
+        return None;
+    }
+
+    let offsets: Vec<usize> = body
+        .chunks_exact(8)
+        .map(|c| u64::from_le_bytes(c.try_into().unwrap()) as usize)
+        .collect();
+
+    // An index must start at zero, increase strictly and stay inside the file.
+    let valid = match offsets.first() {
+        None => stamp.size == 0,
+        Some(&first) => {
+            first == 0
+                && offsets.windows(2).all(|w| w[0] < w[1])
+                && (*offsets.last().unwrap() as u64) < stamp.size
+        }
+    };
+
+    if valid {
+        let _ = file.set_modified(SystemTime::now());
+    }
+    valid.then_some(offsets)
+}
+
+/// Writes line offsets to the cache. The file is written under a temporary
+/// name and renamed so a crash never leaves a truncated cache behind. The
+/// directory is then pruned back under `MAX_CACHE_BYTES`.
+pub fn store(cache_file: &Path, stamp: &FileStamp, offsets: &[usize]) -> Result<(), String> {
+    store_within(cache_file, stamp, offsets, MAX_CACHE_BYTES)
+}
+
+fn store_within(
+    cache_file: &Path,
+    stamp: &FileStamp,
+    offsets: &[usize],
+    max_bytes: u64,
+) -> Result<(), String> {
+    if let Some(dir) = cache_file.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create cache dir: {}", e))?;
+    }
+
+    let tmp = cache_file.with_extension("lidx.tmp");
+    let write = || -> std::io::Result<()> {
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&stamp.size.to_le_bytes())?;
+        out.write_all(&stamp.mtime_secs.to_le_bytes())?;
+        out.write_all(&stamp.mtime_nanos.to_le_bytes())?;
+        out.write_all(&stamp.fingerprint.to_le_bytes())?;
+        out.write_all(&(offsets.len() as u64).to_le_bytes())?;
+
+        let mut checksum = FNV_OFFSET;
+        for &offset in offsets {
+            let bytes = (offset as u64).to_le_bytes();
+            checksum = fnv1a(checksum, &bytes);
+            out.write_all(&bytes)?;
+        }
+        out.write_all(&checksum.to_le_bytes())?;
+        out.flush()
+    };
+
+    write()
+        .and_then(|_| fs::rename(&tmp, cache_file))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("Failed to write index cache: {}", e)
+        })?;
+
+    if let Some(dir) = cache_file.parent() {
+        prune(dir, cache_file, max_bytes);
+    }
+    Ok(())
+}
+
+/// Removes the least recently used cache files in `dir` until the rest fit
+/// in `max_bytes`. `keep`, the file just written, is never removed. Errors
+/// are ignored: a cache file left behind only costs disk space.
+fn prune(dir: &Path, keep: &Path, max_bytes: u64) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+        .filter_map(Result::ok)
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "lidx"))
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            Some((meta.modified().ok()?, meta.len(), e.path()))
+        })
+        .collect();
+
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    // Oldest first.
+    files.sort();
+    for (_, len, path) in files {
+        if total <= max_bytes {
+            break;
+        }
+        if path != keep && fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn sample() -> (Vec<u8>, Vec<usize>) {
+        let data: Vec<u8> = (0..20_000)
+            .flat_map(|i| format!("line {:05}\n", i).into_bytes())
+            .collect();
+        let offsets = (0..20_000).map(|i| i * 11).collect();
+        (data, offsets)
+    }
+
+    fn modified() -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789))
+    }
+
+    #[test]
+    fn round_trips_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache").join("log.lidx");
+        let (data, offsets) = sample();
+        let stamp = FileStamp::new(&data, modified());
+
+        store(&cache_file, &stamp, &offsets).unwrap();
+        assert_eq!(load(&cache_file, &stamp), Some(offsets));
+        assert!(!cache_file.with_extension("lidx.tmp").exists());
+
+        let empty = FileStamp::new(b"", modified());
+        store(&cache_file, &empty, &[]).unwrap();
+        assert_eq!(load(&cache_file, &empty), Some(Vec::new()));
+    }
+
+    #[test]
+    fn rejects_a_changed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("log.lidx");
+        let (data, offsets) = sample();
+        let stamp = FileStamp::new(&data, modified());
+        store(&cache_file, &stamp, &offsets).unwrap();
+
+        let mut head = data.clone();
+        head[3] = b'X';
+        let mut tail = data.clone();
+        tail[data.len() - 3] = b'X';
+        let mut longer = data.clone();
+        longer.extend_from_slice(b"line 20000\n");
+        let later = modified().map(|t| t + Duration::from_nanos(1));
+
+        for changed in [
+            FileStamp::new(&head, modified()),
+            FileStamp::new(&tail, modified()),
+            FileStamp::new(&longer, modified()),
+            FileStamp::new(&data[..data.len() - 11], modified()),
+            FileStamp::new(&data, later),
+        ] {
+            assert_ne!(changed, stamp);
+            assert_eq!(load(&cache_file, &changed), None);
+        }
+        assert_eq!(load(&cache_file, &stamp), Some(offsets));
+    }
+
+    #[test]
+    fn rejects_damaged_cache_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("log.lidx");
+        let (data, offsets) = sample();
+        let stamp = FileStamp::new(&data, modified());
+        store(&cache_file, &stamp, &offsets).unwrap();
+        let bytes = fs::read(&cache_file).unwrap();
+
+        let damaged = |bytes: &[u8]| {
+            fs::write(&cache_file, bytes).unwrap();
+            load(&cache_file, &stamp)
+        };
+        assert_eq!(damaged(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(damaged(&bytes[..bytes.len() - 8]), None);
+        assert_eq!(damaged(&bytes[..HEADER_LEN]), None);
+        assert_eq!(damaged(&[]), None);
+
+        let mut body = bytes.clone();
+        body[HEADER_LEN + 100] ^= 1;
+        assert_eq!(damaged(&body), None);
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert_eq!(damaged(&magic), None);
+
+        let mut version = bytes.clone();
+        version[8] = 99;
+        assert_eq!(damaged(&version), None);
+
+        // Offsets that do not fit the file are rejected even with a valid
+        // checksum.
+        let mut unordered = offsets.clone();
+        unordered.swap(1, 2);
+        store(&cache_file, &stamp, &unordered).unwrap();
+        assert_eq!(load(&cache_file, &stamp), None);
+
+        assert_eq!(damaged(&bytes), Some(offsets));
+        fs::remove_file(&cache_file).unwrap();
+        assert_eq!(load(&cache_file, &stamp), None);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (data, offsets) = sample();
+        let stamp = FileStamp::new(&data, modified());
+        let files: Vec<PathBuf> = (0..4)
+            .map(|i| dir.path().join(format!("log{}.lidx", i)))
+            .collect();
+        for (i, file) in files[..3].iter().enumerate() {
+            store(file, &stamp, &offsets).unwrap();
+            let used = UNIX_EPOCH + Duration::from_secs(1_700_000_000 + i as u64);
+            File::open(file).unwrap().set_modified(used).unwrap();
+        }
+        let other = dir.path().join("notes.txt");
+        fs::write(&other, b"not a cache file").unwrap();
+        let size = fs::metadata(&files[0]).unwrap().len();
+
+        // Loading the oldest file makes it the most recently used.
+        assert_eq!(load(&files[0], &stamp), Some(offsets.clone()));
+        store_within(&files[3], &stamp, &offsets, size * 3).unwrap();
+        let exists: Vec<bool> = files.iter().map(|f| f.exists()).collect();
+        assert_eq!(exists, [true, false, true, true]);
+
+        // The file just written is kept even when it alone is over the cap.
+        store_within(&files[1], &stamp, &offsets, 0).unwrap();
+        let exists: Vec<bool> = files.iter().map(|f| f.exists()).collect();
+        assert_eq!(exists, [false, true, false, false]);
+        assert!(other.exists());
+    }
+}
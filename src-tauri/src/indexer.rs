@@ -1,15 +1,51 @@
-use crate::filter::FilterEngine;
+use crate::archive;
+use crate::decompress::{self, Compression};
+use crate::filter::{FilterEngine, FilterOutcome, FilterRun};
+use crate::index_cache::{self, FileStamp};
 use memmap2::Mmap;
 use rayon::prelude::*;
 use serde::Serialize;
 use std::fs::File;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Size of the slices handed to each rayon worker while scanning for newlines.
+const SCAN_CHUNK_SIZE: usize = 4 * 1024 * 1024;
 
 pub struct Indexer {
     path: PathBuf,
     mmap: Arc<Mmap>,
     offsets: Vec<usize>,
+    modified: Option<SystemTime>,
+    file_id: Option<(u64, u64)>,
+    cache_dir: Option<PathBuf>,
+    /// For compressed files and archive entries, the temporary file holding
+    /// the extracted data that `mmap` points into. Kept open for the
+    /// lifetime of the map.
+    extracted: Option<(Extracted, File)>,
+}
+
+/// Where the indexed bytes came from when they are not the file at `path`
+/// itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Extracted {
+    Decompressed(Compression),
+    ZipEntry(String),
+}
+
+/// How the file on disk changed since it was last indexed or refreshed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileChange {
+    Unchanged,
+    /// Bytes were appended. Lines from `first_changed_line` on are new, or
+    /// were extended because the previous last line had no trailing newline.
+    Appended {
+        first_changed_line: usize,
+    },
+    /// The file shrank or the path now points at a different file, so the
+    /// existing offsets are meaningless and the file must be re-indexed.
+    Replaced,
 }
 
 #[derive(Serialize, Clone)]
@@ -22,65 +58,233 @@ pub struct MinimapBucket {
 impl Indexer {
     pub fn new(path: PathBuf) -> Result<Self, String> {
         let file = File::open(&path).map_err(|e| format!("Failed to open file: {}", e))?;
+        let metadata = file
+            .metadata()
+            .map_err(|e| format!("Failed to read file metadata: {}", e))?;
+        let mmap = unsafe { Mmap::map(&file).map_err(|e| format!("Failed to map file: {}", e))? };
+
+        Ok(Self {
+            path,
+            mmap: Arc::new(mmap),
+            offsets: Vec::new(),
+            modified: metadata.modified().ok(),
+            file_id: file_id(&metadata),
+            cache_dir: None,
+            extracted: None,
+        })
+    }
+
+    /// Opens `path`, transparently extracting zip archives and decompressing
+    /// gzip, zstd, xz and bzip2 input into a temporary file first. For zip
+    /// archives `entry` selects the member to open, defaulting to the main
+    /// bugreport text. Extraction progress is reported through
+    /// `progress_callback`; plain files are mapped directly.
+    pub async fn open<F>(
+        path: PathBuf,
+        entry: Option<&str>,
+        mut progress_callback: F,
+    ) -> Result<Self, String>
+    where
+        F: FnMut(f64),
+    {
+        let (file, extracted) = if archive::is_zip(&path)? {
+            let (file, name) =
+                archive::extract_to_temp(&path, entry, &mut progress_callback).await?;
+            (file, Extracted::ZipEntry(name))
+        } else if let Some(compression) = Compression::sniff(&path)? {
+            let file =
+                decompress::decompress_to_temp(&path, compression, &mut progress_callback).await?;
+            (file, Extracted::Decompressed(compression))
+        } else {
+            return Self::new(path);
+        };
+
+        let metadata =
+            std::fs::metadata(&path).map_err(|e| format!("Failed to read file metadata: {}", e))?;
         let mmap = unsafe { Mmap::map(&file).map_err(|e| format!("Failed to map file: {}", e))? };
 
         Ok(Self {
             path,
             mmap: Arc::new(mmap),
             offsets: Vec::new(),
+            modified: metadata.modified().ok(),
+            file_id: file_id(&metadata),
+            cache_dir: None,
+            extracted: Some((extracted, file)),
         })
     }
 
+    /// How the indexed data was extracted from the opened file, if at all.
+    pub fn extracted(&self) -> Option<&Extracted> {
+        self.extracted.as_ref().map(|(e, _)| e)
+    }
+
+    /// Creates a fresh, unindexed `Indexer` for the same path and cache
+    /// settings. Used when the file has been replaced underneath us.
+    pub fn reopen(&self) -> Result<Self, String> {
+        let indexer = Self::new(self.path.clone())?;
+        Ok(match &self.cache_dir {
+            Some(dir) => indexer.with_cache_dir(dir.clone()),
+            None => indexer,
+        })
+    }
+
+    /// Enables the on-disk line index cache. `index` will reuse offsets
+    /// stored in `dir` when the file is unchanged and save them otherwise.
+    pub fn with_cache_dir(mut self, dir: PathBuf) -> Self {
+        self.cache_dir = Some(dir);
+        self
+    }
+
     pub async fn index<F>(&mut self, mut progress_callback: F)
     where
         F: FnMut(f64),
     {
-        let data = &self.mmap[..];
-        let mut offsets = Vec::new();
-        let total_size = data.len();
-
-        if total_size == 0 {
-            self.offsets = offsets;
-            progress_callback(1.0);
-            return;
+        let cache = self.cache_dir.as_ref().map(|dir| {
+            (
+                index_cache::cache_path(dir, &self.path, self.archive_entry()),
+                FileStamp::new(&self.mmap, self.modified),
+            )
+        });
+
+        if let Some((cache_file, stamp)) = &cache {
+            if let Some(offsets) = index_cache::load(cache_file, stamp) {
+                self.offsets = offsets;
+                progress_callback(1.0);
+                return;
+            }
         }
 
-        offsets.push(0);
-        let chunk_size = 1_048_576;
-        let mut last_reported_pos = 0;
+        self.scan(&mut progress_callback).await;
 
-        for (i, &byte) in data.iter().enumerate() {
-            if byte == b'\n' {
-                if i + 1 < total_size {
-                    offsets.push(i + 1);
-                }
-            }
+        if let Some((cache_file, stamp)) = &cache {
+            // The cache is only an accelerator; failing to write it is not fatal.
+            let _ = index_cache::store(cache_file, stamp, &self.offsets);
+        }
+    }
 
-            if i - last_reported_pos >= chunk_size {
-                progress_callback(i as f64 / total_size as f64);
-                last_reported_pos = i;
-                tokio::task::yield_now().await;
-            }
+    async fn scan<F>(&mut self, progress_callback: &mut F)
+    where
+        F: FnMut(f64),
+    {
+        self.offsets = scan_offsets(&self.mmap, SCAN_CHUNK_SIZE, progress_callback).await;
+    }
+
+    /// Picks up changes to the file since it was indexed. Appended bytes are
+    /// mapped and scanned without touching the existing offsets; truncation
+    /// or rotation is reported as `FileChange::Replaced`.
+    pub fn refresh(&mut self) -> Result<FileChange, String> {
+        if self.extracted.is_some() {
+            return Err(
+                "Follow mode is not supported for compressed files or archives".to_string(),
+            );
         }
 
-        self.offsets = offsets;
-        progress_callback(1.0);
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            // A rotated file may not have been recreated yet.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(FileChange::Unchanged),
+            Err(e) => return Err(format!("Failed to open file: {}", e)),
+        };
+        let metadata = file
+            .metadata()
+            .map_err(|e| format!("Failed to read file metadata: {}", e))?;
+
+        let old_len = self.mmap.len();
+        let new_len = metadata.len() as usize;
+
+        if file_id(&metadata) != self.file_id || new_len < old_len {
+            return Ok(FileChange::Replaced);
+        }
+        if new_len == old_len {
+            return Ok(FileChange::Unchanged);
+        }
+
+        let mmap = unsafe { Mmap::map(&file).map_err(|e| format!("Failed to map file: {}", e))? };
+        let new_len = mmap.len();
+
+        let first_changed_line = if old_len == 0 {
+            self.offsets.push(0);
+            0
+        } else if mmap[old_len - 1] == b'\n' {
+            // The old trailing newline now starts a line.
+            self.offsets.push(old_len);
+            self.offsets.len() - 1
+        } else {
+            self.offsets.len() - 1
+        };
+
+        self.offsets.extend(
+            memchr::memchr_iter(b'\n', &mmap[old_len..])
+                .map(|pos| old_len + pos + 1)
+                .filter(|&offset| offset < new_len),
+        );
+
+        self.mmap = Arc::new(mmap);
+        self.modified = metadata.modified().ok();
+
+        Ok(FileChange::Appended { first_changed_line })
     }
 
     /// Filters the offsets based on a FilterEngine.
-    /// Returns a new vector of offsets that pass the filters.
-    pub fn apply_filters(&self, engine: &FilterEngine) -> Vec<usize> {
-        self.offsets
-            .par_iter()
-            .filter_map(|&offset| {
-                let line_data = self.get_line_at_offset(offset);
-                if engine.matches(&line_data) {
-                    Some(offset)
-                } else {
-                    None
-                }
-            })
-            .collect()
+    /// Returns the offsets that pass the filters along with per-filter match
+    /// counts over them, both gathered in the same pass.
+    pub fn apply_filters(&self, engine: &FilterEngine) -> FilterOutcome {
+        self.apply_filters_from(engine, 0)
+    }
+
+    /// Like `apply_filters`, but only returns lines from `first_line` on.
+    pub fn apply_filters_from(&self, engine: &FilterEngine, first_line: usize) -> FilterOutcome {
+        self.run_filters_from(engine, first_line, &FilterRun::default())
+            .unwrap_or_default()
+    }
+
+    /// Like `apply_filters`, but returns `None` if `run` is cancelled first.
+    pub fn run_filters(&self, engine: &FilterEngine, run: &FilterRun) -> Option<FilterOutcome> {
+        self.run_filters_from(engine, 0, run)
+    }
+
+    /// Filters only the lines starting at `offsets`, typically a previous
+    /// result that the engine narrows.
+    pub fn run_filters_within(
+        &self,
+        engine: &FilterEngine,
+        offsets: &[usize],
+        run: &FilterRun,
+    ) -> Option<FilterOutcome> {
+        let mut outcome = engine.run_filter(
+            0..offsets.len(),
+            |i| self.get_line_at_offset(offsets[i]),
+            run,
+        )?;
+        for visible in &mut outcome.visible {
+            *visible = offsets[*visible];
+        }
+        Some(outcome)
+    }
+
+    fn run_filters_from(
+        &self,
+        engine: &FilterEngine,
+        first_line: usize,
+        run: &FilterRun,
+    ) -> Option<FilterOutcome> {
+        let first_line = std::cmp::min(first_line, self.offsets.len());
+
+        let mut outcome = engine.run_filter(
+            first_line..self.offsets.len(),
+            |i| self.get_line_at_offset(self.offsets[i]),
+            run,
+        )?;
+        for visible in &mut outcome.visible {
+            *visible = self.offsets[*visible];
+        }
+        Some(outcome)
+    }
+
+    /// Index of the line starting at `offset`.
+    pub fn line_index_of(&self, offset: usize) -> Option<usize> {
+        self.offsets.binary_search(&offset).ok()
     }
 
     pub fn get_line(&self, index: usize) -> Option<String> {
@@ -122,9 +326,18 @@ impl Indexer {
         String::from_utf8_lossy(line_data).into_owned()
     }
 
+    /// Raw bytes of line `index`, without the line terminator.
+    pub fn line_bytes(&self, index: usize) -> Option<&[u8]> {
+        let start = *self.offsets.get(index)?;
+        let end = self.find_newline(start);
+        let line = &self.mmap[start..end];
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        Some(line.strip_suffix(b"\r").unwrap_or(line))
+    }
+
     fn find_newline(&self, start: usize) -> usize {
         let data = &self.mmap[start..];
-        match data.iter().position(|&b| b == b'\n') {
+        match memchr::memchr(b'\n', data) {
             Some(pos) => start + pos + 1,
             None => self.mmap.len(),
         }
@@ -142,52 +355,306 @@ impl Indexer {
     ) -> Vec<MinimapBucket> {
         let active_offsets = custom_offsets.unwrap_or(&self.offsets);
 
-        if active_offsets.is_empty() || buckets == 0 {
-            return Vec::new();
+        minimap_buckets(active_offsets.len(), buckets, |i| {
+            let line_data = self.get_line_at_offset(active_offsets[i]);
+            engine.get_match_color(&line_data)
+        })
+    }
+
+    pub fn file_path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    fn archive_entry(&self) -> Option<&str> {
+        match self.extracted() {
+            Some(Extracted::ZipEntry(name)) => Some(name),
+            _ => None,
         }
+    }
 
-        let total_lines = active_offsets.len();
-        let bucket_size = (total_lines as f64 / buckets as f64).max(1.0);
-
-        (0..buckets)
-            .into_par_iter()
-            .map(|b| {
-                let start_idx = (b as f64 * bucket_size) as usize;
-                let end_idx = ((b + 1) as f64 * bucket_size) as usize;
-                let end_idx = std::cmp::min(end_idx, total_lines);
-
-                if start_idx >= end_idx {
-                    return MinimapBucket {
-                        intensity: 0.0,
-                        color: None,
-                        count: 0,
-                    };
-                }
+    /// Byte offset at which line `index` starts.
+    pub fn line_offset(&self, index: usize) -> Option<usize> {
+        self.offsets.get(index).copied()
+    }
+}
+
+/// Finds the offset at which each line of `data` starts, scanning slices of
+/// `chunk_size` bytes in parallel.
+async fn scan_offsets<F>(data: &[u8], chunk_size: usize, progress_callback: &mut F) -> Vec<usize>
+where
+    F: FnMut(f64),
+{
+    let mut offsets = Vec::new();
+    let total_size = data.len();
 
-                let mut matched_count = 0;
-                let mut first_color = None;
-
-                for i in start_idx..end_idx {
-                    let offset = active_offsets[i];
-                    let line_data = self.get_line_at_offset(offset);
-                    if let Some(color) = engine.get_match_color(&line_data) {
-                        matched_count += 1;
-                        if first_color.is_none() {
-                            first_color = Some(color);
-                        }
+    if total_size == 0 {
+        progress_callback(1.0);
+        return offsets;
+    }
+
+    // Scan a batch of chunks in parallel, then stitch their offsets back
+    // together in order. Batching keeps progress reports and yields to the
+    // runtime flowing while rayon saturates the cores within each batch.
+    offsets.push(0);
+    let batch_size = chunk_size * rayon::current_num_threads().max(1);
+    let mut batch_start = 0;
+
+    while batch_start < total_size {
+        let batch_end = std::cmp::min(batch_start + batch_size, total_size);
+
+        let chunk_offsets: Vec<Vec<usize>> = data[batch_start..batch_end]
+            .par_chunks(chunk_size)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let base = batch_start + i * chunk_size;
+                memchr::memchr_iter(b'\n', chunk)
+                    .map(|pos| base + pos + 1)
+                    .collect()
+            })
+            .collect();
+
+        for chunk in chunk_offsets {
+            offsets.extend(chunk);
+        }
+
+        batch_start = batch_end;
+        progress_callback(batch_start as f64 / total_size as f64);
+        tokio::task::yield_now().await;
+    }
+
+    // A trailing newline does not start another line.
+    if offsets.last() == Some(&total_size) {
+        offsets.pop();
+    }
+
+    progress_callback(1.0);
+    offsets
+}
+
+/// Splits `total_lines` lines into `buckets` ranges and summarises each one
+/// for the minimap. `color_at` returns the match colour of a line, or `None`
+/// if the line does not match.
+pub fn minimap_buckets<F>(total_lines: usize, buckets: usize, color_at: F) -> Vec<MinimapBucket>
+where
+    F: Fn(usize) -> Option<String> + Sync,
+{
+    if total_lines == 0 || buckets == 0 {
+        return Vec::new();
+    }
+
+    let bucket_size = (total_lines as f64 / buckets as f64).max(1.0);
+
+    (0..buckets)
+        .into_par_iter()
+        .map(|b| {
+            let start_idx = (b as f64 * bucket_size) as usize;
+            let end_idx = ((b + 1) as f64 * bucket_size) as usize;
+            let end_idx = std::cmp::min(end_idx, total_lines);
+
+            if start_idx >= end_idx {
+                return MinimapBucket {
+                    intensity: 0.0,
+                    color: None,
+                    count: 0,
+                };
+            }
+
+            let mut matched_count = 0;
+            let mut first_color = None;
+
+            for i in start_idx..end_idx {
+                if let Some(color) = color_at(i) {
+                    matched_count += 1;
+                    if first_color.is_none() {
+                        first_color = Some(color);
                     }
                 }
+            }
 
-                MinimapBucket {
-                    intensity: matched_count as f32 / (end_idx - start_idx) as f32,
-                    color: first_color,
-                    count: matched_count,
-                }
-            })
+            MinimapBucket {
+                intensity: matched_count as f32 / (end_idx - start_idx) as f32,
+                color: first_color,
+                count: matched_count,
+            }
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::Path;
+
+    fn append(path: &Path, data: &str) {
+        let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+    }
+
+    async fn indexed(path: &Path) -> Indexer {
+        let mut indexer = Indexer::new(path.to_path_buf()).unwrap();
+        indexer.index(|_| {}).await;
+        indexer
+    }
+
+    /// Line start offsets found one newline at a time.
+    fn sequential_offsets(data: &[u8]) -> Vec<usize> {
+        if data.is_empty() {
+            return Vec::new();
+        }
+        let starts = memchr::memchr_iter(b'\n', data).map(|pos| pos + 1);
+        std::iter::once(0)
+            .chain(starts)
+            .filter(|&offset| offset < data.len())
             .collect()
     }
 
-    pub fn file_path(&self) -> &PathBuf {
-        &self.path
+    #[tokio::test]
+    async fn scan_joins_offsets_across_chunks() {
+        let inputs: [&[u8]; 8] = [
+            b"",
+            b"\n",
+            b"\n\n\n",
+            b"no newline",
+            b"a\nbb\nccc\ndddd\n",
+            b"a\nbb\nccc\ndddd",
+            b"\nstarts with a newline\n\nand has a blank line",
+            b"x\ny\r\nz\r\n\r\n",
+        ];
+        for data in inputs {
+            // Small chunks put newlines at the first and last byte of chunks
+            // and of batches.
+            for chunk_size in [1, 2, 3, 4, 5, 7, 64] {
+                let mut reported = Vec::new();
+                let offsets = scan_offsets(data, chunk_size, &mut |p| reported.push(p)).await;
+                assert_eq!(
+                    offsets,
+                    sequential_offsets(data),
+                    "{:?} in chunks of {}",
+                    String::from_utf8_lossy(data),
+                    chunk_size
+                );
+                assert_eq!(reported.last(), Some(&1.0));
+            }
+        }
+    }
+
+    fn lines(indexer: &Indexer) -> Vec<String> {
+        (0..indexer.line_count())
+            .filter_map(|i| indexer.get_line(i))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn refresh_indexes_appended_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txt");
+        std::fs::write(&path, "").unwrap();
+        let mut indexer = indexed(&path).await;
+        assert!(matches!(indexer.refresh(), Ok(FileChange::Unchanged)));
+
+        append(&path, "a\nb\n");
+        assert!(matches!(
+            indexer.refresh(),
+            Ok(FileChange::Appended {
+                first_changed_line: 0
+            })
+        ));
+        assert_eq!(indexer.offsets, vec![0, 2]);
+
+        append(&path, "c\n");
+        assert!(matches!(
+            indexer.refresh(),
+            Ok(FileChange::Appended {
+                first_changed_line: 2
+            })
+        ));
+        assert_eq!(indexer.offsets, vec![0, 2, 4]);
+        assert_eq!(lines(&indexer), ["a", "b", "c"]);
+        assert!(matches!(indexer.refresh(), Ok(FileChange::Unchanged)));
+    }
+
+    #[tokio::test]
+    async fn refresh_extends_a_partial_last_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txt");
+        std::fs::write(&path, "a\nb").unwrap();
+        let mut indexer = indexed(&path).await;
+        assert_eq!(indexer.offsets, vec![0, 2]);
+
+        append(&path, "c\nd");
+        assert!(matches!(
+            indexer.refresh(),
+            Ok(FileChange::Appended {
+                first_changed_line: 1
+            })
+        ));
+        assert_eq!(indexer.offsets, vec![0, 2, 5]);
+        assert_eq!(lines(&indexer), ["a", "bc", "d"]);
+
+        append(&path, "\n");
+        assert!(matches!(
+            indexer.refresh(),
+            Ok(FileChange::Appended {
+                first_changed_line: 2
+            })
+        ));
+        assert_eq!(indexer.offsets, vec![0, 2, 5]);
+        assert_eq!(lines(&indexer), ["a", "bc", "d"]);
+    }
+
+    #[tokio::test]
+    async fn refresh_detects_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txt");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+        let mut indexer = indexed(&path).await;
+
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        append(&path, "new\n");
+        assert!(matches!(indexer.refresh(), Ok(FileChange::Replaced)));
+
+        let mut replacement = indexer.reopen().unwrap();
+        replacement.index(|_| {}).await;
+        assert_eq!(replacement.offsets, vec![0]);
+        assert_eq!(lines(&replacement), ["new"]);
+    }
+
+    #[tokio::test]
+    async fn refresh_detects_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txt");
+        std::fs::write(&path, "old\n").unwrap();
+        let mut indexer = indexed(&path).await;
+
+        // Until the file is recreated there is nothing new to show.
+        std::fs::rename(&path, dir.path().join("log.txt.1")).unwrap();
+        assert!(matches!(indexer.refresh(), Ok(FileChange::Unchanged)));
+
+        // A longer file at the same path is still a different file.
+        std::fs::write(&path, "rotated\nfile\n").unwrap();
+        assert!(matches!(indexer.refresh(), Ok(FileChange::Replaced)));
+        assert_eq!(lines(&indexer), ["old"]);
+
+        let mut replacement = indexer.reopen().unwrap();
+        replacement.index(|_| {}).await;
+        assert_eq!(replacement.offsets, vec![0, 8]);
+        assert_eq!(lines(&replacement), ["rotated", "file"]);
     }
 }
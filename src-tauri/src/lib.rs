@@ -1,153 +1,694 @@
 pub mod adb;
+pub mod adb_client;
+pub mod archive;
+pub mod capture;
+pub mod decompress;
+pub mod document;
+pub mod event_tags;
 pub mod filter;
+pub mod follow;
+pub mod index_cache;
 pub mod indexer;
+pub mod logcat;
+pub mod logcat_binary;
+pub mod merge;
+pub mod processes;
+pub mod query;
+pub mod timestamp;
 
-use crate::adb::AdbManager;
-use crate::filter::{FilterEngine, LogFilter};
+use crate::capture::CaptureBuffer;
+use crate::document::{Document, DocumentId, DocumentInfo, DocumentKind, Documents};
+use crate::event_tags::EventTags;
+use crate::filter::{CancelToken, ContextLines, FilterEngine, FilterRun, HighlightSpan, LogFilter};
+use crate::logcat::{LogLevel, LogLine};
+use crate::merge::MergedView;
+use crate::processes::ProcessMap;
+use crate::query::QueryError;
 use rayon::prelude::*;
 use serde::Serialize;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tauri::{AppHandle, Emitter, State};
+use tauri::{AppHandle, Emitter, Manager, Runtime, State};
 use tokio::sync::Mutex;
 
 pub struct AppState {
-    pub indexer: Arc<Mutex<Option<indexer::Indexer>>>,
-    pub filtered_offsets: Arc<Mutex<Option<Vec<usize>>>>,
-    pub adb_manager: Arc<Mutex<AdbManager>>,
-    pub adb_buffer: Arc<Mutex<Vec<String>>>,
-    pub is_adb_active: Arc<Mutex<bool>>,
+    pub documents: Arc<Mutex<Documents>>,
+    /// Tag definitions for the events buffer, from `load_event_tags`.
+    pub event_tags: Arc<Mutex<Arc<EventTags>>>,
 }
 
 #[derive(Clone, Serialize)]
 struct IndexProgress {
+    document_id: DocumentId,
     progress: f64,
 }
 
+#[derive(Clone, Serialize)]
+struct OpenedDocument {
+    document_id: DocumentId,
+    line_count: usize,
+}
+
+#[derive(Clone, Serialize)]
+struct MergedInfo {
+    document_id: DocumentId,
+    line_count: usize,
+    sources: Vec<String>,
+}
+
 #[derive(Clone, Serialize)]
 struct FilterResult {
     visible_count: usize,
+    /// Visible lines that matched, as opposed to context around them.
+    match_count: usize,
     filter_counts: std::collections::HashMap<String, usize>,
 }
 
 #[tauri::command]
 async fn open_file(
     path: String,
+    entry: Option<String>,
     app: AppHandle,
     state: State<'_, AppState>,
-) -> Result<usize, String> {
-    // Stop ADB if active when opening a file
-    let mut adb_active = state.is_adb_active.lock().await;
-    if *adb_active {
-        let mut adb_manager = state.adb_manager.lock().await;
-        adb_manager.stop();
-        *adb_active = false;
+) -> Result<OpenedDocument, String> {
+    let document_id = state.documents.lock().await.reserve_id();
+    let report = |p: f64| {
+        let _ = app.emit(
+            "indexing-progress",
+            IndexProgress {
+                document_id,
+                progress: p,
+            },
+        );
+    };
+
+    let path = PathBuf::from(path);
+    let cache_dir = app
+        .path()
+        .app_cache_dir()
+        .ok()
+        .map(|dir| dir.join("line-index"));
+    let indexer = open_indexer(path.clone(), entry.as_deref(), cache_dir, report).await?;
+
+    let line_count = indexer.line_count();
+    let mut title = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if let Some(indexer::Extracted::ZipEntry(name)) = indexer.extracted() {
+        title = format!("{}/{}", title, name);
+    }
+
+    state
+        .documents
+        .lock()
+        .await
+        .insert(document_id, Document::file(title, indexer));
+
+    Ok(OpenedDocument {
+        document_id,
+        line_count,
+    })
+}
+
+/// Opens and indexes `path`, reporting progress from 0 to 1 across both.
+/// Extracting an archive or decompressing the file takes the first half.
+async fn open_indexer<F: Fn(f64)>(
+    path: PathBuf,
+    entry: Option<&str>,
+    cache_dir: Option<PathBuf>,
+    report: F,
+) -> Result<indexer::Indexer, String> {
+    let mut indexer = indexer::Indexer::open(path, entry, |p| report(p / 2.0)).await?;
+    if let Some(dir) = cache_dir {
+        indexer = indexer.with_cache_dir(dir);
     }
 
-    let mut indexer = indexer::Indexer::new(PathBuf::from(path))?;
+    let start = if indexer.extracted().is_some() {
+        0.5
+    } else {
+        0.0
+    };
+    indexer.index(|p| report(start + p * (1.0 - start))).await;
+    Ok(indexer)
+}
 
-    indexer
-        .index(|p| {
-            let _ = app.emit("indexing-progress", IndexProgress { progress: p });
+/// Opens `paths` as one view interleaved by time. `boot_time`, such as
+/// `2024-01-02 03:04:05.000`, lines kernel and monotonic logcat times up
+/// with wall-clock ones.
+#[tauri::command]
+async fn open_merged(
+    paths: Vec<String>,
+    boot_time: Option<String>,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<MergedInfo, String> {
+    if paths.is_empty() {
+        return Err("No files to merge".to_string());
+    }
+    let boot_time = boot_time
+        .map(|time| {
+            timestamp::parse_line_timestamp(time.as_bytes())
+                .ok_or_else(|| format!("Invalid boot time '{}'", time))
         })
-        .await;
+        .transpose()?;
 
-    let line_count = indexer.line_count();
+    let document_id = state.documents.lock().await.reserve_id();
+
+    let cache_dir = app
+        .path()
+        .app_cache_dir()
+        .ok()
+        .map(|dir| dir.join("line-index"));
+    // Each file gets an equal share of the progress bar, plus one for merging.
+    let steps = (paths.len() + 1) as f64;
+
+    let mut sources = Vec::with_capacity(paths.len());
+    for (i, path) in paths.into_iter().enumerate() {
+        let report = |p: f64| {
+            let _ = app.emit(
+                "indexing-progress",
+                IndexProgress {
+                    document_id,
+                    progress: (i as f64 + p) / steps,
+                },
+            );
+        };
+
+        sources.push(open_indexer(PathBuf::from(path), None, cache_dir.clone(), report).await?);
+    }
+
+    let merged = MergedView::build(sources, boot_time, |p| {
+        let _ = app.emit(
+            "indexing-progress",
+            IndexProgress {
+                document_id,
+                progress: (steps - 1.0 + p) / steps,
+            },
+        );
+    })
+    .await?;
+
+    let info = MergedInfo {
+        document_id,
+        line_count: merged.line_count(),
+        sources: merged.source_paths(),
+    };
+
+    let title = format!("Merged ({} files)", info.sources.len());
+    state
+        .documents
+        .lock()
+        .await
+        .insert(document_id, Document::merged(title, merged));
 
-    let mut filtered_offsets = state.filtered_offsets.lock().await;
-    *filtered_offsets = None;
+    Ok(info)
+}
+
+/// Closes a document, stopping its live sources and releasing its index.
+#[tauri::command]
+async fn close_document(document_id: DocumentId, state: State<'_, AppState>) -> Result<(), String> {
+    let doc = state
+        .documents
+        .lock()
+        .await
+        .remove(document_id)
+        .ok_or_else(|| format!("No open document with id {}", document_id))?;
+    doc.close().await;
+    Ok(())
+}
 
-    let mut state_indexer = state.indexer.lock().await;
-    *state_indexer = Some(indexer);
+#[tauri::command]
+async fn list_documents(state: State<'_, AppState>) -> Result<Vec<DocumentInfo>, String> {
+    Ok(state.documents.lock().await.list())
+}
 
-    Ok(line_count)
+#[tauri::command]
+async fn list_archive_entries(path: String) -> Result<archive::ArchiveListing, String> {
+    archive::list_entries(&PathBuf::from(path))
 }
 
+/// Filters the document and stores the result as its current view. Each
+/// call cancels the run before it; a cancelled run returns `None` and leaves
+/// the previous view in place.
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn apply_filters(
+    document_id: DocumentId,
     filters: Vec<LogFilter>,
     search_query: String,
+    min_level: Option<LogLevel>,
+    query: Option<String>,
+    context: Option<ContextLines>,
+    app: AppHandle,
     state: State<'_, AppState>,
-) -> Result<FilterResult, String> {
-    let adb_active = state.is_adb_active.lock().await;
+) -> Result<Option<FilterResult>, String> {
+    let doc = state.documents.lock().await.get(document_id)?;
+
+    let cancel = CancelToken::new();
+    if let Some(previous) = doc.filter_run.lock().await.replace(cancel.clone()) {
+        previous.cancel();
+    }
+
+    let has_search = !search_query.is_empty();
+    let has_filters = min_level.is_some()
+        || query.as_deref().is_some_and(|q| !q.trim().is_empty())
+        || filters
+            .iter()
+            .any(|f| f.is_enabled && !f.pattern.is_empty());
+
+    if doc.kind == DocumentKind::Adb {
+        // Every captured line is kept, so the whole buffer is re-filtered
+        // and the capture keeps `filtered_offsets` up to date from here on.
+        let buffer = Arc::clone(&doc.adb_buffer).lock_owned().await;
+        let processes = doc.processes.lock().await.clone().unwrap_or_default();
+        let mut active_filter = Arc::clone(&doc.active_filter).lock_owned().await;
+        let mut filtered_offsets = Arc::clone(&doc.filtered_offsets).lock_owned().await;
+        let mut filtered_context = Arc::clone(&doc.filtered_context).lock_owned().await;
+        if !has_search && !has_filters {
+            *active_filter = None;
+            *filtered_offsets = None;
+            *filtered_context = None;
+            return Ok(Some(FilterResult {
+                visible_count: buffer.len(),
+                match_count: buffer.len(),
+                filter_counts: std::collections::HashMap::new(),
+            }));
+        }
 
-    if *adb_active {
-        // For ADB, we don't have static offsets, filters are applied at ingestion.
-        let buffer = state.adb_buffer.lock().await;
         let mut engine = FilterEngine::new(filters)?;
         engine.set_search_query(&search_query)?;
+        engine.set_min_level(min_level);
+        engine.set_event_tags(Arc::clone(&*state.event_tags.lock().await));
+        engine.set_query(query.as_deref().unwrap_or(""))?;
+        engine.set_context(context.unwrap_or_default());
+        engine.set_processes(processes);
+
+        return run_blocking(&app, document_id, cancel, move |run| {
+            let within = match (active_filter.as_ref(), filtered_offsets.as_deref()) {
+                (Some(previous), Some(indices)) if engine.narrows(previous) => Some(indices),
+                _ => None,
+            };
+            let outcome = adb::filter_buffer(&engine, &buffer, within, run)?;
+            let count = outcome.visible.len();
+            *active_filter = Some(engine);
+            *filtered_offsets = Some(outcome.visible);
+            *filtered_context = outcome.context;
 
-        let counts = engine.get_match_counts(&buffer);
-        return Ok(FilterResult {
-            visible_count: buffer.len(),
-            filter_counts: counts,
-        });
+            Some(FilterResult {
+                visible_count: count,
+                match_count: outcome.match_count,
+                filter_counts: outcome.counts,
+            })
+        })
+        .await;
     }
 
-    let indexer_lock = state.indexer.lock().await;
-    let indexer = indexer_lock.as_ref().ok_or("No file opened")?;
+    let mut engine = FilterEngine::new(filters)?;
+    if has_search {
+        engine.set_search_query(&search_query)?;
+    }
+    engine.set_min_level(min_level);
+    engine.set_event_tags(Arc::clone(&*state.event_tags.lock().await));
+    engine.set_query(query.as_deref().unwrap_or(""))?;
+    engine.set_context(context.unwrap_or_default());
+    if engine.needs_processes() {
+        engine.set_processes(process_map(&doc).await?);
+    }
 
-    let has_search = !search_query.is_empty();
-    let has_filters = !filters.is_empty()
-        && filters
-            .iter()
-            .any(|f| f.is_enabled && !f.pattern.is_empty());
+    let merged_lock = Arc::clone(&doc.merged_view).lock_owned().await;
+    if let Some(merged) = merged_lock.as_ref() {
+        let mut active_filter = Arc::clone(&doc.active_filter).lock_owned().await;
+        let mut filtered_offsets = Arc::clone(&doc.filtered_offsets).lock_owned().await;
+        let mut filtered_context = Arc::clone(&doc.filtered_context).lock_owned().await;
+        if !has_search && !has_filters {
+            *active_filter = None;
+            *filtered_offsets = None;
+            *filtered_context = None;
+            return Ok(Some(FilterResult {
+                visible_count: merged.line_count(),
+                match_count: merged.line_count(),
+                filter_counts: std::collections::HashMap::new(),
+            }));
+        }
+
+        return run_blocking(&app, document_id, cancel, move |run| {
+            let merged = merged_lock.as_ref()?;
+            let outcome = match (active_filter.as_ref(), filtered_offsets.as_deref()) {
+                (Some(previous), Some(positions)) if engine.narrows(previous) => {
+                    merged.run_filters_within(&engine, positions, run)
+                }
+                _ => merged.run_filters(&engine, run),
+            }?;
+            let count = outcome.visible.len();
+            *active_filter = Some(engine);
+            *filtered_offsets = Some(outcome.visible);
+            *filtered_context = outcome.context;
+
+            Some(FilterResult {
+                visible_count: count,
+                match_count: outcome.match_count,
+                filter_counts: outcome.counts,
+            })
+        })
+        .await;
+    }
+    drop(merged_lock);
+
+    let indexer_lock = Arc::clone(&doc.indexer).lock_owned().await;
+    let indexer = indexer_lock.as_ref().ok_or("No file opened")?;
+    let mut active_filter = Arc::clone(&doc.active_filter).lock_owned().await;
+    let mut filtered_offsets = Arc::clone(&doc.filtered_offsets).lock_owned().await;
+    let mut filtered_context = Arc::clone(&doc.filtered_context).lock_owned().await;
 
     if !has_search && !has_filters {
-        let mut filtered_offsets = state.filtered_offsets.lock().await;
+        *active_filter = None;
         *filtered_offsets = None;
-        return Ok(FilterResult {
+        *filtered_context = None;
+        return Ok(Some(FilterResult {
             visible_count: indexer.line_count(),
+            match_count: indexer.line_count(),
             filter_counts: std::collections::HashMap::new(),
-        });
+        }));
     }
 
-    let mut engine = FilterEngine::new(filters)?;
-    if has_search {
-        engine.set_search_query(&search_query)?;
-    }
+    run_blocking(&app, document_id, cancel, move |run| {
+        let indexer = indexer_lock.as_ref()?;
+        // A change that only hides more lines re-filters the current result
+        // instead of the whole file.
+        let outcome = match (active_filter.as_ref(), filtered_offsets.as_deref()) {
+            (Some(previous), Some(offsets)) if engine.narrows(previous) => {
+                indexer.run_filters_within(&engine, offsets, run)
+            }
+            _ => indexer.run_filters(&engine, run),
+        }?;
+        let count = outcome.visible.len();
 
-    let new_offsets = indexer.apply_filters(&engine);
-    let count = new_offsets.len();
+        // Keep the engine around so follow mode can filter newly appended lines.
+        *active_filter = Some(engine);
+        *filtered_offsets = Some(outcome.visible);
+        *filtered_context = outcome.context;
 
-    let counts = engine.get_match_counts_from_offsets(indexer, &new_offsets);
+        Some(FilterResult {
+            visible_count: count,
+            match_count: outcome.match_count,
+            filter_counts: outcome.counts,
+        })
+    })
+    .await
+}
+
+/// The document's process map. File and merged documents only build theirs,
+/// from every line on the blocking pool, the first time a filter needs it.
+async fn process_map(doc: &Document) -> Result<Arc<ProcessMap>, String> {
+    // Same lock order as `visible_lines` and follow mode.
+    let merged_lock = Arc::clone(&doc.merged_view).lock_owned().await;
+    let indexer_lock = Arc::clone(&doc.indexer).lock_owned().await;
+    let mut processes = doc.processes.lock().await;
+    if let Some(map) = processes.as_ref() {
+        return Ok(Arc::clone(map));
+    }
 
-    let mut filtered_offsets = state.filtered_offsets.lock().await;
-    *filtered_offsets = Some(new_offsets);
+    let map =
+        tokio::task::spawn_blocking(
+            move || match (merged_lock.as_ref(), indexer_lock.as_ref()) {
+                (Some(merged), _) => ProcessMap::collect(merged.line_count(), |i| {
+                    merged.get_line(i).unwrap_or_default()
+                }),
+                (None, Some(indexer)) => ProcessMap::collect(indexer.line_count(), |i| {
+                    indexer.get_line(i).unwrap_or_default()
+                }),
+                (None, None) => ProcessMap::default(),
+            },
+        )
+        .await
+        .map_err(|e| format!("Failed to collect processes: {}", e))?;
+    Ok(Arc::clone(processes.insert(Arc::new(map))))
+}
 
-    Ok(FilterResult {
-        visible_count: count,
-        filter_counts: counts,
+/// Runs the CPU-bound part of a filter on the blocking pool, so that the
+/// runtime keeps serving other commands, such as the next `apply_filters`
+/// that cancels this one, while it scans.
+async fn run_blocking<R, T, F>(
+    app: &AppHandle<R>,
+    document_id: DocumentId,
+    cancel: CancelToken,
+    scan: F,
+) -> Result<T, String>
+where
+    R: Runtime,
+    T: Send + 'static,
+    F: FnOnce(&FilterRun) -> T + Send + 'static,
+{
+    let app = app.clone();
+    tokio::task::spawn_blocking(move || {
+        let report = |progress| {
+            let _ = app.emit(
+                "filter-progress",
+                IndexProgress {
+                    document_id,
+                    progress,
+                },
+            );
+        };
+        scan(&FilterRun::new(cancel, &report))
     })
+    .await
+    .map_err(|e| format!("Failed to run filters: {}", e))
+}
+
+/// Parses `query` without running it, so the UI can point at mistakes
+/// while the user types.
+#[tauri::command]
+async fn check_query(query: String) -> Result<Option<QueryError>, String> {
+    Ok(query::parse(&query).err())
+}
+
+/// A line of the current view together with where it sits in the
+/// unfiltered document.
+#[derive(Clone, Serialize)]
+struct VisibleLine {
+    /// Zero-based line number in the unfiltered document; for merged
+    /// documents, the position in the merged view.
+    line_number: usize,
+    /// Where the line starts in its file. Not known for ADB lines.
+    byte_offset: Option<usize>,
+    text: String,
+    /// The process that logged the line, when its pid is known.
+    process: Option<String>,
+}
+
+/// Where an original line number lands in the current view.
+#[derive(Clone, Serialize)]
+struct ViewPosition {
+    position: usize,
+    /// False if the line itself is filtered out and `position` is the first
+    /// visible line after it, or the last visible line if none follows.
+    exact: bool,
 }
 
 #[tauri::command]
 async fn get_log_lines(
+    document_id: DocumentId,
     start_index: usize,
     count: usize,
     state: State<'_, AppState>,
-) -> Result<Vec<String>, String> {
-    let adb_active = state.is_adb_active.lock().await;
-    if *adb_active {
-        let buffer = state.adb_buffer.lock().await;
-        let end = std::cmp::min(start_index + count, buffer.len());
-        if start_index >= buffer.len() {
-            return Ok(Vec::new());
+) -> Result<Vec<VisibleLine>, String> {
+    let doc = state.documents.lock().await.get(document_id)?;
+    visible_lines(&doc, start_index, count).await
+}
+
+/// Maps a line number of the unfiltered document to its position in the
+/// current view. Returns `None` if nothing is visible.
+#[tauri::command]
+async fn get_view_position(
+    document_id: DocumentId,
+    line_number: usize,
+    state: State<'_, AppState>,
+) -> Result<Option<ViewPosition>, String> {
+    let doc = state.documents.lock().await.get(document_id)?;
+    view_position(&doc, line_number).await
+}
+
+async fn view_position(doc: &Document, line_number: usize) -> Result<Option<ViewPosition>, String> {
+    let (visible_count, position) = if doc.kind == DocumentKind::Adb {
+        let buffer = doc.adb_buffer.lock().await;
+        match doc.filtered_offsets.lock().await.as_ref() {
+            Some(indices) => (indices.len(), indices.partition_point(|&i| i < line_number)),
+            None => (buffer.len(), line_number),
+        }
+    } else {
+        let merged_lock = doc.merged_view.lock().await;
+        if let Some(merged) = merged_lock.as_ref() {
+            match doc.filtered_offsets.lock().await.as_ref() {
+                Some(positions) => (
+                    positions.len(),
+                    positions.partition_point(|&p| p < line_number),
+                ),
+                None => (merged.line_count(), line_number),
+            }
+        } else {
+            // The indexer is locked before the offsets, as the follow task
+            // does.
+            let indexer_lock = doc.indexer.lock().await;
+            let indexer = indexer_lock.as_ref().ok_or("No file opened")?;
+            let filtered_lock = doc.filtered_offsets.lock().await;
+            match (filtered_lock.as_ref(), indexer.line_offset(line_number)) {
+                (Some(offsets), Some(offset)) => {
+                    (offsets.len(), offsets.partition_point(|&o| o < offset))
+                }
+                (Some(offsets), None) => (offsets.len(), offsets.len()),
+                (None, _) => (indexer.line_count(), line_number),
+            }
         }
-        return Ok(buffer[start_index..end].to_vec());
+    };
+
+    if visible_count == 0 {
+        return Ok(None);
     }
+    let exact =
+        position < visible_count && get_line_number_at(doc, position).await? == Some(line_number);
+    Ok(Some(ViewPosition {
+        position: position.min(visible_count - 1),
+        exact,
+    }))
+}
+
+/// Maps a position in the current view back to the line number in the
+/// unfiltered document.
+#[tauri::command]
+async fn get_line_number(
+    document_id: DocumentId,
+    position: usize,
+    state: State<'_, AppState>,
+) -> Result<Option<usize>, String> {
+    let doc = state.documents.lock().await.get(document_id)?;
+    get_line_number_at(&doc, position).await
+}
+
+async fn get_line_number_at(doc: &Document, position: usize) -> Result<Option<usize>, String> {
+    Ok(visible_lines(doc, position, 1)
+        .await?
+        .first()
+        .map(|line| line.line_number))
+}
+
+/// Returns the visible lines in the range parsed into logcat records.
+#[tauri::command]
+async fn get_log_records(
+    document_id: DocumentId,
+    start_index: usize,
+    count: usize,
+    state: State<'_, AppState>,
+) -> Result<Vec<LogLine<'static>>, String> {
+    let doc = state.documents.lock().await.get(document_id)?;
+    let lines = visible_lines(&doc, start_index, count).await?;
+    Ok(lines
+        .iter()
+        .map(|line| logcat::parse_line(&line.text).into_owned())
+        .collect())
+}
+
+/// Returns, for each visible line in the range, the spans matched by the
+/// search query and each enabled filter.
+#[tauri::command]
+async fn get_line_highlights(
+    document_id: DocumentId,
+    start_index: usize,
+    count: usize,
+    filters: Vec<LogFilter>,
+    search_query: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<Vec<HighlightSpan>>, String> {
+    let doc = state.documents.lock().await.get(document_id)?;
+    let lines = visible_lines(&doc, start_index, count).await?;
 
-    let indexer_lock = state.indexer.lock().await;
+    let mut engine = FilterEngine::new(filters)?;
+    engine.set_search_query(&search_query)?;
+    engine.set_event_tags(Arc::clone(&*state.event_tags.lock().await));
+
+    Ok(lines
+        .par_iter()
+        .map(|line| engine.highlight_spans(&line.text))
+        .collect())
+}
+
+/// Reads up to `count` lines starting at `start_index`, counted among the
+/// lines that passed the current filters.
+async fn visible_lines(
+    doc: &Document,
+    start_index: usize,
+    count: usize,
+) -> Result<Vec<VisibleLine>, String> {
+    let range = start_index..start_index.saturating_add(count);
+
+    if doc.kind == DocumentKind::Adb {
+        let buffer = doc.adb_buffer.lock().await;
+        let processes = doc.processes.lock().await.clone().unwrap_or_default();
+        let filtered_lock = doc.filtered_offsets.lock().await;
+        return Ok(range
+            .map_while(|i| {
+                let index = match filtered_lock.as_ref() {
+                    Some(indices) => *indices.get(i)?,
+                    None => i,
+                };
+                let text = buffer.get(index)?.into_owned();
+                Some(VisibleLine {
+                    line_number: index,
+                    byte_offset: None,
+                    process: process_of(&processes, &text),
+                    text,
+                })
+            })
+            .collect());
+    }
+
+    let merged_lock = doc.merged_view.lock().await;
+    if let Some(merged) = merged_lock.as_ref() {
+        let processes = doc.processes.lock().await.clone().unwrap_or_default();
+        let filtered_lock = doc.filtered_offsets.lock().await;
+        let lines = range
+            .map_while(|i| {
+                let position = match filtered_lock.as_ref() {
+                    Some(positions) => *positions.get(i)?,
+                    None => i,
+                };
+                let text = merged.get_line(position)?;
+                Some(VisibleLine {
+                    line_number: position,
+                    byte_offset: merged.line_offset(position),
+                    process: process_of(&processes, &text),
+                    text,
+                })
+            })
+            .collect();
+        return Ok(lines);
+    }
+
+    let indexer_lock = doc.indexer.lock().await;
     let indexer = indexer_lock.as_ref().ok_or("No file opened")?;
+    let processes = doc.processes.lock().await.clone().unwrap_or_default();
 
-    let filtered_lock = state.filtered_offsets.lock().await;
+    let filtered_lock = doc.filtered_offsets.lock().await;
 
     let mut lines = Vec::new();
-    for i in start_index..(start_index + count) {
+    for i in range {
         let line = if let Some(offsets) = filtered_lock.as_ref() {
-            indexer.get_line_from_offsets(offsets, i)
+            indexer.get_line_from_offsets(offsets, i).and_then(|text| {
+                Some(VisibleLine {
+                    line_number: indexer.line_index_of(offsets[i])?,
+                    byte_offset: Some(offsets[i]),
+                    process: process_of(&processes, &text),
+                    text,
+                })
+            })
         } else {
-            indexer.get_line(i)
+            indexer.get_line(i).map(|text| VisibleLine {
+                line_number: i,
+                byte_offset: indexer.line_offset(i),
+                process: process_of(&processes, &text),
+                text,
+            })
         };
 
         if let Some(l) = line {
@@ -160,23 +701,188 @@ async fn get_log_lines(
     Ok(lines)
 }
 
+/// The name of the process that logged `text`, if its pid is known.
+fn process_of(processes: &ProcessMap, text: &str) -> Option<String> {
+    let parsed = logcat::parse_line(text);
+    let record = parsed.record()?;
+    let name = processes.name(record.pid?, record.timestamp)?;
+    Some(name.to_string())
+}
+
+#[derive(Clone, Serialize)]
+struct LineMark {
+    /// Shown as context around a match rather than matching itself.
+    context: bool,
+    /// Lines were skipped between this line and the one above it.
+    gap_before: bool,
+}
+
+/// Returns context and gap markers for each visible line in the range. Both
+/// are always false unless the current filters show context lines.
+#[tauri::command]
+async fn get_line_marks(
+    document_id: DocumentId,
+    start_index: usize,
+    count: usize,
+    state: State<'_, AppState>,
+) -> Result<Vec<LineMark>, String> {
+    let doc = state.documents.lock().await.get(document_id)?;
+
+    let marks = {
+        // Same lock order as follow mode and ADB capture.
+        let indexer_lock = doc.indexer.lock().await;
+        let filtered_lock = doc.filtered_offsets.lock().await;
+        let context_lock = doc.filtered_context.lock().await;
+        match (filtered_lock.as_deref(), context_lock.as_deref()) {
+            (Some(visible), Some(context)) => Some(line_marks(
+                visible,
+                context,
+                indexer_lock.as_ref(),
+                start_index,
+                count,
+            )),
+            _ => None,
+        }
+    };
+    if let Some(marks) = marks {
+        return Ok(marks);
+    }
+
+    let visible = visible_lines(&doc, start_index, count).await?.len();
+    Ok(vec![
+        LineMark {
+            context: false,
+            gap_before: false,
+        };
+        visible
+    ])
+}
+
+/// Marks for `count` visible lines from `start_index`. `visible` holds byte
+/// offsets into `indexer`'s file, or line numbers if there is no indexer.
+fn line_marks(
+    visible: &[usize],
+    context: &[bool],
+    indexer: Option<&indexer::Indexer>,
+    start_index: usize,
+    count: usize,
+) -> Vec<LineMark> {
+    let end = std::cmp::min(start_index.saturating_add(count), visible.len());
+    if start_index >= end {
+        return Vec::new();
+    }
+
+    // Line numbers of the visible lines, from the one above the range on.
+    let first = start_index.saturating_sub(1);
+    let line_numbers: Vec<Option<usize>> = match indexer {
+        Some(indexer) => visible[first..end]
+            .iter()
+            .map(|&o| indexer.line_index_of(o))
+            .collect(),
+        None => visible[first..end].iter().map(|&p| Some(p)).collect(),
+    };
+
+    (start_index..end)
+        .map(|i| {
+            let line = line_numbers[i - first];
+            let above = if i == 0 {
+                None
+            } else {
+                line_numbers[i - 1 - first]
+            };
+            LineMark {
+                context: context.get(i).copied().unwrap_or(false),
+                gap_before: match (above, line) {
+                    (Some(above), Some(line)) => line > above + 1,
+                    (None, Some(line)) => i == 0 && line > 0,
+                    _ => false,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Returns, for each visible line in the range, the index into the merged
+/// view's `sources` of the file it came from.
+#[tauri::command]
+async fn get_line_sources(
+    document_id: DocumentId,
+    start_index: usize,
+    count: usize,
+    state: State<'_, AppState>,
+) -> Result<Vec<usize>, String> {
+    let doc = state.documents.lock().await.get(document_id)?;
+
+    let merged_lock = doc.merged_view.lock().await;
+    let merged = merged_lock.as_ref().ok_or("No merged view opened")?;
+    let filtered_lock = doc.filtered_offsets.lock().await;
+
+    Ok((start_index..start_index.saturating_add(count))
+        .map_while(|i| match filtered_lock.as_ref() {
+            Some(positions) => positions.get(i).and_then(|&p| merged.source_of(p)),
+            None => merged.source_of(i),
+        })
+        .collect())
+}
+
+/// Loads the `event-log-tags` that name the fields of events-buffer
+/// entries, from `path` or, without one, from the device with the given
+/// serial. Returns the number of tags loaded.
+#[tauri::command]
+async fn load_event_tags(
+    path: Option<String>,
+    serial: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<usize, String> {
+    let text = match path {
+        Some(path) => std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read event tags: {}", e))?,
+        None => {
+            let command = format!("cat {}", event_tags::DEVICE_PATH);
+            adb_client::AdbClient::default()
+                .shell(serial.as_deref(), &command)
+                .await?
+        }
+    };
+
+    let tags = EventTags::parse(&text);
+    if tags.is_empty() {
+        return Err("No event tags found".to_string());
+    }
+    let count = tags.len();
+    *state.event_tags.lock().await = Arc::new(tags);
+    Ok(count)
+}
+
 #[tauri::command]
+async fn list_devices() -> Result<Vec<adb::AdbDevice>, String> {
+    let client = adb_client::AdbClient::default();
+    if client.is_reachable().await {
+        return client.devices().await;
+    }
+    // The adb executable starts the server when it is not running yet.
+    adb::list_devices().await
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn start_adb(
+    serial: Option<String>,
+    logcat: Option<adb::LogcatOptions>,
+    reconnect: Option<adb::ReconnectPolicy>,
     filters: Vec<LogFilter>,
     search_query: String,
+    min_level: Option<LogLevel>,
+    query: Option<String>,
+    context: Option<ContextLines>,
+    memory_lines: Option<usize>,
     app: AppHandle,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    let mut adb_active = state.is_adb_active.lock().await;
-    let mut buffer = state.adb_buffer.lock().await;
-    let mut adb_manager = state.adb_manager.lock().await;
-
-    // Reset buffer for new stream
-    buffer.clear();
-
+) -> Result<DocumentId, String> {
     let has_search = !search_query.is_empty();
-    let has_filters = !filters.is_empty()
-        && filters
+    let has_filters = min_level.is_some()
+        || query.as_deref().is_some_and(|q| !q.trim().is_empty())
+        || filters
             .iter()
             .any(|f| f.is_enabled && !f.pattern.is_empty());
 
@@ -187,21 +893,62 @@ async fn start_adb(
         if has_search {
             eng.set_search_query(&search_query)?;
         }
+        eng.set_min_level(min_level);
+        eng.set_event_tags(Arc::clone(&*state.event_tags.lock().await));
+        eng.set_query(query.as_deref().unwrap_or(""))?;
+        eng.set_context(context.unwrap_or_default());
         Some(eng)
     };
 
-    adb_manager
-        .start_streaming(app, engine, Arc::clone(&state.adb_buffer))
+    // Starting the stream talks to the adb server, so the registry is only
+    // locked to reserve the id and to insert the document afterwards.
+    let document_id = state.documents.lock().await.reserve_id();
+    let spill_dir = match app.path().app_cache_dir() {
+        Ok(dir) if std::fs::create_dir_all(dir.join("captures")).is_ok() => dir.join("captures"),
+        _ => std::env::temp_dir(),
+    };
+    let buffer = CaptureBuffer::new(
+        memory_lines.unwrap_or(capture::DEFAULT_MEMORY_LINES),
+        spill_dir,
+    );
+    let title = match &serial {
+        Some(serial) => format!("ADB Logcat ({})", serial),
+        None => "ADB Logcat".to_string(),
+    };
+    let doc = Document::adb(title, buffer);
+
+    if let Some(mut engine) = engine {
+        engine.set_processes(doc.processes.lock().await.clone().unwrap_or_default());
+        let (before, after) = engine.max_context();
+        *doc.filtered_offsets.lock().await = Some(Vec::new());
+        *doc.filtered_context.lock().await = (before > 0 || after > 0).then(Vec::new);
+        *doc.active_filter.lock().await = Some(engine);
+    }
+
+    doc.adb_manager
+        .lock()
+        .await
+        .start_streaming(
+            app,
+            document_id,
+            &doc,
+            serial,
+            logcat.unwrap_or_default(),
+            reconnect.unwrap_or_default(),
+            Arc::clone(&state.event_tags),
+        )
         .await?;
-    *adb_active = true;
+    state.documents.lock().await.insert(document_id, doc);
 
-    Ok(())
+    Ok(document_id)
 }
 
 #[tauri::command]
-async fn stop_adb(state: State<'_, AppState>) -> Result<(), String> {
-    let mut adb_active = state.is_adb_active.lock().await;
-    let mut adb_manager = state.adb_manager.lock().await;
+async fn stop_adb(document_id: DocumentId, state: State<'_, AppState>) -> Result<(), String> {
+    let doc = state.documents.lock().await.get(document_id)?;
+
+    let mut adb_active = doc.is_adb_active.lock().await;
+    let mut adb_manager = doc.adb_manager.lock().await;
 
     adb_manager.stop();
     *adb_active = false;
@@ -209,73 +956,94 @@ async fn stop_adb(state: State<'_, AppState>) -> Result<(), String> {
     Ok(())
 }
 
+#[tauri::command]
+async fn start_follow(
+    document_id: DocumentId,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let doc = state.documents.lock().await.get(document_id)?;
+
+    if doc.kind != DocumentKind::File {
+        return Err("Follow mode is only available for files".to_string());
+    }
+
+    let mut follow_manager = doc.follow_manager.lock().await;
+    follow_manager.start(
+        app,
+        document_id,
+        Arc::clone(&doc.indexer),
+        Arc::clone(&doc.filtered_offsets),
+        Arc::clone(&doc.filtered_context),
+        Arc::clone(&doc.active_filter),
+        Arc::clone(&doc.processes),
+        Arc::clone(&doc.filter_run),
+    );
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn stop_follow(document_id: DocumentId, state: State<'_, AppState>) -> Result<(), String> {
+    let doc = state.documents.lock().await.get(document_id)?;
+
+    doc.follow_manager.lock().await.stop();
+    Ok(())
+}
+
 #[tauri::command]
 async fn get_minimap_data(
+    document_id: DocumentId,
     filters: Vec<LogFilter>,
     search_query: String,
+    min_level: Option<LogLevel>,
+    query: Option<String>,
     buckets: usize,
     state: State<'_, AppState>,
 ) -> Result<Vec<indexer::MinimapBucket>, String> {
-    let adb_active = state.is_adb_active.lock().await;
+    let doc = state.documents.lock().await.get(document_id)?;
 
-    if *adb_active {
-        // For ADB, we can use the current buffer
-        let buffer = state.adb_buffer.lock().await;
-        if buffer.is_empty() || buckets == 0 {
-            return Ok(Vec::new());
-        }
+    if doc.kind == DocumentKind::Adb {
+        let buffer = doc.adb_buffer.lock().await;
 
         let mut engine = FilterEngine::new(filters)?;
         engine.set_search_query(&search_query)?;
+        engine.set_min_level(min_level);
+        engine.set_event_tags(Arc::clone(&*state.event_tags.lock().await));
+        engine.set_query(query.as_deref().unwrap_or(""))?;
+        engine.set_processes(doc.processes.lock().await.clone().unwrap_or_default());
 
-        let total_lines = buffer.len();
-        let bucket_size = (total_lines as f64 / buckets as f64).max(1.0);
-
-        let data = (0..buckets)
-            .into_par_iter()
-            .map(|b| {
-                let start_idx = (b as f64 * bucket_size) as usize;
-                let end_idx = ((b + 1) as f64 * bucket_size) as usize;
-                let end_idx = std::cmp::min(end_idx, total_lines);
-
-                if start_idx >= end_idx {
-                    return indexer::MinimapBucket {
-                        intensity: 0.0,
-                        color: None,
-                        count: 0,
-                    };
-                }
-
-                let mut matched_count = 0;
-                let mut first_color = None;
+        let filtered_lock = doc.filtered_offsets.lock().await;
+        let line_at = |i: usize| match filtered_lock.as_ref() {
+            Some(indices) => buffer.get(indices[i]).unwrap_or_default(),
+            None => buffer.get(i).unwrap_or_default(),
+        };
+        let total_lines = filtered_lock.as_ref().map_or(buffer.len(), |v| v.len());
 
-                for i in start_idx..end_idx {
-                    if let Some(color) = engine.get_match_color(&buffer[i]) {
-                        matched_count += 1;
-                        if first_color.is_none() {
-                            first_color = Some(color);
-                        }
-                    }
-                }
+        return Ok(indexer::minimap_buckets(total_lines, buckets, |i| {
+            engine.get_match_color(&line_at(i))
+        }));
+    }
 
-                indexer::MinimapBucket {
-                    intensity: matched_count as f32 / (end_idx - start_idx) as f32,
-                    color: first_color,
-                    count: matched_count,
-                }
-            })
-            .collect();
+    let mut engine = FilterEngine::new(filters)?;
+    engine.set_search_query(&search_query)?;
+    engine.set_min_level(min_level);
+    engine.set_event_tags(Arc::clone(&*state.event_tags.lock().await));
+    engine.set_query(query.as_deref().unwrap_or(""))?;
+    if engine.needs_processes() {
+        engine.set_processes(process_map(&doc).await?);
+    }
 
-        return Ok(data);
+    let merged_lock = doc.merged_view.lock().await;
+    if let Some(merged) = merged_lock.as_ref() {
+        let filtered_lock = doc.filtered_offsets.lock().await;
+        return Ok(merged.get_minimap_data(&engine, buckets, filtered_lock.as_deref()));
     }
 
-    let indexer_lock = state.indexer.lock().await;
+    let indexer_lock = doc.indexer.lock().await;
     let indexer = indexer_lock.as_ref().ok_or("No file opened")?;
 
-    let filtered_lock = state.filtered_offsets.lock().await;
-
-    let mut engine = FilterEngine::new(filters)?;
-    engine.set_search_query(&search_query)?;
+    let filtered_lock = doc.filtered_offsets.lock().await;
 
     Ok(indexer.get_minimap_data(&engine, buckets, filtered_lock.as_deref()))
 }
@@ -284,22 +1052,135 @@ async fn get_minimap_data(
 pub fn run() {
     tauri::Builder::default()
         .manage(AppState {
-            indexer: Arc::new(Mutex::new(None)),
-            filtered_offsets: Arc::new(Mutex::new(None)),
-            adb_manager: Arc::new(Mutex::new(AdbManager::new())),
-            adb_buffer: Arc::new(Mutex::new(Vec::new())),
-            is_adb_active: Arc::new(Mutex::new(false)),
+            documents: Arc::new(Mutex::new(Documents::default())),
+            event_tags: Arc::default(),
         })
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
         .invoke_handler(tauri::generate_handler![
             open_file,
+            open_merged,
+            close_document,
+            list_documents,
+            list_archive_entries,
             get_log_lines,
+            get_log_records,
+            get_line_highlights,
+            get_line_marks,
+            get_view_position,
+            get_line_number,
+            get_line_sources,
             apply_filters,
+            check_query,
+            load_event_tags,
+            list_devices,
             start_adb,
             stop_adb,
+            start_follow,
+            stop_follow,
             get_minimap_data
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A file document of ten lines, half of them marked `keep`.
+    async fn ten_lines() -> (Document, tempfile::NamedTempFile) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for i in 0..10 {
+            let mark = if [1, 4, 5, 8].contains(&i) {
+                " keep"
+            } else {
+                ""
+            };
+            writeln!(file, "line {}{}", i, mark).unwrap();
+        }
+        let mut indexer = indexer::Indexer::new(file.path().to_path_buf()).unwrap();
+        indexer.index(|_| {}).await;
+        (Document::file("ten".to_string(), indexer), file)
+    }
+
+    async fn filter_for(doc: &Document, pattern: &str) {
+        let filter = LogFilter {
+            id: pattern.to_string(),
+            pattern: pattern.to_string(),
+            is_include: true,
+            is_enabled: true,
+            color: None,
+            text_color: None,
+            field: None,
+            context_before: 0,
+            context_after: 0,
+        };
+        let engine = FilterEngine::new(vec![filter]).unwrap();
+        let indexer_lock = doc.indexer.lock().await;
+        let outcome = indexer_lock.as_ref().unwrap().apply_filters(&engine);
+        *doc.filtered_offsets.lock().await = Some(outcome.visible);
+    }
+
+    async fn position_of(doc: &Document, line_number: usize) -> Option<(usize, bool)> {
+        view_position(doc, line_number)
+            .await
+            .unwrap()
+            .map(|p| (p.position, p.exact))
+    }
+
+    #[tokio::test]
+    async fn maps_lines_to_view_positions_and_back() {
+        let (doc, _file) = ten_lines().await;
+        for line in 0..10 {
+            assert_eq!(position_of(&doc, line).await, Some((line, true)));
+            assert_eq!(get_line_number_at(&doc, line).await.unwrap(), Some(line));
+        }
+        // Past the end lands on the last line.
+        assert_eq!(position_of(&doc, 10).await, Some((9, false)));
+        assert_eq!(get_line_number_at(&doc, 10).await.unwrap(), None);
+
+        filter_for(&doc, "keep").await;
+        for (position, line) in [1, 4, 5, 8].into_iter().enumerate() {
+            assert_eq!(
+                get_line_number_at(&doc, position).await.unwrap(),
+                Some(line)
+            );
+            assert_eq!(position_of(&doc, line).await, Some((position, true)));
+        }
+        // A hidden line maps to the next visible one, or the last one if
+        // none follows.
+        assert_eq!(position_of(&doc, 0).await, Some((0, false)));
+        assert_eq!(position_of(&doc, 2).await, Some((1, false)));
+        assert_eq!(position_of(&doc, 9).await, Some((3, false)));
+        assert_eq!(position_of(&doc, 10).await, Some((3, false)));
+        assert_eq!(get_line_number_at(&doc, 4).await.unwrap(), None);
+
+        filter_for(&doc, "nothing matches").await;
+        assert_eq!(position_of(&doc, 3).await, None);
+        assert_eq!(get_line_number_at(&doc, 0).await.unwrap(), None);
+    }
+
+    fn gaps(visible: &[usize], start_index: usize, count: usize) -> Vec<bool> {
+        let context = vec![false; visible.len()];
+        line_marks(visible, &context, None, start_index, count)
+            .into_iter()
+            .map(|m| m.gap_before)
+            .collect()
+    }
+
+    #[test]
+    fn marks_gaps_between_non_adjacent_lines() {
+        let visible = [1, 2, 3, 7, 8, 9];
+        assert_eq!(
+            gaps(&visible, 0, visible.len()),
+            vec![true, false, false, true, false, false]
+        );
+        // The line above the range decides the first mark.
+        assert_eq!(gaps(&visible, 3, 2), vec![true, false]);
+        assert_eq!(gaps(&visible, 4, 10), vec![false, false]);
+        assert_eq!(gaps(&[0, 1], 0, 2), vec![false, false]);
+        assert!(gaps(&visible, 6, 1).is_empty());
+    }
+}
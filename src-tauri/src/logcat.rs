@@ -0,0 +1,625 @@
+use crate::timestamp;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum LogLevel {
+    #[serde(rename = "V")]
+    Verbose,
+    #[serde(rename = "D")]
+    Debug,
+    #[serde(rename = "I")]
+    Info,
+    #[serde(rename = "W")]
+    Warn,
+    #[serde(rename = "E")]
+    Error,
+    #[serde(rename = "F")]
+    Fatal,
+}
+
+impl LogLevel {
+    /// Maps a logcat priority letter to a level. `A` (assert) is what older
+    /// releases print for fatal messages.
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'V' => Some(Self::Verbose),
+            'D' => Some(Self::Debug),
+            'I' => Some(Self::Info),
+            'W' => Some(Self::Warn),
+            'E' => Some(Self::Error),
+            'F' | 'A' => Some(Self::Fatal),
+            _ => None,
+        }
+    }
+
+    pub fn as_char(self) -> char {
+        match self {
+            Self::Verbose => 'V',
+            Self::Debug => 'D',
+            Self::Info => 'I',
+            Self::Warn => 'W',
+            Self::Error => 'E',
+            Self::Fatal => 'F',
+        }
+    }
+
+    fn from_token(token: &str) -> Option<Self> {
+        let mut chars = token.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Self::from_char(c),
+            _ => None,
+        }
+    }
+}
+
+/// One logcat entry. Fields a format does not print are `None`; the
+/// timestamp is in nanoseconds, since the Unix epoch for wall-clock formats
+/// and since boot for `monotonic`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogRecord<'a> {
+    pub timestamp: Option<i64>,
+    pub uid: Option<Cow<'a, str>>,
+    pub pid: Option<u32>,
+    pub tid: Option<u32>,
+    pub level: LogLevel,
+    pub tag: Option<Cow<'a, str>>,
+    pub message: Cow<'a, str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LogLine<'a> {
+    Record(LogRecord<'a>),
+    /// A line that does not start a logcat entry, such as the message lines
+    /// under a `long` header or text that is not logcat output at all.
+    Continuation {
+        text: Cow<'a, str>,
+    },
+    /// The `--------- beginning of <buffer>` or `--------- switch to
+    /// <buffer>` line logcat prints when reading several buffers.
+    Divider {
+        buffer: Cow<'a, str>,
+    },
+}
+
+impl LogLine<'_> {
+    pub fn into_owned(self) -> LogLine<'static> {
+        match self {
+            LogLine::Record(r) => LogLine::Record(LogRecord {
+                timestamp: r.timestamp,
+                uid: r.uid.map(|u| Cow::Owned(u.into_owned())),
+                pid: r.pid,
+                tid: r.tid,
+                level: r.level,
+                tag: r.tag.map(|t| Cow::Owned(t.into_owned())),
+                message: Cow::Owned(r.message.into_owned()),
+            }),
+            LogLine::Continuation { text } => LogLine::Continuation {
+                text: Cow::Owned(text.into_owned()),
+            },
+            LogLine::Divider { buffer } => LogLine::Divider {
+                buffer: Cow::Owned(buffer.into_owned()),
+            },
+        }
+    }
+
+    pub fn record(&self) -> Option<&LogRecord<'_>> {
+        match self {
+            LogLine::Record(r) => Some(r),
+            LogLine::Continuation { .. } | LogLine::Divider { .. } => None,
+        }
+    }
+}
+
+/// Reads the pid and process name from an ActivityManager `Start proc`
+/// message, either `Start proc 1234:com.foo:remote/u0a12 for ...` or the
+/// older `Start proc com.foo for ...: pid=1234 uid=...`.
+pub fn parse_start_proc(message: &str) -> Option<(u32, &str)> {
+    let rest = message.trim_start().strip_prefix("Start proc ")?;
+    let name_end = rest.find([' ', '/']).unwrap_or(rest.len());
+
+    let (pid, process) = match rest[..name_end].split_once(':') {
+        Some((pid, process)) if pid.bytes().all(|b| b.is_ascii_digit()) => {
+            (pid.parse().ok()?, process)
+        }
+        _ => {
+            let pid = rest.split(' ').find_map(|t| t.strip_prefix("pid="))?;
+            (pid.parse().ok()?, &rest[..name_end])
+        }
+    };
+    (!process.is_empty()).then_some((pid, process))
+}
+
+/// Parses one line of `adb logcat` output in any of the `-v` formats brief,
+/// process, tag, thread, time, threadtime, long, epoch and monotonic, with
+/// or without the `uid`, `year` and `zone` modifiers. The format is worked
+/// out per line, so files mixing formats parse too.
+pub fn parse_line(line: &str) -> LogLine<'_> {
+    if let Some(buffer) = parse_divider(line) {
+        return LogLine::Divider {
+            buffer: Cow::Borrowed(buffer),
+        };
+    }
+    parse_record(line)
+        .map(LogLine::Record)
+        .unwrap_or(LogLine::Continuation {
+            text: Cow::Borrowed(line),
+        })
+}
+
+fn parse_divider(line: &str) -> Option<&str> {
+    let rest = line.trim_end().strip_prefix("--------- ")?;
+    let buffer = rest
+        .strip_prefix("beginning of ")
+        .or_else(|| rest.strip_prefix("switch to "))?;
+    (!buffer.is_empty() && !buffer.contains(' ')).then_some(buffer)
+}
+
+fn parse_record(line: &str) -> Option<LogRecord<'_>> {
+    let s = line.trim_start();
+
+    if let Some(header) = s.strip_prefix("[ ") {
+        return parse_long_header(header);
+    }
+
+    match parse_timestamp(s) {
+        Some((timestamp, rest)) => {
+            let rest = rest.trim_start();
+            parse_brief(rest, Some(timestamp)).or_else(|| parse_threadtime(rest, timestamp))
+        }
+        None => parse_brief(s, None)
+            .or_else(|| parse_process_or_thread(s))
+            .or_else(|| parse_tag(s)),
+    }
+}
+
+/// Reads a leading timestamp and the optional `-v zone` offset after it.
+fn parse_timestamp(s: &str) -> Option<(i64, &str)> {
+    let bytes = s.as_bytes();
+    let (nanos, len) = timestamp::parse_dated(bytes)
+        .or_else(|| timestamp::parse_undated(bytes))
+        .or_else(|| timestamp::parse_epoch(bytes))?;
+    let rest = &s[len..];
+    if !rest.starts_with(' ') {
+        return None;
+    }
+
+    let after = rest.trim_start();
+    let zone = after.split(' ').next().unwrap_or("");
+    let is_zone = zone.len() == 5
+        && matches!(zone.as_bytes()[0], b'+' | b'-')
+        && zone[1..].bytes().all(|b| b.is_ascii_digit());
+    Some((nanos, if is_zone { &after[5..] } else { rest }))
+}
+
+/// `L/Tag( pid): message`, the layout of `brief` and, after the timestamp,
+/// of `time`. With `uid` the parentheses hold `uid: pid`.
+fn parse_brief(s: &str, timestamp: Option<i64>) -> Option<LogRecord<'_>> {
+    let level = level_slash(s)?;
+    let body = &s[2..];
+
+    // The tag is padded and may itself contain parentheses, so take the
+    // first parenthesised group that holds ids and is followed by a colon.
+    let mut search = 0;
+    while let Some(open) = body[search..].find('(').map(|i| i + search) {
+        search = open + 1;
+        let Some(close) = body[open..].find(')').map(|i| i + open) else {
+            break;
+        };
+        let Some(after) = body[close + 1..].strip_prefix(':') else {
+            continue;
+        };
+        let Some((uid, pid, None)) = parse_ids(&body[open + 1..close]) else {
+            continue;
+        };
+        return Some(LogRecord {
+            timestamp,
+            uid: uid.map(Cow::Borrowed),
+            pid: Some(pid),
+            tid: None,
+            level,
+            tag: Some(Cow::Borrowed(body[..open].trim_end())),
+            message: Cow::Borrowed(strip_space(after)),
+        });
+    }
+    None
+}
+
+/// `L( pid) message  (Tag)` for `process` and `L( pid: tid) message` for
+/// `thread`. With `uid`, `process` prints `uid: pid`, which is told apart
+/// from `thread` by the trailing tag.
+fn parse_process_or_thread(s: &str) -> Option<LogRecord<'_>> {
+    let level = LogLevel::from_char(s.chars().next()?)?;
+    let inner = s[1..].strip_prefix('(')?;
+    let close = inner.find(')')?;
+    let ids = &inner[..close];
+    let message = strip_space(&inner[close + 1..]);
+
+    let trailing_tag = message
+        .strip_suffix(')')
+        .and_then(|m| m.rfind("  (").map(|i| (&m[..i], &m[i + 3..])));
+
+    let (uid, pid, tid, message, tag) = match (parse_ids(ids)?, trailing_tag) {
+        ((uid, pid, None), Some((message, tag))) => (uid, pid, None, message, Some(tag)),
+        ((uid, pid, None), None) => (uid, pid, None, message, None),
+        // `process` with a numeric uid: the two numbers are uid and pid.
+        ((None, _, Some(pid)), Some((message, tag))) => {
+            let uid = ids[..ids.find(':')?].trim();
+            (Some(uid), pid, None, message, Some(tag))
+        }
+        ((uid, pid, tid), _) => (uid, pid, tid, message, None),
+    };
+
+    Some(LogRecord {
+        timestamp: None,
+        uid: uid.map(Cow::Borrowed),
+        pid: Some(pid),
+        tid,
+        level,
+        tag: tag.map(Cow::Borrowed),
+        message: Cow::Borrowed(message),
+    })
+}
+
+/// `L/Tag: message`.
+fn parse_tag(s: &str) -> Option<LogRecord<'_>> {
+    let level = level_slash(s)?;
+    let (tag, message) = split_tag(&s[2..])?;
+    Some(LogRecord {
+        timestamp: None,
+        uid: None,
+        pid: None,
+        tid: None,
+        level,
+        tag: Some(Cow::Borrowed(tag)),
+        message: Cow::Borrowed(message),
+    })
+}
+
+/// `[uid] pid tid L Tag: message`, which follows the timestamp in
+/// `threadtime`, `epoch` and `monotonic`.
+fn parse_threadtime(s: &str, timestamp: i64) -> Option<LogRecord<'_>> {
+    let mut ids: Vec<&str> = Vec::with_capacity(3);
+    let mut rest = s;
+    let level = loop {
+        rest = rest.trim_start();
+        let end = rest.find(' ')?;
+        let token = &rest[..end];
+        rest = &rest[end + 1..];
+
+        if let Some(level) = LogLevel::from_token(token) {
+            break level;
+        }
+        // A 5-character uid name runs straight into a 5-digit pid.
+        match token.split_once(':') {
+            Some((uid, pid)) if !pid.is_empty() => ids.extend([uid, pid]),
+            _ => ids.push(token.trim_end_matches(':')),
+        }
+        if ids.len() > 3 {
+            return None;
+        }
+    };
+
+    let (uid, pid, tid) = match ids.as_slice() {
+        [pid, tid] => (None, *pid, *tid),
+        [uid, pid, tid] => (Some(*uid), *pid, *tid),
+        _ => return None,
+    };
+    let (tag, message) = split_tag(rest)?;
+
+    Some(LogRecord {
+        timestamp: Some(timestamp),
+        uid: uid.map(Cow::Borrowed),
+        pid: Some(pid.parse().ok()?),
+        tid: Some(tid.parse().ok()?),
+        level,
+        tag: Some(Cow::Borrowed(tag)),
+        message: Cow::Borrowed(message),
+    })
+}
+
+/// `[ timestamp [uid:] pid: tid L/Tag ]`, the header line of the `long`
+/// format. The message follows on its own lines, which parse as
+/// continuations.
+fn parse_long_header(s: &str) -> Option<LogRecord<'_>> {
+    let s = s.trim_end().strip_suffix(']')?.trim_end();
+    let (timestamp, rest) = parse_timestamp(s)?;
+    let rest = rest.trim_start();
+
+    let level_at = rest
+        .char_indices()
+        .find(|&(i, _)| {
+            (i == 0 || rest.as_bytes()[i - 1] == b' ') && level_slash(&rest[i..]).is_some()
+        })
+        .map(|(i, _)| i)?;
+    let (uid, pid, tid) = parse_ids(&rest[..level_at])?;
+
+    Some(LogRecord {
+        timestamp: Some(timestamp),
+        uid: uid.map(Cow::Borrowed),
+        pid: Some(pid),
+        tid,
+        level: level_slash(&rest[level_at..])?,
+        tag: Some(Cow::Borrowed(rest[level_at + 2..].trim_end())),
+        message: Cow::Borrowed(""),
+    })
+}
+
+/// Parses `pid`, `pid: tid`, `uid: pid` or `uid: pid: tid` with any padding.
+/// Two numbers are returned as pid and tid; callers that know the format
+/// prints a uid there reinterpret them.
+fn parse_ids(s: &str) -> Option<(Option<&str>, u32, Option<u32>)> {
+    let parts: Vec<&str> = s.split(':').map(str::trim).collect();
+    let number = |p: &str| p.parse::<u32>().ok();
+    match parts.as_slice() {
+        [pid] => Some((None, number(pid)?, None)),
+        [first, second] => match number(first) {
+            Some(pid) => Some((None, pid, Some(number(second)?))),
+            None if !first.is_empty() && !first.contains(' ') => {
+                Some((Some(*first), number(second)?, None))
+            }
+            None => None,
+        },
+        [uid, pid, tid] if !uid.is_empty() => Some((Some(*uid), number(pid)?, Some(number(tid)?))),
+        _ => None,
+    }
+}
+
+/// Reads the `L/` that starts brief, tag and time entries.
+fn level_slash(s: &str) -> Option<LogLevel> {
+    let bytes = s.as_bytes();
+    if bytes.get(1) != Some(&b'/') {
+        return None;
+    }
+    LogLevel::from_char(bytes[0] as char)
+}
+
+/// Splits `Tag     : message` at the first colon followed by a space or the
+/// end of the line.
+fn split_tag(s: &str) -> Option<(&str, &str)> {
+    let colon = s
+        .match_indices(':')
+        .map(|(i, _)| i)
+        .find(|&i| matches!(s.as_bytes().get(i + 1), None | Some(b' ')))?;
+    Some((s[..colon].trim_end(), strip_space(&s[colon + 1..])))
+}
+
+fn strip_space(s: &str) -> &str {
+    s.strip_prefix(' ').unwrap_or(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> Option<i64> {
+        timestamp::parse_dated(time.as_bytes()).map(|(nanos, _)| nanos)
+    }
+
+    fn record(
+        timestamp: Option<i64>,
+        uid: Option<&'static str>,
+        pid: Option<u32>,
+        tid: Option<u32>,
+        level: LogLevel,
+        tag: Option<&'static str>,
+        message: &'static str,
+    ) -> LogLine<'static> {
+        LogLine::Record(LogRecord {
+            timestamp,
+            uid: uid.map(Cow::Borrowed),
+            pid,
+            tid,
+            level,
+            tag: tag.map(Cow::Borrowed),
+            message: Cow::Borrowed(message),
+        })
+    }
+
+    #[test]
+    fn parses_every_format() {
+        use LogLevel::*;
+        let time = at("2000-01-02 03:04:05.678");
+        let am = Some("ActivityManager");
+        let cases = [
+            // brief
+            (
+                "I/ActivityManager(  600): Start proc",
+                record(None, None, Some(600), None, Info, am, "Start proc"),
+            ),
+            (
+                "W/ActivityManager(system:  600): Slow op",
+                record(None, Some("system"), Some(600), None, Warn, am, "Slow op"),
+            ),
+            (
+                "D/Tag (with parens)( 1234): x (y): z",
+                record(
+                    None,
+                    None,
+                    Some(1234),
+                    None,
+                    Debug,
+                    Some("Tag (with parens)"),
+                    "x (y): z",
+                ),
+            ),
+            // process
+            (
+                "I(  600) Start proc  (ActivityManager)",
+                record(None, None, Some(600), None, Info, am, "Start proc"),
+            ),
+            (
+                "I( 1000:  600) Start proc  (ActivityManager)",
+                record(None, Some("1000"), Some(600), None, Info, am, "Start proc"),
+            ),
+            // tag
+            (
+                "E/ActivityManager: ANR in com.foo",
+                record(None, None, None, None, Error, am, "ANR in com.foo"),
+            ),
+            // thread
+            (
+                "V(  600:  620) Start proc",
+                record(
+                    None,
+                    None,
+                    Some(600),
+                    Some(620),
+                    Verbose,
+                    None,
+                    "Start proc",
+                ),
+            ),
+            (
+                "V(system:  600:  620) Start proc",
+                record(
+                    None,
+                    Some("system"),
+                    Some(600),
+                    Some(620),
+                    Verbose,
+                    None,
+                    "Start proc",
+                ),
+            ),
+            // time
+            (
+                "01-02 03:04:05.678 I/ActivityManager(  600): Start proc",
+                record(time, None, Some(600), None, Info, am, "Start proc"),
+            ),
+            // threadtime, with the uid, year, zone and nsec modifiers
+            (
+                "01-02 03:04:05.678   600   620 I ActivityManager: Start proc",
+                record(time, None, Some(600), Some(620), Info, am, "Start proc"),
+            ),
+            (
+                "01-02 03:04:05.678  system   600   620 F ActivityManager: Crash",
+                record(
+                    time,
+                    Some("system"),
+                    Some(600),
+                    Some(620),
+                    Fatal,
+                    am,
+                    "Crash",
+                ),
+            ),
+            (
+                "01-02 03:04:05.678 u0_a5:12345 12360 D Tag: x",
+                record(
+                    time,
+                    Some("u0_a5"),
+                    Some(12345),
+                    Some(12360),
+                    Debug,
+                    Some("Tag"),
+                    "x",
+                ),
+            ),
+            (
+                "2024-01-02 03:04:05.678   600   620 I ActivityManager: Start proc",
+                record(
+                    at("2024-01-02 03:04:05.678"),
+                    None,
+                    Some(600),
+                    Some(620),
+                    Info,
+                    am,
+                    "Start proc",
+                ),
+            ),
+            (
+                "01-02 03:04:05.678 +0100   600   620 I ActivityManager: Start proc",
+                record(time, None, Some(600), Some(620), Info, am, "Start proc"),
+            ),
+            (
+                "01-02 03:04:05.678901234   600   620 I ActivityManager: Start proc",
+                record(
+                    at("2000-01-02 03:04:05.678901234"),
+                    None,
+                    Some(600),
+                    Some(620),
+                    Info,
+                    am,
+                    "Start proc",
+                ),
+            ),
+            // epoch and monotonic
+            (
+                "1704164645.678   600   620 I ActivityManager: Start proc",
+                record(
+                    Some(1_704_164_645_678_000_000),
+                    None,
+                    Some(600),
+                    Some(620),
+                    Info,
+                    am,
+                    "Start proc",
+                ),
+            ),
+            (
+                "   123.456   600   620 I ActivityManager: Start proc",
+                record(
+                    Some(123_456_000_000),
+                    None,
+                    Some(600),
+                    Some(620),
+                    Info,
+                    am,
+                    "Start proc",
+                ),
+            ),
+            // long, whose message follows on its own lines
+            (
+                "[ 01-02 03:04:05.678   600:  620 I/ActivityManager ]",
+                record(time, None, Some(600), Some(620), Info, am, ""),
+            ),
+            (
+                "[ 01-02 03:04:05.678 system:  600:  620 W/ActivityManager ]",
+                record(time, Some("system"), Some(600), Some(620), Warn, am, ""),
+            ),
+            (
+                "Start proc 4321:com.foo/u0a12",
+                LogLine::Continuation {
+                    text: Cow::Borrowed("Start proc 4321:com.foo/u0a12"),
+                },
+            ),
+            (
+                "\tat com.foo.Bar.run(Bar.java:12)",
+                LogLine::Continuation {
+                    text: Cow::Borrowed("\tat com.foo.Bar.run(Bar.java:12)"),
+                },
+            ),
+            (
+                "",
+                LogLine::Continuation {
+                    text: Cow::Borrowed(""),
+                },
+            ),
+            // dividers
+            (
+                "--------- beginning of main",
+                LogLine::Divider {
+                    buffer: Cow::Borrowed("main"),
+                },
+            ),
+            (
+                "--------- switch to system",
+                LogLine::Divider {
+                    buffer: Cow::Borrowed("system"),
+                },
+            ),
+            (
+                "--------- session 2: resumed after logcat exited",
+                LogLine::Continuation {
+                    text: Cow::Borrowed("--------- session 2: resumed after logcat exited"),
+                },
+            ),
+        ];
+
+        for (line, expected) in cases {
+            assert_eq!(parse_line(line), expected, "{:?}", line);
+        }
+    }
+}
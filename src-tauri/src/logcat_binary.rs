@@ -0,0 +1,368 @@
+use crate::event_tags::{self, EventTags};
+use crate::logcat::{LogLevel, LogRecord};
+use crate::timestamp;
+use serde::Serialize;
+use std::borrow::Cow;
+
+/// Size of the v1 `logger_entry` header, which has no `hdr_size` field.
+const V1_HEADER_SIZE: usize = 20;
+
+/// The buffer an entry was logged to, numbered as in liblog's `log_id_t`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogId {
+    Main,
+    Radio,
+    Events,
+    System,
+    Crash,
+    Stats,
+    Security,
+    Kernel,
+}
+
+impl LogId {
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(Self::Main),
+            1 => Some(Self::Radio),
+            2 => Some(Self::Events),
+            3 => Some(Self::System),
+            4 => Some(Self::Crash),
+            5 => Some(Self::Stats),
+            6 => Some(Self::Security),
+            7 => Some(Self::Kernel),
+            _ => None,
+        }
+    }
+
+    /// The name logcat uses for the buffer, as in `-b events`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Main => "main",
+            Self::Radio => "radio",
+            Self::Events => "events",
+            Self::System => "system",
+            Self::Crash => "crash",
+            Self::Stats => "stats",
+            Self::Security => "security",
+            Self::Kernel => "kernel",
+        }
+    }
+
+    /// Whether entries carry a binary event rather than priority, tag and
+    /// message text.
+    pub fn is_binary(self) -> bool {
+        matches!(self, Self::Events | Self::Stats | Self::Security)
+    }
+}
+
+/// One `logger_entry` as printed by `logcat -B`. Header fields a version
+/// does not have are `None`: v1 has neither log id nor uid, v2 has the
+/// writer's effective uid, v3 the log id and v4 both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub pid: i32,
+    pub tid: u32,
+    pub sec: u32,
+    pub nsec: u32,
+    pub log_id: Option<LogId>,
+    pub uid: Option<u32>,
+    pub payload: Vec<u8>,
+}
+
+impl LogEntry {
+    /// Nanoseconds since the Unix epoch.
+    pub fn timestamp(&self) -> i64 {
+        self.sec as i64 * 1_000_000_000 + self.nsec as i64
+    }
+
+    /// The entry as a record. Text payloads are `priority, tag\0,
+    /// message\0`; binary events are rendered with the names `tags` gives
+    /// them, or as their raw bytes in hex if they do not decode.
+    pub fn record(&self, tags: &EventTags) -> LogRecord<'_> {
+        let (level, tag, message) = if self.log_id.is_some_and(LogId::is_binary) {
+            let (tag, message) = match event_tags::decode_event(&self.payload) {
+                Some((number, value)) => tags.render(number, &value),
+                None => (
+                    String::new(),
+                    self.payload.iter().map(|b| format!("{:02x}", b)).collect(),
+                ),
+            };
+            (LogLevel::Info, Cow::Owned(tag), Cow::Owned(message))
+        } else {
+            let priority = self.payload.first().copied().unwrap_or(0);
+            let text = self.payload.get(1..).unwrap_or_default();
+            let (tag, rest) = split_nul(text);
+            let (message, _) = split_nul(rest);
+            (
+                level_of(priority),
+                String::from_utf8_lossy(tag),
+                String::from_utf8_lossy(message),
+            )
+        };
+
+        LogRecord {
+            timestamp: Some(self.timestamp()),
+            uid: self.uid.map(|uid| Cow::Owned(uid.to_string())),
+            pid: Some(self.pid as u32),
+            tid: Some(self.tid),
+            level,
+            tag: Some(tag),
+            message,
+        }
+    }
+
+    /// The entry as `threadtime` lines with the `year`, `zone`, `nsec` and
+    /// `uid` modifiers in UTC, one per line of the message, which is how
+    /// logcat prints multi-line messages too.
+    pub fn to_lines(&self, tags: &EventTags) -> Vec<String> {
+        let record = self.record(tags);
+        let mut header = format!("{} +0000 ", timestamp::format_dated(self.timestamp()));
+        if let Some(uid) = self.uid {
+            header.push_str(&format!("{:5} ", uid));
+        }
+        header.push_str(&format!(
+            "{:5} {:5} {} {}: ",
+            self.pid,
+            self.tid,
+            record.level.as_char(),
+            record.tag.as_deref().unwrap_or_default()
+        ));
+
+        let message = record.message.trim_end_matches('\n');
+        message
+            .split('\n')
+            .map(|line| format!("{}{}", header, line.trim_end_matches('\r')))
+            .collect()
+    }
+}
+
+/// Decodes the `logcat -B` byte stream, which may arrive in chunks that
+/// split entries anywhere.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    pending: Vec<u8>,
+}
+
+impl Decoder {
+    /// Appends `bytes` and returns the entries they complete.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Vec<LogEntry>, String> {
+        self.pending.extend_from_slice(bytes);
+
+        let mut entries = Vec::new();
+        let mut at = 0;
+        while let Some((entry, len)) = decode_entry(&self.pending[at..])? {
+            entries.push(entry);
+            at += len;
+        }
+        self.pending.drain(..at);
+        Ok(entries)
+    }
+
+    /// Number of bytes received that do not make up a whole entry yet.
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Decodes the entry at the start of `bytes`, returning it and its length,
+/// or `None` if `bytes` ends before it does.
+fn decode_entry(bytes: &[u8]) -> Result<Option<(LogEntry, usize)>, String> {
+    if bytes.len() < 4 {
+        return Ok(None);
+    }
+    let payload_len = u16_at(bytes, 0) as usize;
+    let header_len = match u16_at(bytes, 2) as usize {
+        0 => V1_HEADER_SIZE,
+        size if size >= V1_HEADER_SIZE => size,
+        size => return Err(format!("Invalid logger_entry header size {}", size)),
+    };
+    let len = header_len + payload_len;
+    if bytes.len() < len {
+        return Ok(None);
+    }
+
+    // v2 and v3 headers are both 24 bytes; v2 has a uid where v3 has the
+    // log id. Log ids are small, so anything larger is taken as a uid.
+    let (log_id, uid) = match header_len {
+        24..=27 => {
+            let value = u32_at(bytes, 20);
+            match LogId::from_id(value) {
+                Some(id) => (Some(id), None),
+                None => (None, Some(value)),
+            }
+        }
+        28.. => (LogId::from_id(u32_at(bytes, 20)), Some(u32_at(bytes, 24))),
+        _ => (None, None),
+    };
+
+    let entry = LogEntry {
+        pid: u32_at(bytes, 4) as i32,
+        tid: u32_at(bytes, 8),
+        sec: u32_at(bytes, 12),
+        nsec: u32_at(bytes, 16),
+        log_id,
+        uid,
+        payload: bytes[header_len..len].to_vec(),
+    };
+    Ok(Some((entry, len)))
+}
+
+/// Maps an `android_LogPriority` to a level. Unknown and default
+/// priorities count as verbose; silent is never logged.
+fn level_of(priority: u8) -> LogLevel {
+    match priority {
+        3 => LogLevel::Debug,
+        4 => LogLevel::Info,
+        5 => LogLevel::Warn,
+        6 => LogLevel::Error,
+        7.. => LogLevel::Fatal,
+        _ => LogLevel::Verbose,
+    }
+}
+
+/// Splits at the first NUL, dropping it.
+fn split_nul(bytes: &[u8]) -> (&[u8], &[u8]) {
+    match bytes.iter().position(|&b| b == 0) {
+        Some(nul) => (&bytes[..nul], &bytes[nul + 1..]),
+        None => (bytes, &[]),
+    }
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logcat::{self, LogLine};
+
+    /// v4: `I/ActivityManager` from pid 1234, tid 1250, uid 10123 in main at
+    /// 2024-01-02 03:04:05.123456789 UTC, with a two-line message.
+    const V4_ENTRY: &[u8] = &[
+        0x41, 0x00, 0x1c, 0x00, 0xd2, 0x04, 0x00, 0x00, 0xe2, 0x04, 0x00, 0x00, 0x25, 0x7d, 0x93,
+        0x65, 0x15, 0xcd, 0x5b, 0x07, 0x00, 0x00, 0x00, 0x00, 0x8b, 0x27, 0x00, 0x00, 0x04, 0x41,
+        0x63, 0x74, 0x69, 0x76, 0x69, 0x74, 0x79, 0x4d, 0x61, 0x6e, 0x61, 0x67, 0x65, 0x72, 0x00,
+        0x53, 0x74, 0x61, 0x72, 0x74, 0x20, 0x70, 0x72, 0x6f, 0x63, 0x20, 0x34, 0x33, 0x32, 0x31,
+        0x3a, 0x63, 0x6f, 0x6d, 0x2e, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2f, 0x75, 0x30,
+        0x61, 0x31, 0x32, 0x0a, 0x66, 0x6f, 0x72, 0x20, 0x61, 0x63, 0x74, 0x69, 0x76, 0x69, 0x74,
+        0x79, 0x0a, 0x00,
+    ];
+
+    /// v1: `E/DEBUG: Abort message` from pid 99, tid 100 at
+    /// 2010-01-01 00:00:00.005 UTC.
+    const V1_ENTRY: &[u8] = &[
+        0x15, 0x00, 0x00, 0x00, 0x63, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x00, 0x00, 0x3b, 0x3d,
+        0x4b, 0x40, 0x4b, 0x4c, 0x00, 0x06, 0x44, 0x45, 0x42, 0x55, 0x47, 0x00, 0x41, 0x62, 0x6f,
+        0x72, 0x74, 0x20, 0x6d, 0x65, 0x73, 0x73, 0x61, 0x67, 0x65, 0x00,
+    ];
+
+    /// v3: `W/WindowManager` from pid 1000, tid 1011 in the system buffer.
+    const V3_ENTRY: &[u8] = &[
+        0x21, 0x00, 0x18, 0x00, 0xe8, 0x03, 0x00, 0x00, 0xf3, 0x03, 0x00, 0x00, 0x01, 0x3b, 0x3d,
+        0x4b, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x05, 0x57, 0x69, 0x6e, 0x64, 0x6f,
+        0x77, 0x4d, 0x61, 0x6e, 0x61, 0x67, 0x65, 0x72, 0x00, 0x57, 0x69, 0x6e, 0x64, 0x6f, 0x77,
+        0x20, 0x6c, 0x6f, 0x73, 0x74, 0x20, 0x66, 0x6f, 0x63, 0x75, 0x73, 0x00,
+    ];
+
+    /// v4: event tag 30001 in the events buffer, with two bytes of data.
+    const EVENT_ENTRY: &[u8] = &[
+        0x06, 0x00, 0x1c, 0x00, 0xf4, 0x01, 0x00, 0x00, 0xf5, 0x01, 0x00, 0x00, 0x26, 0x7d, 0x93,
+        0x65, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0xe8, 0x03, 0x00, 0x00, 0x31, 0x75,
+        0x00, 0x00, 0x03, 0x00,
+    ];
+
+    fn capture() -> Vec<u8> {
+        [V4_ENTRY, V1_ENTRY, V3_ENTRY, EVENT_ENTRY].concat()
+    }
+
+    #[test]
+    fn decodes_every_header_version() {
+        let tags = EventTags::parse("30001 am_empty (Values|4)");
+        let entries = Decoder::default().feed(&capture()).unwrap();
+        assert_eq!(entries.len(), 4);
+
+        let v4 = &entries[0];
+        assert_eq!((v4.pid, v4.tid, v4.uid), (1234, 1250, Some(10123)));
+        assert_eq!(v4.log_id, Some(LogId::Main));
+        assert_eq!(v4.timestamp(), 1_704_164_645_123_456_789);
+        let record = v4.record(&tags);
+        assert_eq!(record.level, LogLevel::Info);
+        assert_eq!(record.tag.as_deref(), Some("ActivityManager"));
+        assert_eq!(
+            record.message,
+            "Start proc 4321:com.example/u0a12\nfor activity\n"
+        );
+
+        let v1 = &entries[1];
+        assert_eq!((v1.pid, v1.tid, v1.log_id, v1.uid), (99, 100, None, None));
+        assert_eq!(v1.record(&tags).level, LogLevel::Error);
+        assert_eq!(v1.record(&tags).message, "Abort message");
+
+        let v3 = &entries[2];
+        assert_eq!((v3.log_id, v3.uid), (Some(LogId::System), None));
+        assert_eq!(v3.record(&tags).tag.as_deref(), Some("WindowManager"));
+
+        let event = &entries[3];
+        assert_eq!(event.log_id, Some(LogId::Events));
+        assert_eq!(event.record(&tags).tag.as_deref(), Some("am_empty"));
+        assert_eq!(event.record(&tags).message, "[Values=[]]");
+        let unnamed = event.record(&EventTags::default());
+        assert_eq!(unnamed.tag.as_deref(), Some("30001"));
+        assert_eq!(unnamed.message, "[]");
+    }
+
+    #[test]
+    fn decodes_entries_split_across_chunks() {
+        let bytes = capture();
+        let whole = Decoder::default().feed(&bytes).unwrap();
+
+        let mut decoder = Decoder::default();
+        let mut entries = Vec::new();
+        for chunk in bytes.chunks(7) {
+            entries.extend(decoder.feed(chunk).unwrap());
+        }
+        assert_eq!(entries, whole);
+        assert_eq!(decoder.pending_len(), 0);
+
+        let mut truncated = Decoder::default();
+        assert!(truncated.feed(&V4_ENTRY[..40]).unwrap().is_empty());
+        assert_eq!(truncated.pending_len(), 40);
+        assert!(Decoder::default().feed(&[1, 0, 5, 0]).is_err());
+    }
+
+    #[test]
+    fn formats_one_parsable_line_per_message_line() {
+        let entries = Decoder::default().feed(V4_ENTRY).unwrap();
+        let lines = entries[0].to_lines(&EventTags::default());
+        assert_eq!(
+            lines,
+            [
+                "2024-01-02 03:04:05.123456789 +0000 10123  1234  1250 I ActivityManager: \
+                 Start proc 4321:com.example/u0a12",
+                "2024-01-02 03:04:05.123456789 +0000 10123  1234  1250 I ActivityManager: \
+                 for activity",
+            ]
+        );
+
+        for (line, message) in lines
+            .iter()
+            .zip(["Start proc 4321:com.example/u0a12", "for activity"])
+        {
+            let LogLine::Record(record) = logcat::parse_line(line) else {
+                panic!("{} did not parse", line);
+            };
+            assert_eq!(record.timestamp, Some(entries[0].timestamp()));
+            assert_eq!(record.uid.as_deref(), Some("10123"));
+            assert_eq!((record.pid, record.tid), (Some(1234), Some(1250)));
+            assert_eq!(record.tag.as_deref(), Some("ActivityManager"));
+            assert_eq!(record.message, message);
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use crate::filter::{FilterEngine, FilterOutcome, FilterRun};
+use crate::indexer::{self, Indexer, MinimapBucket};
+use crate::timestamp;
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Times below this count from boot rather than from the epoch: about
+/// three years of uptime, and decades before the year that logcat's
+/// year-less times are placed in.
+const MAX_UPTIME: i64 = 100_000_000 * 1_000_000_000;
+
+/// What a source's times count from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Clock {
+    /// Kernel `[secs.micros]` and logcat `-v monotonic` times.
+    Boot,
+    Wall,
+}
+
+impl Clock {
+    fn of(time: i64) -> Self {
+        if time < MAX_UPTIME {
+            Self::Boot
+        } else {
+            Self::Wall
+        }
+    }
+}
+
+/// A line of the merged view: which source it comes from and its line
+/// number within that source.
+#[derive(Debug, Clone, Copy)]
+struct LineRef {
+    source: u32,
+    line: u32,
+}
+
+/// Several indexed files interleaved into one sequence ordered by the
+/// timestamp at the start of each line.
+///
+/// Positions in the merged sequence play the role of offsets: filtering
+/// returns the positions of matching lines, and the `*_from_positions`
+/// accessors read through such a list.
+pub struct MergedView {
+    sources: Vec<Indexer>,
+    order: Vec<LineRef>,
+}
+
+impl MergedView {
+    /// Merges already indexed `sources`. Lines without a timestamp inherit
+    /// the previous line's, so continuation lines stay with their entry;
+    /// lines within one source never change their relative order.
+    ///
+    /// Kernel and monotonic logcat times count from boot, so they only line
+    /// up with wall-clock times given `boot_time`, the wall-clock time the
+    /// device booted at. Without it, merging the two kinds is an error.
+    pub async fn build<F>(
+        sources: Vec<Indexer>,
+        boot_time: Option<i64>,
+        mut progress_callback: F,
+    ) -> Result<Self, String>
+    where
+        F: FnMut(f64),
+    {
+        if sources.iter().any(|s| s.line_count() > u32::MAX as usize) {
+            return Err("File has too many lines to merge".to_string());
+        }
+
+        let steps = (sources.len() + 1) as f64;
+        let mut timed = Vec::with_capacity(sources.len());
+        let mut timestamps = Vec::with_capacity(sources.len());
+        let mut clocks = Vec::with_capacity(sources.len());
+        for (i, source) in sources.into_iter().enumerate() {
+            let (source, (stamps, clock)) = tokio::task::spawn_blocking(move || {
+                let stamps = line_timestamps(&source, boot_time);
+                (source, stamps)
+            })
+            .await
+            .map_err(|e| format!("Failed to read timestamps: {}", e))?;
+            timed.push(source);
+            timestamps.push(stamps);
+            clocks.push(clock);
+            progress_callback((i + 1) as f64 / steps);
+        }
+
+        let boot = clocks.iter().position(|&c| c == Some(Clock::Boot));
+        let wall = clocks.iter().position(|&c| c == Some(Clock::Wall));
+        if let (None, Some(boot), Some(wall)) = (boot_time, boot, wall) {
+            return Err(format!(
+                "Cannot merge {} with {}: the first counts time from boot and the second \
+                 from the wall clock. Give the time the device booted to line them up",
+                timed[boot].file_path().display(),
+                timed[wall].file_path().display()
+            ));
+        }
+
+        let order = tokio::task::spawn_blocking(move || merge_order(&timestamps))
+            .await
+            .map_err(|e| format!("Failed to merge files: {}", e))?;
+
+        progress_callback(1.0);
+        Ok(Self {
+            sources: timed,
+            order,
+        })
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn source_paths(&self) -> Vec<String> {
+        self.sources
+            .iter()
+            .map(|s| s.file_path().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    pub fn get_line(&self, index: usize) -> Option<String> {
+        let r = self.order.get(index)?;
+        self.sources[r.source as usize].get_line(r.line as usize)
+    }
+
+    pub fn get_line_from_positions(&self, positions: &[usize], index: usize) -> Option<String> {
+        self.get_line(*positions.get(index)?)
+    }
+
+    /// Byte offset of line `index` within the file it came from.
+    pub fn line_offset(&self, index: usize) -> Option<usize> {
+        let r = self.order.get(index)?;
+        self.sources[r.source as usize].line_offset(r.line as usize)
+    }
+
+    /// Index into `source_paths` of the file line `index` came from.
+    pub fn source_of(&self, index: usize) -> Option<usize> {
+        self.order.get(index).map(|r| r.source as usize)
+    }
+
+    /// Returns the positions of all lines that pass the filters, with
+    /// per-filter match counts over them, or `None` if `run` is cancelled
+    /// first.
+    pub fn run_filters(&self, engine: &FilterEngine, run: &FilterRun) -> Option<FilterOutcome> {
+        engine.run_filter(
+            0..self.order.len(),
+            |i| self.get_line(i).unwrap_or_default(),
+            run,
+        )
+    }
+
+    /// Filters only the lines at `positions`, typically a previous result
+    /// that the engine narrows.
+    pub fn run_filters_within(
+        &self,
+        engine: &FilterEngine,
+        positions: &[usize],
+        run: &FilterRun,
+    ) -> Option<FilterOutcome> {
+        let mut outcome = engine.run_filter(
+            0..positions.len(),
+            |i| self.get_line(positions[i]).unwrap_or_default(),
+            run,
+        )?;
+        for visible in &mut outcome.visible {
+            *visible = positions[*visible];
+        }
+        Some(outcome)
+    }
+
+    pub fn get_minimap_data(
+        &self,
+        engine: &FilterEngine,
+        buckets: usize,
+        positions: Option<&[usize]>,
+    ) -> Vec<MinimapBucket> {
+        let total_lines = positions.map_or(self.order.len(), |p| p.len());
+
+        indexer::minimap_buckets(total_lines, buckets, |i| {
+            let index = positions.map_or(i, |p| p[i]);
+            self.get_line(index)
+                .and_then(|line| engine.get_match_color(&line))
+        })
+    }
+}
+
+/// The order of the merged lines: a k-way merge of the sources by time, in
+/// which ties go to the source listed first.
+fn merge_order(timestamps: &[Vec<i64>]) -> Vec<LineRef> {
+    let total: usize = timestamps.iter().map(Vec::len).sum();
+    let mut order = Vec::with_capacity(total);
+    let mut cursors = vec![0usize; timestamps.len()];
+    let mut heap = BinaryHeap::with_capacity(timestamps.len());
+
+    for (source, stamps) in timestamps.iter().enumerate() {
+        if let Some(&first) = stamps.first() {
+            heap.push(Reverse((first, source)));
+        }
+    }
+
+    while let Some(Reverse((_, source))) = heap.pop() {
+        let line = cursors[source];
+        order.push(LineRef {
+            source: source as u32,
+            line: line as u32,
+        });
+        cursors[source] += 1;
+        if let Some(&next) = timestamps[source].get(line + 1) {
+            heap.push(Reverse((next, source)));
+        }
+    }
+    order
+}
+
+/// Timestamp of every line of `source`, with untimed lines inheriting the
+/// previous line's, and the clock the times count from. Leading untimed
+/// lines take the first timestamp found. Times since boot are moved to the
+/// wall clock when `boot_time` is known.
+fn line_timestamps(source: &Indexer, boot_time: Option<i64>) -> (Vec<i64>, Option<Clock>) {
+    let parsed: Vec<Option<i64>> = (0..source.line_count())
+        .into_par_iter()
+        .map(|i| {
+            let time = source
+                .line_bytes(i)
+                .and_then(timestamp::parse_line_timestamp)?;
+            Some(match boot_time {
+                Some(boot_time) if Clock::of(time) == Clock::Boot => boot_time + time,
+                _ => time,
+            })
+        })
+        .collect();
+
+    let first = parsed.iter().flatten().next().copied();
+    let clock = first.map(Clock::of);
+
+    let mut last = first.unwrap_or(i64::MIN);
+    let stamps = parsed
+        .into_iter()
+        .map(|t| {
+            if let Some(t) = t {
+                last = t;
+            }
+            last
+        })
+        .collect();
+    (stamps, clock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    async fn merge_at(
+        files: &[&str],
+        boot_time: Option<i64>,
+    ) -> (Result<MergedView, String>, Vec<NamedTempFile>) {
+        let mut temps = Vec::new();
+        let mut sources = Vec::new();
+        for contents in files {
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(contents.as_bytes()).unwrap();
+            let mut indexer = Indexer::new(file.path().to_path_buf()).unwrap();
+            indexer.index(|_| {}).await;
+            sources.push(indexer);
+            temps.push(file);
+        }
+        (MergedView::build(sources, boot_time, |_| {}).await, temps)
+    }
+
+    async fn merge(files: &[&str]) -> (MergedView, Vec<NamedTempFile>) {
+        let (view, temps) = merge_at(files, None).await;
+        (view.unwrap(), temps)
+    }
+
+    fn lines(view: &MergedView) -> Vec<(usize, String)> {
+        (0..view.line_count())
+            .map(|i| (view.source_of(i).unwrap(), view.get_line(i).unwrap()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn interleaves_sources_by_time() {
+        let main = "\
+01-02 03:04:05.000  100  100 I A: main 1
+01-02 03:04:07.000  100  100 I A: main 2
+java.lang.RuntimeException: boom
+\tat com.foo.Bar.run(Bar.java:12)
+01-02 03:04:09.000  100  100 I A: main 3
+";
+        let system = "\
+01-02 03:04:06.000  200  200 I B: system 1
+01-02 03:04:07.000  200  200 I B: system 2
+01-02 03:04:08.000  200  200 I B: system 3
+";
+        let (view, _files) = merge(&[main, system]).await;
+
+        let expected = [
+            (0, "01-02 03:04:05.000  100  100 I A: main 1"),
+            (1, "01-02 03:04:06.000  200  200 I B: system 1"),
+            // Ties go to the first source, and the untimed lines after
+            // main 2 stay with it.
+            (0, "01-02 03:04:07.000  100  100 I A: main 2"),
+            (0, "java.lang.RuntimeException: boom"),
+            (0, "\tat com.foo.Bar.run(Bar.java:12)"),
+            (1, "01-02 03:04:07.000  200  200 I B: system 2"),
+            (1, "01-02 03:04:08.000  200  200 I B: system 3"),
+            (0, "01-02 03:04:09.000  100  100 I A: main 3"),
+        ];
+        let expected: Vec<(usize, String)> =
+            expected.iter().map(|&(s, l)| (s, l.to_string())).collect();
+        assert_eq!(lines(&view), expected);
+        assert_eq!(view.line_offset(3), Some(82));
+    }
+
+    #[tokio::test]
+    async fn untimed_lines_inherit_the_nearest_time() {
+        // Lines before the first timestamp take that timestamp; a file
+        // without any keeps its order and sorts first.
+        let headed = "\
+--------- beginning of main
+01-02 03:04:06.000  100  100 I A: first
+";
+        let untimed = "no time here\nnor here\n";
+        let timed = "01-02 03:04:05.000  200  200 I B: early\n";
+        let (view, _files) = merge(&[headed, untimed, timed]).await;
+
+        let order: Vec<&str> = [
+            "no time here",
+            "nor here",
+            "01-02 03:04:05.000  200  200 I B: early",
+        ]
+        .into_iter()
+        .chain([
+            "--------- beginning of main",
+            "01-02 03:04:06.000  100  100 I A: first",
+        ])
+        .collect();
+        let merged: Vec<String> = lines(&view).into_iter().map(|(_, l)| l).collect();
+        assert_eq!(merged, order);
+    }
+
+    #[tokio::test]
+    async fn merges_kernel_logs_with_monotonic_logcat() {
+        let kernel = "\
+<6>[    1.500000] init: starting
+[    3.250000] usb 1-1: new device
+";
+        let logcat = "\
+     2.000   100   100 I A: between
+     4.000   100   100 I A: after
+";
+        let (view, _files) = merge(&[kernel, logcat]).await;
+
+        let sources: Vec<usize> = lines(&view).into_iter().map(|(s, _)| s).collect();
+        assert_eq!(sources, [0, 1, 0, 1]);
+    }
+
+    #[tokio::test]
+    async fn lines_up_boot_and_wall_clock_times_by_the_boot_time() {
+        let kernel = "\
+[    1.000000] init: starting
+[   90.000000] usb 1-1: new device
+";
+        let logcat = "\
+01-02 03:04:30.000   100   100 I A: before usb
+01-02 03:06:00.000   100   100 I A: after usb
+";
+        let (merged, _files) = merge_at(&[kernel, logcat], None).await;
+        let error = merged.err().unwrap();
+        assert!(error.starts_with("Cannot merge"), "{}", error);
+
+        let boot_time = timestamp::parse_line_timestamp(b"01-02 03:04:00.000");
+        let (view, _files) = merge_at(&[kernel, logcat], boot_time).await;
+        let sources: Vec<usize> = lines(&view.unwrap()).into_iter().map(|(s, _)| s).collect();
+        assert_eq!(sources, [0, 1, 0, 1]);
+
+        // Untimed files go with either clock.
+        let (view, _files) = merge_at(&[kernel, "no time here\n"], None).await;
+        assert_eq!(view.unwrap().line_count(), 3);
+    }
+}
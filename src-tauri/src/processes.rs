@@ -0,0 +1,247 @@
+use crate::logcat;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// A process that held a pid from `since` until the next one took it over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Holder {
+    /// Nanoseconds, as in `LogRecord::timestamp`.
+    since: i64,
+    name: String,
+    /// Whether `since` is when the process started rather than when a
+    /// listing first showed it running.
+    started: bool,
+}
+
+/// Which process held each pid over the course of a log, learned from
+/// ActivityManager `Start proc` lines and `ps -A` listings. Pids get reused
+/// during long captures, so each pid keeps the processes that held it in
+/// time order and a line resolves to the one that held its pid at the
+/// line's time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessMap {
+    pids: HashMap<u32, Vec<Holder>>,
+}
+
+impl ProcessMap {
+    /// Builds a map from the `Start proc` lines among `count` lines fetched
+    /// through `line_at`.
+    pub fn collect<F, S>(count: usize, line_at: F) -> Self
+    where
+        F: Fn(usize) -> S + Sync,
+        S: AsRef<str>,
+    {
+        let starts: Vec<(Option<i64>, u32, String)> = (0..count)
+            .into_par_iter()
+            .filter_map(|i| start_of(line_at(i).as_ref()))
+            .collect();
+
+        let mut map = Self::default();
+        for (time, pid, name) in starts {
+            map.started(pid, &name, time);
+        }
+        map
+    }
+
+    /// Records the processes started by the `Start proc` lines among
+    /// `lines`. Returns whether the map changed.
+    pub fn scan<S: AsRef<str>>(&mut self, lines: &[S]) -> bool {
+        let mut changed = false;
+        for (time, pid, name) in lines.iter().filter_map(|l| start_of(l.as_ref())) {
+            changed |= self.started(pid, &name, time);
+        }
+        changed
+    }
+
+    /// Records that `pid` was started as `name` at `time`. Without a time
+    /// the start is taken to follow everything known so far.
+    pub fn started(&mut self, pid: u32, name: &str, time: Option<i64>) -> bool {
+        let holders = self.pids.entry(pid).or_default();
+        let since = time
+            .or_else(|| holders.last().map(|h| h.since))
+            .unwrap_or(i64::MIN);
+        insert(
+            holders,
+            Holder {
+                since,
+                name: name.to_string(),
+                started: true,
+            },
+        )
+    }
+
+    /// Records the processes in a `ps -A` listing taken at `time`, the time
+    /// of the latest line logged before it. Pids whose holder at that time
+    /// has the same name are left alone. Returns whether the map changed.
+    pub fn observe(&mut self, listing: &str, time: Option<i64>) -> bool {
+        let mut changed = false;
+        for (pid, name) in parse_ps(listing) {
+            if self.name(pid, time) == Some(name) {
+                continue;
+            }
+            let holders = self.pids.entry(pid).or_default();
+            let since = time
+                .or_else(|| holders.last().map(|h| h.since))
+                .unwrap_or(i64::MIN);
+            changed |= insert(
+                holders,
+                Holder {
+                    since,
+                    name: name.to_string(),
+                    started: false,
+                },
+            );
+        }
+        changed
+    }
+
+    /// The name of the process that held `pid` at `time`, or that holds it
+    /// last when the time is unknown. A process first seen in a listing is
+    /// assumed to have held the pid since before anything else was known.
+    pub fn name(&self, pid: u32, time: Option<i64>) -> Option<&str> {
+        let holders = self.pids.get(&pid)?;
+        let holder = match time {
+            None => holders.last(),
+            Some(time) => match holders.partition_point(|h| h.since <= time) {
+                0 => holders.first().filter(|h| !h.started),
+                n => holders.get(n - 1),
+            },
+        };
+        holder.map(|h| h.name.as_str())
+    }
+}
+
+/// Adds `holder` after the holders since the same time or earlier, unless
+/// the one it would follow is the same process.
+fn insert(holders: &mut Vec<Holder>, holder: Holder) -> bool {
+    let at = holders.partition_point(|h| h.since <= holder.since);
+    if at > 0 && holders[at - 1].name == holder.name {
+        return false;
+    }
+    holders.insert(at, holder);
+    true
+}
+
+/// The time, pid and process name of a `Start proc` line.
+fn start_of(line: &str) -> Option<(Option<i64>, u32, String)> {
+    if !line.contains("Start proc ") {
+        return None;
+    }
+    let parsed = logcat::parse_line(line);
+    let record = parsed.record()?;
+    let (pid, name) = logcat::parse_start_proc(&record.message)?;
+    Some((record.timestamp, pid, name.to_string()))
+}
+
+/// The package a process belongs to: its name up to the `:` that starts
+/// the suffix of a secondary process such as `com.foo:remote`.
+pub fn package_of(process: &str) -> &str {
+    process.split(':').next().unwrap_or(process)
+}
+
+/// Parses the pids and names out of a `ps` listing. The pid column is found
+/// from the header, as toybox and the older toolbox `ps` order their
+/// columns differently; the name is the last column in both.
+pub fn parse_ps(listing: &str) -> Vec<(u32, &str)> {
+    let mut lines = listing.lines();
+    let Some(pid_column) = lines
+        .by_ref()
+        .find_map(|line| line.split_whitespace().position(|c| c == "PID"))
+    else {
+        return Vec::new();
+    };
+
+    lines
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            let pid = columns.get(pid_column)?.parse().ok()?;
+            let name = *columns.last()?;
+            (columns.len() > pid_column + 1).then_some((pid, name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp;
+
+    fn at(time: &str) -> Option<i64> {
+        timestamp::parse_line_timestamp(time.as_bytes())
+    }
+
+    const PS: &str = "\
+USER           PID  PPID     VSZ    RSS WCHAN            ADDR S NAME
+root             1     0 10782796  9440 do_epoll_wait       0 S init
+system         600     1 14586168 245876 do_epoll_wait      0 S system_server
+u0_a123       4321   600 13993252 112340 do_epoll_wait      0 S com.example.app:remote
+";
+
+    #[test]
+    fn parses_ps_listings() {
+        assert_eq!(
+            parse_ps(PS),
+            vec![
+                (1, "init"),
+                (600, "system_server"),
+                (4321, "com.example.app:remote")
+            ]
+        );
+
+        let toolbox = "\
+USER     PID   PPID  VSIZE  RSS     WCHAN    PC         NAME
+root      1     0     640    496   c00bd520 00019fb8 S /init
+u0_a45    1234  87    512000 40000 ffffffff 00000000 S com.android.phone
+";
+        assert_eq!(
+            parse_ps(toolbox),
+            vec![(1, "/init"), (1234, "com.android.phone")]
+        );
+        assert!(parse_ps("/system/bin/sh: ps: not found\n").is_empty());
+        assert_eq!(package_of("com.example.app:remote"), "com.example.app");
+    }
+
+    #[test]
+    fn resolves_reused_pids_by_time() {
+        let lines = [
+            "01-02 03:04:05.000   600   620 I ActivityManager: Start proc 4321:com.first/u0a12 for activity {com.first/.Main}",
+            "01-02 03:04:06.000  4321  4321 I first: hello",
+            "01-02 03:10:00.000   600   620 I ActivityManager: Start proc 4321:com.second:sync/u0a13 for service {com.second/.Sync}",
+        ];
+        let mut map = ProcessMap::collect(lines.len(), |i| lines[i]);
+
+        assert_eq!(map.name(4321, at("01-02 03:04:04.999")), None);
+        assert_eq!(map.name(4321, at("01-02 03:04:06.000")), Some("com.first"));
+        assert_eq!(
+            map.name(4321, at("01-02 03:10:01.000")),
+            Some("com.second:sync")
+        );
+        assert_eq!(map.name(4321, None), Some("com.second:sync"));
+
+        // A listing backs up what is known and fills in the rest, from
+        // before the first line for processes that were already running.
+        assert!(!map.observe("PID NAME\n4321 com.second:sync\n", at("01-02 03:11:00.000")));
+        assert!(map.observe(PS, at("01-02 03:11:00.000")));
+        assert_eq!(
+            map.name(600, at("01-01 00:00:00.000")),
+            Some("system_server")
+        );
+        assert_eq!(
+            map.name(4321, at("01-02 03:11:00.000")),
+            Some("com.example.app:remote")
+        );
+        assert_eq!(
+            map.name(4321, at("01-02 03:10:30.000")),
+            Some("com.second:sync")
+        );
+
+        let restart = ["01-02 04:00:00.000   600   620 I ActivityManager: Start proc 600:com.other/u0a14 for service {com.other/.Svc}"];
+        assert!(map.scan(&restart));
+        assert!(!map.scan(&restart));
+        assert_eq!(
+            map.name(600, at("01-02 03:59:59.000")),
+            Some("system_server")
+        );
+        assert_eq!(map.name(600, at("01-02 04:00:00.000")), Some("com.other"));
+    }
+}
@@ -0,0 +1,635 @@
+use crate::filter::FilterField;
+use crate::logcat::LogLevel;
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::cmp::Ordering;
+
+/// A parsed filter query.
+///
+/// Precedence from tightest to loosest is `NOT`, `AND` (which may be left
+/// out between terms) and `OR`, so `a OR b NOT c` reads as
+/// `a OR (b AND NOT c)`.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// A literal or regex matched against a field, or the whole line when
+    /// `field` is `None`.
+    Match {
+        field: Option<FilterField>,
+        regex: Regex,
+    },
+    Level {
+        comparison: Comparison,
+        level: LogLevel,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+}
+
+impl Comparison {
+    /// Whether `ordering`, the result of comparing a value against the
+    /// query's operand, satisfies the comparison.
+    pub fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Self::Lt => ordering == Ordering::Less,
+            Self::Le => ordering != Ordering::Greater,
+            Self::Eq => ordering == Ordering::Equal,
+            Self::Ge => ordering != Ordering::Less,
+            Self::Gt => ordering == Ordering::Greater,
+        }
+    }
+}
+
+/// Why a query failed to parse, with the character range to underline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QueryError {
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at position {}", self.message, self.start)
+    }
+}
+
+impl Expr {
+    /// Whether evaluating the expression needs the line parsed into a
+    /// logcat record.
+    pub fn uses_fields(&self) -> bool {
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => a.uses_fields() || b.uses_fields(),
+            Expr::Not(e) => e.uses_fields(),
+            Expr::Match { field, .. } => field.is_some(),
+            Expr::Level { .. } => true,
+        }
+    }
+
+    pub fn uses_field(&self, target: &FilterField) -> bool {
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => a.uses_field(target) || b.uses_field(target),
+            Expr::Not(e) => e.uses_field(target),
+            Expr::Match { field, .. } => field.as_ref() == Some(target),
+            Expr::Level { .. } => false,
+        }
+    }
+
+    /// Whether the expression refers to a field of an events-buffer entry.
+    pub fn uses_event_fields(&self) -> bool {
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => a.uses_event_fields() || b.uses_event_fields(),
+            Expr::Not(e) => e.uses_event_fields(),
+            Expr::Match { field, .. } => matches!(field, Some(FilterField::Event(_))),
+            Expr::Level { .. } => false,
+        }
+    }
+}
+
+/// Parses a query such as
+/// `(tag:ActivityManager AND "Start proc") OR level>=E NOT tag:chatty`.
+///
+/// Terms are bare words, `"quoted literals"` or `/regex/` (with an optional
+/// `i` flag), optionally prefixed by `tag:`, `pid:`, `tid:`, `level:`,
+/// `message:` (`msg:`), `package:` (`pkg:`), `process:` (`proc:`) or
+/// `event.<field>:` for a named field of an events-buffer entry, such as
+/// `event.process_name:`. Literals match case-insensitively as substrings,
+/// except against pid, tid and level where they must equal the whole value.
+/// Any other `name:` before a quoted literal is an error, while an unquoted
+/// `name:value` is searched for as written. `level` also takes `<`, `<=`,
+/// `=`, `>=` and `>`. Returns `Ok(None)` for a blank query.
+pub fn parse(query: &str) -> Result<Option<Expr>, QueryError> {
+    let tokens = tokenize(query)?;
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+
+    let mut parser = Parser {
+        query,
+        tokens,
+        pos: 0,
+    };
+    let expr = parser.parse_or()?;
+    if let Some(token) = parser.peek() {
+        return Err(parser.error_at(token, "Unexpected ')'"));
+    }
+    Ok(Some(expr))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Word(String),
+    Quoted(String),
+    Regex { pattern: String, ignore_case: bool },
+    Field(FilterField),
+    LevelOp(Comparison),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let kind = match c {
+            '(' => {
+                chars.next();
+                TokenKind::LParen
+            }
+            ')' => {
+                chars.next();
+                TokenKind::RParen
+            }
+            '"' | '/' => {
+                chars.next();
+                let mut text = String::new();
+                let mut closed = false;
+                while let Some((_, ch)) = chars.next() {
+                    if ch == c {
+                        closed = true;
+                        break;
+                    }
+                    if ch == '\\' {
+                        // Keep escapes other than the delimiter for the regex.
+                        match chars.next() {
+                            Some((_, next)) if next == c || (c == '"' && next == '\\') => {
+                                text.push(next)
+                            }
+                            Some((_, next)) => {
+                                text.push('\\');
+                                text.push(next);
+                            }
+                            None => text.push('\\'),
+                        }
+                        continue;
+                    }
+                    text.push(ch);
+                }
+                if !closed {
+                    let what = if c == '"' { "quoted literal" } else { "regex" };
+                    return Err(error(
+                        query,
+                        start,
+                        query.len(),
+                        format!("Unterminated {}", what),
+                    ));
+                }
+                if c == '"' {
+                    TokenKind::Quoted(text)
+                } else {
+                    let mut ignore_case = false;
+                    while let Some(&(_, 'i')) = chars.peek() {
+                        chars.next();
+                        ignore_case = true;
+                    }
+                    TokenKind::Regex {
+                        pattern: text,
+                        ignore_case,
+                    }
+                }
+            }
+            _ => {
+                let rest = &query[start..];
+                if let Some((kind, len)) = field_prefix(rest) {
+                    for _ in rest[..len].chars() {
+                        chars.next();
+                    }
+                    kind
+                } else {
+                    let mut word = String::new();
+                    while let Some(&(_, ch)) = chars.peek() {
+                        if ch.is_whitespace() || matches!(ch, '(' | ')' | '"') {
+                            break;
+                        }
+                        word.push(ch);
+                        chars.next();
+                    }
+                    // `name:"value"` can only be meant as a field, while a
+                    // bare `name:value` may be text such as `Error:42`.
+                    if let Some(name) = word.strip_suffix(':') {
+                        let quoted = chars.peek().is_some_and(|&(_, ch)| ch == '"');
+                        if quoted
+                            && !name.is_empty()
+                            && name.chars().all(|c| c.is_ascii_alphabetic())
+                        {
+                            return Err(error(
+                                query,
+                                start,
+                                start + name.len(),
+                                format!("Unknown field '{}'", name),
+                            ));
+                        }
+                    }
+                    match word.as_str() {
+                        "AND" => TokenKind::And,
+                        "OR" => TokenKind::Or,
+                        "NOT" => TokenKind::Not,
+                        _ => TokenKind::Word(word),
+                    }
+                }
+            }
+        };
+
+        let end = chars.peek().map_or(query.len(), |&(i, _)| i);
+        tokens.push(Token { kind, start, end });
+    }
+
+    Ok(tokens)
+}
+
+/// Recognizes `field:` and the `level` comparison operators at the start of
+/// `s`, returning the token and its length in bytes.
+fn field_prefix(s: &str) -> Option<(TokenKind, usize)> {
+    let name_len = s
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    let name = s[..name_len].to_ascii_lowercase();
+    let rest = &s[name_len..];
+
+    if name == "level" {
+        for (op, comparison) in [
+            (">=", Comparison::Ge),
+            ("<=", Comparison::Le),
+            (">", Comparison::Gt),
+            ("<", Comparison::Lt),
+            ("=", Comparison::Eq),
+            (":", Comparison::Eq),
+        ] {
+            if rest.starts_with(op) {
+                return Some((TokenKind::LevelOp(comparison), name_len + op.len()));
+            }
+        }
+        return None;
+    }
+
+    if name == "event" {
+        let key_len = rest
+            .strip_prefix('.')?
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len() - 1);
+        if key_len == 0 || !rest[1 + key_len..].starts_with(':') {
+            return None;
+        }
+        let field = FilterField::Event(rest[1..1 + key_len].to_string());
+        return Some((TokenKind::Field(field), name_len + key_len + 2));
+    }
+
+    if !rest.starts_with(':') {
+        return None;
+    }
+    let field = match name.as_str() {
+        "tag" => FilterField::Tag,
+        "pid" => FilterField::Pid,
+        "tid" => FilterField::Tid,
+        "message" | "msg" => FilterField::Message,
+        "package" | "pkg" => FilterField::Package,
+        "process" | "proc" => FilterField::Process,
+        _ => return None,
+    };
+    Some((TokenKind::Field(field), name_len + 1))
+}
+
+struct Parser<'a> {
+    query: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut left = self.parse_and()?;
+        while self.peek().is_some_and(|t| t.kind == TokenKind::Or) {
+            self.next();
+            let right = self.parse_and()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            match self.peek().map(|t| &t.kind) {
+                Some(TokenKind::And) => {
+                    self.next();
+                }
+                Some(TokenKind::Or | TokenKind::RParen) | None => break,
+                // Juxtaposed terms are an implicit AND.
+                Some(_) => {}
+            }
+            let right = self.parse_unary()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, QueryError> {
+        if self.peek().is_some_and(|t| t.kind == TokenKind::Not) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, QueryError> {
+        let Some(token) = self.next() else {
+            return Err(self.error_at_end("Unexpected end of query"));
+        };
+
+        match token.kind {
+            TokenKind::LParen => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(t) if t.kind == TokenKind::RParen => Ok(expr),
+                    Some(t) => Err(self.error_at(&t, "Expected ')'")),
+                    None => Err(error(
+                        self.query,
+                        token.start,
+                        token.end,
+                        "Unclosed '('".to_string(),
+                    )),
+                }
+            }
+            TokenKind::Field(field) => {
+                let value = self.next().filter(|t| is_value(&t.kind));
+                match value {
+                    Some(value) => self.term(Some(field), &value),
+                    None => Err(error(
+                        self.query,
+                        token.start,
+                        token.end,
+                        "Expected a value after the field".to_string(),
+                    )),
+                }
+            }
+            TokenKind::LevelOp(comparison) => {
+                let level = match self.next() {
+                    Some(Token {
+                        kind: TokenKind::Word(word),
+                        ..
+                    }) => parse_level(&word),
+                    _ => None,
+                };
+                match level {
+                    Some(level) => Ok(Expr::Level { comparison, level }),
+                    None => Err(error(
+                        self.query,
+                        token.start,
+                        self.tokens.get(self.pos - 1).map_or(token.end, |t| t.end),
+                        "Expected a level (V, D, I, W, E or F)".to_string(),
+                    )),
+                }
+            }
+            TokenKind::Word(_) | TokenKind::Quoted(_) | TokenKind::Regex { .. } => {
+                self.term(None, &token)
+            }
+            TokenKind::RParen => Err(self.error_at(&token, "Unexpected ')'")),
+            TokenKind::And | TokenKind::Or => {
+                Err(self.error_at(&token, "Expected a term before the operator"))
+            }
+            TokenKind::Not => unreachable!("NOT is handled by parse_unary"),
+        }
+    }
+
+    fn term(&self, field: Option<FilterField>, token: &Token) -> Result<Expr, QueryError> {
+        let exact = matches!(field, Some(FilterField::Pid | FilterField::Tid));
+        let regex = match &token.kind {
+            TokenKind::Word(text) | TokenKind::Quoted(text) => {
+                let escaped = regex::escape(text);
+                let pattern = if exact {
+                    format!("^{}$", escaped)
+                } else {
+                    escaped
+                };
+                RegexBuilder::new(&pattern).case_insensitive(true).build()
+            }
+            TokenKind::Regex {
+                pattern,
+                ignore_case,
+            } => RegexBuilder::new(pattern)
+                .case_insensitive(*ignore_case)
+                .build(),
+            _ => unreachable!("term called with a non-value token"),
+        };
+
+        let regex = regex.map_err(|e| {
+            error(
+                self.query,
+                token.start,
+                token.end,
+                // The last line of the regex error holds the reason.
+                format!(
+                    "Invalid regex: {}",
+                    e.to_string()
+                        .lines()
+                        .last()
+                        .unwrap_or_default()
+                        .trim_start_matches("error: ")
+                ),
+            )
+        })?;
+        Ok(Expr::Match { field, regex })
+    }
+
+    fn error_at(&self, token: &Token, message: &str) -> QueryError {
+        error(self.query, token.start, token.end, message.to_string())
+    }
+
+    fn error_at_end(&self, message: &str) -> QueryError {
+        error(
+            self.query,
+            self.query.len(),
+            self.query.len(),
+            message.to_string(),
+        )
+    }
+}
+
+fn is_value(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Word(_) | TokenKind::Quoted(_) | TokenKind::Regex { .. }
+    )
+}
+
+fn parse_level(word: &str) -> Option<LogLevel> {
+    match word.to_ascii_lowercase().as_str() {
+        "verbose" => Some(LogLevel::Verbose),
+        "debug" => Some(LogLevel::Debug),
+        "info" => Some(LogLevel::Info),
+        "warn" | "warning" => Some(LogLevel::Warn),
+        "error" => Some(LogLevel::Error),
+        "fatal" | "assert" => Some(LogLevel::Fatal),
+        _ => {
+            let mut chars = word.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => LogLevel::from_char(c.to_ascii_uppercase()),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Builds an error from byte offsets, reporting them as character offsets
+/// so the frontend can underline the range directly.
+fn error(query: &str, start: usize, end: usize, message: String) -> QueryError {
+    let to_chars = |byte: usize| query[..byte].chars().count();
+    QueryError {
+        start: to_chars(start),
+        end: to_chars(end),
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders the parsed tree with explicit parentheses.
+    fn show(query: &str) -> String {
+        fn render(expr: &Expr) -> String {
+            match expr {
+                Expr::And(a, b) => format!("({} AND {})", render(a), render(b)),
+                Expr::Or(a, b) => format!("({} OR {})", render(a), render(b)),
+                Expr::Not(e) => format!("NOT {}", render(e)),
+                Expr::Match { field: None, regex } => regex.as_str().to_string(),
+                Expr::Match {
+                    field: Some(field),
+                    regex,
+                } => format!("{:?}:{}", field, regex.as_str()),
+                Expr::Level { comparison, level } => format!("level {:?} {:?}", comparison, level),
+            }
+        }
+        render(&parse(query).unwrap().unwrap())
+    }
+
+    fn parse_error(query: &str) -> (usize, usize, String) {
+        let e = parse(query).unwrap_err();
+        (e.start, e.end, e.message)
+    }
+
+    fn matcher(query: &str) -> Regex {
+        match parse(query).unwrap().unwrap() {
+            Expr::Match { regex, .. } => regex,
+            expr => panic!("{:?} is not a single term", expr),
+        }
+    }
+
+    #[test]
+    fn binds_not_then_and_then_or() {
+        assert_eq!(show("a OR b NOT c"), "(a OR (b AND NOT c))");
+        assert_eq!(show("a b OR c AND d"), "((a AND b) OR (c AND d))");
+        assert_eq!(show("NOT NOT a OR b"), "(NOT NOT a OR b)");
+        assert_eq!(show("a OR b OR c"), "((a OR b) OR c)");
+        assert_eq!(show("(a OR b) c"), "((a OR b) AND c)");
+        assert_eq!(show("NOT (a OR (b c))"), "NOT (a OR (b AND c))");
+        assert!(parse("  ").unwrap().is_none());
+    }
+
+    #[test]
+    fn parses_field_prefixes() {
+        assert_eq!(show("tag:ActivityManager"), "Tag:ActivityManager");
+        assert_eq!(show("TAG:am pid:42"), "(Tag:am AND Pid:^42$)");
+        assert_eq!(show("msg:a message:b"), "(Message:a AND Message:b)");
+        assert_eq!(show("pkg:com.foo"), r"Package:com\.foo");
+        assert_eq!(show("proc:x OR process:y"), "(Process:x OR Process:y)");
+        assert_eq!(show("tid:7"), "Tid:^7$");
+        assert_eq!(
+            show("event.process_name:com.foo"),
+            r#"Event("process_name"):com\.foo"#
+        );
+        assert_eq!(show("level>=W"), "level Ge Warn");
+        assert_eq!(show("level<error"), "level Lt Error");
+        assert_eq!(show("level:i"), "level Eq Info");
+        // Not a field, so searched for as written.
+        assert_eq!(show("Error:42 http://x"), "(Error:42 AND http://x)");
+    }
+
+    #[test]
+    fn parses_quoted_literals_and_regexes() {
+        assert_eq!(
+            show(r#""Start proc" "a \"b\" (c)""#),
+            r#"(Start proc AND a "b" \(c\))"#
+        );
+        assert_eq!(show(r#"tag:"Activity Manager""#), "Tag:Activity Manager");
+
+        let literal = matcher("\"Start Proc\"");
+        assert!(literal.is_match("I am: start proc 123"));
+
+        let regex = matcher(r"/start\s+proc \d+/");
+        assert!(!regex.is_match("Start proc 123"));
+        assert!(regex.is_match("start  proc 123"));
+
+        let regex = matcher(r"/start\s+proc \d+/i");
+        assert!(regex.is_match("Start proc 123"));
+
+        let regex = matcher(r"msg:/a\/b/");
+        assert_eq!(regex.as_str(), "a/b");
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        assert_eq!(parse_error("(a OR b"), (0, 1, "Unclosed '('".to_string()));
+        assert_eq!(parse_error("a OR b)"), (6, 7, "Unexpected ')'".to_string()));
+        assert_eq!(parse_error("(a (b)"), (0, 1, "Unclosed '('".to_string()));
+        assert_eq!(
+            parse_error("a OR"),
+            (4, 4, "Unexpected end of query".to_string())
+        );
+        assert_eq!(
+            parse_error("tag:x colour:\"red\""),
+            (6, 12, "Unknown field 'colour'".to_string())
+        );
+        assert_eq!(
+            parse_error("a tag:"),
+            (2, 6, "Expected a value after the field".to_string())
+        );
+        assert_eq!(
+            parse_error("level>=Q"),
+            (0, 8, "Expected a level (V, D, I, W, E or F)".to_string())
+        );
+
+        let (start, end, message) = parse_error("ok /a(b/");
+        assert_eq!((start, end), (3, 8));
+        assert!(message.starts_with("Invalid regex: "), "{}", message);
+
+        // Positions count characters, not bytes.
+        let (start, end, _) = parse_error("é \"open");
+        assert_eq!((start, end), (2, 7));
+    }
+}
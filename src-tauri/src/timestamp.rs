@@ -0,0 +1,238 @@
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+/// Year assumed for logcat's year-less `MM-DD` timestamps, so they only
+/// order correctly against each other. A leap year, so `02-29` is valid.
+const DEFAULT_YEAR: i64 = 2000;
+
+/// Parses the timestamp at the start of `line` into nanoseconds since the
+/// Unix epoch. Recognized forms are
+/// `YYYY-MM-DD HH:MM:SS[.frac]`, `MM-DD HH:MM:SS[.frac]` and
+/// `SECONDS.frac` (logcat `-v epoch`), optionally preceded by whitespace,
+/// and any of them inside the `[ ... ]` that starts a logcat `long` header.
+/// Kernel lines are read by `parse_kernel`.
+pub fn parse_line_timestamp(line: &[u8]) -> Option<i64> {
+    let start = line.iter().position(|b| !b.is_ascii_whitespace())?;
+    let line = &line[start..];
+
+    parse_leading(line)
+        .or_else(|| {
+            let inner = line.strip_prefix(b"[ ")?;
+            parse_leading(trim_spaces(inner))
+        })
+        .or_else(|| parse_kernel(line))
+}
+
+fn parse_leading(s: &[u8]) -> Option<i64> {
+    parse_dated(s)
+        .or_else(|| parse_undated(s))
+        .or_else(|| parse_epoch(s))
+        .map(|(nanos, _)| nanos)
+}
+
+/// The `[ SECONDS.micros]` that starts a kernel log line, as printed by
+/// `dmesg` and in `last_kmsg`, optionally after a `<level>` prefix. The
+/// time counts from boot, like logcat `-v monotonic`.
+pub fn parse_kernel(line: &[u8]) -> Option<i64> {
+    let line = match line.strip_prefix(b"<") {
+        Some(rest) => {
+            let len = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+            rest.get(len..)?.strip_prefix(b">")?
+        }
+        None => line,
+    };
+    let inner = trim_spaces(line.strip_prefix(b"[")?);
+    let (nanos, len) = parse_seconds(inner)?;
+    (inner.get(len) == Some(&b']')).then_some(nanos)
+}
+
+fn trim_spaces(s: &[u8]) -> &[u8] {
+    let start = s.iter().take_while(|&&b| b == b' ').count();
+    &s[start..]
+}
+
+/// `YYYY-MM-DD HH:MM:SS[.frac]`. Returns the timestamp and bytes consumed.
+pub fn parse_dated(s: &[u8]) -> Option<(i64, usize)> {
+    let year = digits(s, 0, 4)?;
+    expect(s, 4, b'-')?;
+    let (nanos, len) = parse_undated_in_year(&s[5..], year)?;
+    Some((nanos, len + 5))
+}
+
+/// `MM-DD HH:MM:SS[.frac]`. Returns the timestamp and bytes consumed.
+pub fn parse_undated(s: &[u8]) -> Option<(i64, usize)> {
+    parse_undated_in_year(s, DEFAULT_YEAR)
+}
+
+fn parse_undated_in_year(s: &[u8], year: i64) -> Option<(i64, usize)> {
+    let month = digits(s, 0, 2)?;
+    expect(s, 2, b'-')?;
+    let day = digits(s, 3, 2)?;
+    if !matches!(s.get(5), Some(b' ') | Some(b'T')) {
+        return None;
+    }
+    let hour = digits(s, 6, 2)?;
+    expect(s, 8, b':')?;
+    let minute = digits(s, 9, 2)?;
+    expect(s, 11, b':')?;
+    let second = digits(s, 12, 2)?;
+
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let (frac, frac_len) = if s.get(14) == Some(&b'.') {
+        fraction(&s[15..]).map(|(n, l)| (n, l + 1))?
+    } else {
+        (0, 0)
+    };
+
+    let days = days_from_civil(year, month, day);
+    let secs = days * 86_400 + hour * 3_600 + minute * 60 + second;
+    Some((secs * NANOS_PER_SEC + frac, 14 + frac_len))
+}
+
+/// `SECONDS.frac`, as printed by logcat `-v epoch` and `-v monotonic`, and
+/// followed by a space or nothing. Returns the timestamp and bytes consumed.
+pub fn parse_epoch(s: &[u8]) -> Option<(i64, usize)> {
+    let (nanos, len) = parse_seconds(s)?;
+    matches!(s.get(len), None | Some(b' ')).then_some((nanos, len))
+}
+
+/// Seconds with the millisecond, microsecond or nanosecond fraction that
+/// logcat and the kernel print, so that numbers such as versions at the
+/// start of a line are not taken for times.
+fn parse_seconds(s: &[u8]) -> Option<(i64, usize)> {
+    let int_len = s.iter().take_while(|b| b.is_ascii_digit()).count();
+    if int_len == 0 || int_len > 12 || s.get(int_len) != Some(&b'.') {
+        return None;
+    }
+    let secs: i64 = std::str::from_utf8(&s[..int_len]).ok()?.parse().ok()?;
+    let (frac, frac_len) = fraction(&s[int_len + 1..])?;
+    if !matches!(frac_len, 3 | 6 | 9) {
+        return None;
+    }
+    Some((secs * NANOS_PER_SEC + frac, int_len + 1 + frac_len))
+}
+
+/// Parses up to nine fractional digits into nanoseconds.
+fn fraction(s: &[u8]) -> Option<(i64, usize)> {
+    let len = s.iter().take_while(|b| b.is_ascii_digit()).count();
+    if len == 0 {
+        return None;
+    }
+    let mut nanos = 0i64;
+    for (i, &b) in s[..len].iter().enumerate().take(9) {
+        nanos += (b - b'0') as i64 * 10i64.pow(8 - i as u32);
+    }
+    Some((nanos, len))
+}
+
+fn digits(s: &[u8], at: usize, len: usize) -> Option<i64> {
+    let bytes = s.get(at..at + len)?;
+    bytes.iter().try_fold(0i64, |acc, &b| {
+        b.is_ascii_digit().then(|| acc * 10 + (b - b'0') as i64)
+    })
+}
+
+fn expect(s: &[u8], at: usize, byte: u8) -> Option<()> {
+    (s.get(at) == Some(&byte)).then_some(())
+}
+
+/// Formats nanoseconds since the Unix epoch as
+/// `YYYY-MM-DD HH:MM:SS.nnnnnnnnn`, which `parse_dated` reads back.
+pub fn format_dated(nanos: i64) -> String {
+    let secs = nanos.div_euclid(NANOS_PER_SEC);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let time = secs.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:09}",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time / 60 % 60,
+        time % 60,
+        nanos.rem_euclid(NANOS_PER_SEC)
+    )
+}
+
+/// The proleptic Gregorian date of a day counted from 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Option<i64> {
+        parse_line_timestamp(line.as_bytes())
+    }
+
+    #[test]
+    fn parses_line_timestamps() {
+        let undated = parse("01-02 03:04:05.678 I/Tag( 1): x");
+        assert_eq!(undated, parse("2000-01-02 03:04:05.678 I/Tag( 1): x"));
+        assert_eq!(
+            parse("  2024-01-02 03:04:05.678901   600   620 I Tag: x").map(format_dated),
+            Some("2024-01-02 03:04:05.678901000".to_string())
+        );
+        assert_eq!(
+            parse("1704164645.678   600   620 I Tag: x"),
+            Some(1_704_164_645_678_000_000)
+        );
+        assert_eq!(
+            parse("   123.456789   600   620 I Tag: x"),
+            Some(123_456_789_000)
+        );
+        assert_eq!(parse("[ 01-02 03:04:05.678   600:  620 I/Tag ]"), undated);
+        assert_eq!(parse("[ 123.456  600:  620 I/Tag ]"), Some(123_456_000_000));
+
+        assert_eq!(parse("[    0.000000] Booting Linux"), Some(0));
+        assert_eq!(parse("[12345.678901] init: x"), Some(12_345_678_901_000));
+        assert_eq!(
+            parse("<6>[  123.456789] usb 1-1: new device"),
+            Some(123_456_789_000)
+        );
+
+        for untimed in [
+            "",
+            "3.14 is not a time",
+            "1.2 ",
+            "1704164645.678:",
+            "2.0.1 release notes",
+            "123.4567 x",
+            "[ 1.5] x",
+            "[123.456789 missing bracket",
+            "<6> [1.000000] x",
+            "13-02 03:04:05.678 bad month",
+            "\tat com.foo.Bar(Bar.java:12)",
+        ] {
+            assert_eq!(parse(untimed), None, "{:?}", untimed);
+        }
+    }
+}
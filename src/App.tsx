@@ -1,4 +1,4 @@
-import { useState, useEffect, useCallback } from "react";
+import { useState, useEffect, useCallback, useRef } from "react";
 import {
   Search,
   Menu,
@@ -9,93 +9,173 @@ import {
   Trash2,
   Filter,
   Loader2,
-  Smartphone
+  Smartphone,
+  Eye
 } from "lucide-react";
 import { open } from "@tauri-apps/plugin-dialog";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 import { LogViewer } from "./components/LogViewer";
-import { FilterSidebar, LogFilter } from "./components/FilterSidebar";
+import { ContextLines, FilterSidebar, LogFilter, LogLevel, QueryError } from "./components/FilterSidebar";
+
+interface AdbDevice {
+  serial: string;
+  state: string;
+  model: string | null;
+  product: string | null;
+}
 
 function App() {
   const [isSidebarOpen, setIsSidebarOpen] = useState(true);
   const [filePath, setFilePath] = useState<string | null>(null);
+  const [documentId, setDocumentId] = useState<number | null>(null);
+  const documentIdRef = useRef<number | null>(null);
+  const filterRunRef = useRef(0);
   const [lineCount, setLineCount] = useState(0);
   const [visibleLineCount, setVisibleLineCount] = useState(0);
   const [isIndexing, setIsIndexing] = useState(false);
   const [isFiltering, setIsFiltering] = useState(false);
   const [isAdbActive, setIsAdbActive] = useState(false);
+  const [isFollowing, setIsFollowing] = useState(false);
   const [indexProgress, setIndexProgress] = useState(0);
+  const [filterProgress, setFilterProgress] = useState(0);
   const [fontSize, setFontSize] = useState(13);
   const [filters, setFilters] = useState<LogFilter[]>([]);
   const [searchQuery, setSearchQuery] = useState("");
+  const [minLevel, setMinLevel] = useState<LogLevel | null>(null);
+  const [filterQuery, setFilterQuery] = useState("");
+  const [context, setContext] = useState<ContextLines>({ before: 0, after: 0 });
+  const [queryError, setQueryError] = useState<QueryError | null>(null);
   const [errorMessage, setErrorMessage] = useState<string | null>(null);
   const [isHighDensity, setIsHighDensity] = useState(false);
   const [filterCounts, setFilterCounts] = useState<Record<string, number>>({});
+  const [devices, setDevices] = useState<AdbDevice[]>([]);
+  const [deviceSerial, setDeviceSerial] = useState<string | null>(null);
+  const [logcatFormat, setLogcatFormat] = useState("threadtime");
+  const [logcatBuffers, setLogcatBuffers] = useState("");
+  const [logcatBinary, setLogcatBinary] = useState(false);
+  const [eventTagCount, setEventTagCount] = useState(0);
 
   useEffect(() => {
-    const unlistenIndex = listen<{ progress: number }>("indexing-progress", (event) => {
+    documentIdRef.current = documentId;
+  }, [documentId]);
+
+  useEffect(() => {
+    const unlistenIndex = listen<{ document_id: number, progress: number }>("indexing-progress", (event) => {
       setIndexProgress(event.payload.progress * 100);
     });
 
-    const unlistenAdb = listen<string[]>("adb-new-lines", (event) => {
-      setLineCount((prev) => {
-        const next = prev + event.payload.length;
-        setVisibleLineCount(next);
-        return next;
-      });
+    const unlistenFilter = listen<{ document_id: number, progress: number }>("filter-progress", (event) => {
+      if (event.payload.document_id !== documentIdRef.current) return;
+      setFilterProgress(event.payload.progress * 100);
+    });
+
+    const unlistenAdb = listen<{ document_id: number, first_index: number, lines: string[], line_count: number }>("adb-new-lines", (event) => {
+      if (event.payload.document_id !== documentIdRef.current) return;
+      setLineCount(event.payload.line_count);
+      setVisibleLineCount(event.payload.first_index + event.payload.lines.length);
+    });
+
+    const unlistenAdbError = listen<{ document_id: number, message: string }>("adb-error", (event) => {
+      if (event.payload.document_id !== documentIdRef.current) return;
+      setErrorMessage(event.payload.message);
+    });
+
+    const unlistenAdbExited = listen<{ document_id: number, status: number | null, stderr: string, reconnecting: boolean }>("adb-exited", (event) => {
+      if (event.payload.document_id !== documentIdRef.current || !event.payload.reconnecting) return;
+      const status = event.payload.status === null ? "was killed" : `exited with status ${event.payload.status}`;
+      setErrorMessage(`adb logcat ${status}${event.payload.stderr ? `: ${event.payload.stderr}` : ""}. Waiting for the device...`);
+    });
+
+    const unlistenAdbStopped = listen<{ document_id: number, error: string | null }>("adb-stopped", (event) => {
+      if (event.payload.document_id !== documentIdRef.current) return;
+      setIsAdbActive(false);
+      if (event.payload.error) setErrorMessage(event.payload.error);
+    });
+
+    const unlistenFollow = listen<{ document_id: number, first_index: number, lines: string[], line_count: number }>("file-new-lines", (event) => {
+      if (event.payload.document_id !== documentIdRef.current) return;
+      setLineCount(event.payload.line_count);
+      setVisibleLineCount(event.payload.first_index + event.payload.lines.length);
+    });
+
+    const unlistenReindexed = listen<{ document_id: number, line_count: number, visible_count: number }>("file-reindexed", (event) => {
+      if (event.payload.document_id !== documentIdRef.current) return;
+      setLineCount(event.payload.line_count);
+      setVisibleLineCount(event.payload.visible_count);
+    });
+
+    const unlistenFollowError = listen<{ document_id: number, message: string }>("follow-error", (event) => {
+      if (event.payload.document_id !== documentIdRef.current) return;
+      setIsFollowing(false);
+      setErrorMessage(event.payload.message);
     });
 
     return () => {
       unlistenIndex.then((fn) => fn());
+      unlistenAdbError.then((fn) => fn());
+      unlistenAdbExited.then((fn) => fn());
+      unlistenAdbStopped.then((fn) => fn());
+      unlistenFilter.then((fn) => fn());
       unlistenAdb.then((fn) => fn());
+      unlistenFollow.then((fn) => fn());
+      unlistenReindexed.then((fn) => fn());
+      unlistenFollowError.then((fn) => fn());
     };
   }, []);
 
-  const handleApplyFilters = useCallback(async (currentFilters: LogFilter[], query: string) => {
-    if (!filePath && !isAdbActive) return;
+  useEffect(() => {
+    invoke<QueryError | null>("check_query", { query: filterQuery })
+      .then(setQueryError)
+      .catch((error) => console.error("Failed to check query:", error));
+  }, [filterQuery]);
 
-    // If ADB is active, we don't apply filters to existing lines in this MVP.
-    // Filters are applied by the backend during ingestion.
-    if (isAdbActive) return;
+  const handleApplyFilters = useCallback(async (currentFilters: LogFilter[], query: string, level: LogLevel | null, expression: string, contextLines: ContextLines) => {
+    if (documentId === null || (!filePath && !isAdbActive)) return;
 
+    const run = ++filterRunRef.current;
     setIsFiltering(true);
+    setFilterProgress(0);
     try {
-      const result = await invoke<{ visible_count: number, filter_counts: Record<string, number> }>("apply_filters", { filters: currentFilters, searchQuery: query });
+      // A newer call cancels this one on the backend, which then returns null.
+      const result = await invoke<{ visible_count: number, match_count: number, filter_counts: Record<string, number> } | null>("apply_filters", { documentId, filters: currentFilters, searchQuery: query, minLevel: level, query: expression, context: contextLines });
+      if (result === null) return;
       setVisibleLineCount(result.visible_count);
       setFilterCounts(result.filter_counts);
     } catch (error) {
       console.error("Failed to apply filters:", error);
     } finally {
-      setIsFiltering(false);
+      if (run === filterRunRef.current) setIsFiltering(false);
     }
-  }, [filePath, isAdbActive]);
+  }, [documentId, filePath, isAdbActive]);
 
   useEffect(() => {
     const timer = setTimeout(() => {
-      handleApplyFilters(filters, searchQuery);
+      if (!queryError) handleApplyFilters(filters, searchQuery, minLevel, filterQuery, context);
     }, 500);
     return () => clearTimeout(timer);
-  }, [filters, searchQuery, handleApplyFilters]);
+  }, [filters, searchQuery, minLevel, filterQuery, context, queryError, handleApplyFilters]);
 
   const handleOpenFile = async () => {
     setErrorMessage(null);
     try {
       const selected = await open({
         multiple: false,
-        filters: [{ name: "Logs", extensions: ["log", "txt", "out"] }]
+        filters: [{ name: "Logs", extensions: ["log", "txt", "out", "gz", "zst", "xz", "bz2", "zip"] }]
       });
 
       if (selected && typeof selected === "string") {
         if (isAdbActive) await handleStopAdb();
+        await handleCloseDocument();
 
         setFilePath(selected);
         setIsIndexing(true);
         setIndexProgress(0);
 
-        const count = await invoke<number>("open_file", { path: selected });
-        setLineCount(count);
-        setVisibleLineCount(count);
+        const opened = await invoke<{ document_id: number, line_count: number }>("open_file", { path: selected });
+        setDocumentId(opened.document_id);
+        setLineCount(opened.line_count);
+        setVisibleLineCount(opened.line_count);
         setIsIndexing(false);
       }
     } catch (error) {
@@ -114,12 +194,72 @@ function App() {
     }
   };
 
+  const handleToggleFollow = async () => {
+    if (documentId === null) return;
+    setErrorMessage(null);
+    try {
+      await invoke(isFollowing ? "stop_follow" : "start_follow", { documentId });
+      setIsFollowing(!isFollowing);
+    } catch (error) {
+      console.error("Failed to toggle follow mode:", error);
+      setErrorMessage(String(error));
+    }
+  };
+
+  const handleCloseDocument = async () => {
+    setIsFollowing(false);
+    if (documentId === null) return;
+    try {
+      await invoke("close_document", { documentId });
+    } catch (error) {
+      console.error("Failed to close document:", error);
+    }
+    setDocumentId(null);
+  };
+
+  const handleRefreshDevices = async () => {
+    try {
+      const listed = await invoke<AdbDevice[]>("list_devices");
+      setDevices(listed);
+      if (!listed.some(d => d.serial === deviceSerial)) {
+        setDeviceSerial(listed.find(d => d.state === "device")?.serial ?? null);
+      }
+    } catch (error) {
+      console.error("Failed to list devices:", error);
+      setErrorMessage(String(error));
+    }
+  };
+
+  const handleLoadEventTags = async () => {
+    setErrorMessage(null);
+    try {
+      setEventTagCount(await invoke<number>("load_event_tags", { serial: deviceSerial }));
+    } catch {
+      // Without a reachable device, fall back to a local copy of the file.
+      const selected = await open({ multiple: false });
+      if (!selected || typeof selected !== "string") return;
+      try {
+        setEventTagCount(await invoke<number>("load_event_tags", { path: selected }));
+      } catch (error) {
+        console.error("Failed to load event tags:", error);
+        setErrorMessage(String(error));
+      }
+    }
+  };
+
   const handleStartAdb = async () => {
     try {
+      await handleCloseDocument();
       setFilePath(null);
       setLineCount(0);
       setVisibleLineCount(0);
-      await invoke("start_adb", { filters, searchQuery });
+      const logcat = {
+        format: logcatBinary ? null : logcatFormat,
+        binary: logcatBinary,
+        buffers: logcatBuffers.split(/[\s,]+/).filter(b => b.length > 0),
+      };
+      const id = await invoke<number>("start_adb", { serial: deviceSerial, logcat, filters, searchQuery, minLevel, query: queryError ? "" : filterQuery, context });
+      setDocumentId(id);
       setIsAdbActive(true);
     } catch (error) {
       console.error("Failed to start ADB:", error);
@@ -129,7 +269,7 @@ function App() {
 
   const handleStopAdb = async () => {
     try {
-      await invoke("stop_adb");
+      await invoke("stop_adb", { documentId });
       setIsAdbActive(false);
     } catch (error) {
       console.error("Failed to stop ADB:", error);
@@ -217,6 +357,24 @@ function App() {
               <Smartphone size={14} />
               <span className="font-medium">ADB Logcat</span>
             </button>
+            {filePath && (
+              <button
+                onClick={handleToggleFollow}
+                disabled={isIndexing || documentId === null}
+                className={`
+                  flex items-center gap-2 px-3 py-1.5 rounded-lg border text-xs 
+                  transition-all duration-fast hover:scale-[1.02] active:scale-[0.98]
+                  ${isFollowing
+                    ? "bg-success/10 border-success/40 text-success shadow-[0_0_15px_rgba(16,185,129,0.2)]"
+                    : "bg-accent/50 border-border text-muted-foreground hover:bg-accent hover:shadow-md"
+                  }
+                `}
+                title="Watch the file for appended lines, like tail -f; re-indexes it when it is truncated or rotated"
+              >
+                <Eye size={14} />
+                <span className="font-medium">{isFollowing ? "Following" : "Follow"}</span>
+              </button>
+            )}
           </div>
         </div>
 
@@ -237,10 +395,119 @@ function App() {
               "
             />
           </div>
+          <div className="relative">
+            <input
+              type="text"
+              placeholder='Query, e.g. tag:Wifi AND level>=W'
+              value={filterQuery}
+              onChange={(e) => setFilterQuery(e.target.value)}
+              title={queryError ? `${queryError.message} (column ${queryError.start + 1})` : undefined}
+              className={`
+                px-3 py-1.5 bg-accent/50 border rounded-lg text-xs font-mono
+                transition-all duration-fast
+                focus:outline-none focus:ring-2 focus:ring-primary/50 focus:bg-accent
+                w-72
+                ${queryError ? "border-destructive" : "border-border hover:border-border/80"}
+              `}
+            />
+            {queryError && (
+              <div className="absolute left-0 top-full mt-1 z-50 px-2 py-1 rounded-md bg-card border border-destructive/40 shadow-md text-[10px] font-mono whitespace-pre max-w-md overflow-hidden">
+                <span>{filterQuery.slice(0, queryError.start)}</span>
+                <span className="underline decoration-wavy decoration-destructive text-destructive">
+                  {filterQuery.slice(queryError.start, Math.max(queryError.end, queryError.start + 1)) || " "}
+                </span>
+                <span>{filterQuery.slice(Math.max(queryError.end, queryError.start + 1))}</span>
+                <div className="text-destructive mt-0.5">{queryError.message}</div>
+              </div>
+            )}
+          </div>
+          <select
+            value={minLevel || ""}
+            onChange={(e) => setMinLevel((e.target.value || null) as LogLevel | null)}
+            className="px-2 py-1.5 bg-accent/50 border border-border rounded-lg text-xs transition-all duration-fast focus:outline-none focus:ring-2 focus:ring-primary/50 hover:border-border/80 cursor-pointer"
+            title="Minimum level"
+          >
+            <option value="">All levels</option>
+            <option value="D">Debug+</option>
+            <option value="I">Info+</option>
+            <option value="W">Warn+</option>
+            <option value="E">Error+</option>
+            <option value="F">Fatal</option>
+          </select>
+          <input
+            type="number"
+            min={0}
+            value={context.before}
+            onChange={(e) => setContext({ ...context, before: Math.max(0, parseInt(e.target.value) || 0) })}
+            className="w-12 px-2 py-1.5 bg-accent/50 border border-border rounded-lg text-xs transition-all duration-fast focus:outline-none focus:ring-2 focus:ring-primary/50 hover:border-border/80"
+            title="Context lines before each match (-B)"
+          />
+          <input
+            type="number"
+            min={0}
+            value={context.after}
+            onChange={(e) => setContext({ ...context, after: Math.max(0, parseInt(e.target.value) || 0) })}
+            className="w-12 px-2 py-1.5 bg-accent/50 border border-border rounded-lg text-xs transition-all duration-fast focus:outline-none focus:ring-2 focus:ring-primary/50 hover:border-border/80"
+            title="Context lines after each match (-A)"
+          />
           <div className="h-6 w-[1px] bg-border/50 mx-2"></div>
+          <select
+            value={deviceSerial ?? ""}
+            onChange={(e) => setDeviceSerial(e.target.value || null)}
+            onFocus={handleRefreshDevices}
+            disabled={isAdbActive}
+            className="px-3 py-1.5 bg-accent/50 border border-border rounded-lg text-xs transition-all duration-fast focus:outline-none focus:ring-2 focus:ring-primary/50 hover:border-border/80"
+            title="Device to stream from"
+          >
+            <option value="">Default device</option>
+            {devices.map(d => (
+              <option key={d.serial} value={d.serial} disabled={d.state !== "device"}>
+                {d.model ?? d.serial}{d.state !== "device" ? ` (${d.state})` : ""}
+              </option>
+            ))}
+          </select>
+          <select
+            value={logcatFormat}
+            onChange={(e) => setLogcatFormat(e.target.value)}
+            disabled={isAdbActive || logcatBinary}
+            className="px-3 py-1.5 bg-accent/50 border border-border rounded-lg text-xs transition-all duration-fast focus:outline-none focus:ring-2 focus:ring-primary/50 hover:border-border/80"
+            title="Logcat output format (-v)"
+          >
+            {["threadtime", "time", "brief", "process", "tag", "thread", "long"].map(f => (
+              <option key={f} value={f}>{f}</option>
+            ))}
+          </select>
+          <input
+            type="text"
+            placeholder="main, system, crash..."
+            value={logcatBuffers}
+            onChange={(e) => setLogcatBuffers(e.target.value)}
+            disabled={isAdbActive}
+            className="w-36 px-3 py-1.5 bg-accent/50 border border-border rounded-lg text-xs transition-all duration-fast focus:outline-none focus:ring-2 focus:ring-primary/50 hover:border-border/80"
+            title="Logcat buffers (-b), default set when empty"
+          />
+          <label
+            className="flex items-center gap-1.5 text-xs text-muted-foreground"
+            title="Read binary log entries (-B), keeping uids, nanosecond timestamps and multi-line messages"
+          >
+            <input
+              type="checkbox"
+              checked={logcatBinary}
+              onChange={(e) => setLogcatBinary(e.target.checked)}
+              disabled={isAdbActive}
+            />
+            Binary
+          </label>
+          <button
+            onClick={handleLoadEventTags}
+            className="px-3 py-1.5 bg-accent/50 border border-border rounded-lg text-xs transition-all duration-fast hover:border-border/80"
+            title="Load event-log-tags from the device, or from a file when none is reachable, to name the fields of events-buffer entries"
+          >
+            {eventTagCount > 0 ? `${eventTagCount} event tags` : "Event tags"}
+          </button>
           <button
             className="p-2 hover:bg-destructive/10 hover:text-destructive rounded-lg text-muted-foreground transition-all duration-fast hover:scale-110 active:scale-90"
-            onClick={() => { setFilePath(null); setLineCount(0); setVisibleLineCount(0); if (isAdbActive) handleStopAdb(); }}
+            onClick={() => { setFilePath(null); setLineCount(0); setVisibleLineCount(0); setIsAdbActive(false); handleCloseDocument(); }}
             title="Clear all"
           >
             <Trash2 size={18} />
@@ -285,7 +552,7 @@ function App() {
         {isFiltering && !isAdbActive && (
           <div className="absolute top-4 right-4 z-50 bg-card/80 backdrop-blur-md border border-primary/20 px-4 py-2 rounded-full shadow-glow flex items-center gap-3 animate-in fade-in slide-in-from-top-2 duration-300">
             <Loader2 size={16} className="animate-spin text-primary" />
-            <span className="text-xs font-bold tracking-tight text-primary">FILTERING...</span>
+            <span className="text-xs font-bold tracking-tight text-primary">FILTERING... <span className="tabular-nums">{filterProgress.toFixed(0)}%</span></span>
           </div>
         )}
 
@@ -296,14 +563,18 @@ function App() {
           </div>
         )}
 
-        {filePath || isAdbActive ? (
+        {documentId !== null && (filePath || isAdbActive) ? (
           <LogViewer
-            key={filePath || "adb-stream"}
+            key={documentId}
+            documentId={documentId}
             filePath={filePath || "ADB_STREAM"}
             lineCount={visibleLineCount}
             fontSize={fontSize}
             filters={filters}
             searchQuery={searchQuery}
+            minLevel={minLevel}
+            query={queryError ? "" : filterQuery}
+            context={context}
             isHighDensity={isHighDensity}
           />
         ) : (
@@ -1,5 +1,15 @@
 import { Plus, Trash2, Eye, EyeOff, ChevronLeft, ChevronRight } from "lucide-react";
 
+export type FilterField = "tag" | "pid" | "tid" | "level" | "message" | "package" | "process" | { event: string };
+
+export type LogLevel = "V" | "D" | "I" | "W" | "E" | "F";
+
+export interface QueryError {
+  start: number;
+  end: number;
+  message: string;
+}
+
 export interface LogFilter {
   id: string;
   pattern: string;
@@ -7,6 +17,14 @@ export interface LogFilter {
   is_enabled: boolean;
   color?: string;
   text_color?: string;
+  field?: FilterField | null;
+  context_before?: number;
+  context_after?: number;
+}
+
+export interface ContextLines {
+  before: number;
+  after: number;
 }
 
 interface FilterSidebarProps {
@@ -94,6 +112,57 @@ export function FilterSidebar({
                 <option value="exclude">Exc</option>
               </select>
 
+              <select
+                value={typeof filter.field === "object" && filter.field ? "event" : filter.field || ""}
+                onChange={(e) => onUpdateFilter(filter.id, {
+                  field: e.target.value === "event" ? { event: "" } : (e.target.value || null) as FilterField | null
+                })}
+                className="bg-accent/40 border border-border/30 text-[9px] px-1.5 py-0.5 rounded-md uppercase font-bold tracking-tight text-muted-foreground transition-all cursor-pointer focus:outline-none focus:ring-1 focus:ring-primary/20"
+                title="Field to match"
+              >
+                <option value="">Line</option>
+                <option value="tag">Tag</option>
+                <option value="pid">PID</option>
+                <option value="tid">TID</option>
+                <option value="level">Level</option>
+                <option value="message">Msg</option>
+                <option value="package">Pkg</option>
+                <option value="process">Proc</option>
+                <option value="event">Event</option>
+              </select>
+
+              {typeof filter.field === "object" && filter.field && (
+                <input
+                  type="text"
+                  value={filter.field.event}
+                  onChange={(e) => onUpdateFilter(filter.id, { field: { event: e.target.value } })}
+                  placeholder="field"
+                  className="w-16 bg-accent/40 border border-border/30 text-[9px] px-1.5 py-0.5 rounded-md text-muted-foreground focus:outline-none focus:ring-1 focus:ring-primary/20"
+                  title="Event field name from event-log-tags, e.g. process_name"
+                />
+              )}
+
+              {filter.is_include && (
+                <>
+                  <input
+                    type="number"
+                    min={0}
+                    value={filter.context_before || 0}
+                    onChange={(e) => onUpdateFilter(filter.id, { context_before: Math.max(0, parseInt(e.target.value) || 0) })}
+                    className="w-8 bg-accent/40 border border-border/30 text-[9px] px-1 py-0.5 rounded-md font-bold text-muted-foreground focus:outline-none focus:ring-1 focus:ring-primary/20"
+                    title="Context lines before each hit (-B)"
+                  />
+                  <input
+                    type="number"
+                    min={0}
+                    value={filter.context_after || 0}
+                    onChange={(e) => onUpdateFilter(filter.id, { context_after: Math.max(0, parseInt(e.target.value) || 0) })}
+                    className="w-8 bg-accent/40 border border-border/30 text-[9px] px-1 py-0.5 rounded-md font-bold text-muted-foreground focus:outline-none focus:ring-1 focus:ring-primary/20"
+                    title="Context lines after each hit (-A)"
+                  />
+                </>
+              )}
+
               <div className="flex items-center gap-1 px-1.5 py-0.5 bg-accent/10 rounded-md border border-border/5">
                 <span className="text-[10px] font-mono font-bold text-foreground/70 tabular-nums">
                   {(filterCounts[filter.id] || 0).toLocaleString()}
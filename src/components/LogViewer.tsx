@@ -1,34 +1,73 @@
-import { useState, useEffect, useRef, useCallback } from "react";
+import { useState, useEffect, useRef, useCallback, FormEvent, ReactNode } from "react";
 import { Virtuoso, VirtuosoHandle } from "react-virtuoso";
 import { invoke } from "@tauri-apps/api/core";
-import { LogFilter } from "./FilterSidebar";
+import { ContextLines, LogFilter, LogLevel } from "./FilterSidebar";
 import { Minimap } from "./Minimap";
 
 interface LogViewerProps {
+  documentId: number;
   filePath: string | null;
   lineCount: number;
   fontSize: number;
   filters: LogFilter[];
   searchQuery: string;
+  minLevel: LogLevel | null;
+  query: string;
+  context: ContextLines;
   isHighDensity?: boolean;
 }
 
-export function LogViewer({ filePath, lineCount, fontSize, filters, searchQuery, isHighDensity }: LogViewerProps) {
+interface HighlightSpan {
+  start: number;
+  end: number;
+  filter_id: string | null;
+  color: string;
+  text_color: string | null;
+}
+
+interface VisibleLine {
+  line_number: number;
+  byte_offset: number | null;
+  text: string;
+  process: string | null;
+}
+
+interface ViewPosition {
+  position: number;
+  exact: boolean;
+}
+
+interface LineMark {
+  context: boolean;
+  gap_before: boolean;
+}
+
+export function LogViewer({ documentId, filePath, lineCount, fontSize, filters, searchQuery, minLevel, query, context, isHighDensity }: LogViewerProps) {
   const virtuosoRef = useRef<VirtuosoHandle>(null);
-  const [loadedLines, setLoadedLines] = useState<Map<number, string>>(new Map());
+  const [loadedLines, setLoadedLines] = useState<Map<number, VisibleLine>>(new Map());
+  const [highlights, setHighlights] = useState<Map<number, HighlightSpan[]>>(new Map());
+  const [marks, setMarks] = useState<Map<number, LineMark>>(new Map());
   const [visibleRange, setVisibleRange] = useState({ startIndex: 0, endIndex: 100 });
+  const [goToLine, setGoToLine] = useState("");
 
   // Clear cache when file OR filters OR search change
   useEffect(() => {
     setLoadedLines(new Map());
-  }, [filePath, lineCount, searchQuery]);
+    setMarks(new Map());
+  }, [filePath, lineCount, searchQuery, context]);
+
+  useEffect(() => {
+    setHighlights(new Map());
+  }, [filePath, lineCount, searchQuery, filters]);
 
   const loadMore = useCallback(async (startIndex: number, count: number) => {
     try {
-      const lines = await invoke<string[]>("get_log_lines", {
-        startIndex,
-        count: Math.min(count, lineCount - startIndex),
-      });
+      const range = { documentId, startIndex, count: Math.min(count, lineCount - startIndex) };
+      const [lines, spans, lineMarks] = await Promise.all([
+        invoke<VisibleLine[]>("get_log_lines", range),
+        invoke<HighlightSpan[][]>("get_line_highlights", { ...range, filters, searchQuery }),
+        invoke<LineMark[]>("get_line_marks", range),
+      ]);
 
       setLoadedLines((prev) => {
         const next = new Map(prev);
@@ -37,10 +76,36 @@ export function LogViewer({ filePath, lineCount, fontSize, filters, searchQuery,
         });
         return next;
       });
+      setHighlights((prev) => {
+        const next = new Map(prev);
+        spans.forEach((lineSpans, i) => {
+          next.set(startIndex + i, lineSpans);
+        });
+        return next;
+      });
+      setMarks((prev) => {
+        const next = new Map(prev);
+        lineMarks.forEach((mark, i) => {
+          next.set(startIndex + i, mark);
+        });
+        return next;
+      });
     } catch (error) {
       console.error("Failed to fetch log lines:", error);
     }
-  }, [lineCount]);
+  }, [documentId, lineCount, filters, searchQuery]);
+
+  const handleGoToLine = async (e: FormEvent) => {
+    e.preventDefault();
+    const lineNumber = parseInt(goToLine);
+    if (!(lineNumber > 0)) return;
+    try {
+      const target = await invoke<ViewPosition | null>("get_view_position", { documentId, lineNumber: lineNumber - 1 });
+      if (target) virtuosoRef.current?.scrollToIndex({ index: target.position, align: 'center', behavior: 'auto' });
+    } catch (error) {
+      console.error("Failed to locate line:", error);
+    }
+  };
 
   const getLogLevel = (line: string): 'ERROR' | 'WARN' | 'INFO' | 'DEBUG' | 'VERBOSE' | null => {
     // Standard Android/Unix patterns: [E],  E , ERROR, E/
@@ -104,7 +169,34 @@ export function LogViewer({ filePath, lineCount, fontSize, filters, searchQuery,
     return {};
   };
 
-  const renderLineContent = (line: string) => {
+  const renderLineContent = (line: string, spans: HighlightSpan[] | undefined) => {
+    if (spans) {
+      if (spans.length === 0) return line;
+      const parts: ReactNode[] = [];
+      let pos = 0;
+      spans.forEach((span, i) => {
+        if (span.start > pos) parts.push(line.slice(pos, span.start));
+        parts.push(
+          span.filter_id === null ? (
+            <mark key={i} className="bg-primary/40 text-white rounded-sm px-0.5 border-b border-primary shadow-[0_0_8px_rgba(250,95,235,0.4)]">
+              {line.slice(span.start, span.end)}
+            </mark>
+          ) : (
+            <mark
+              key={i}
+              className="rounded-sm"
+              style={{ backgroundColor: `${span.color}66`, color: span.text_color || "inherit" }}
+            >
+              {line.slice(span.start, span.end)}
+            </mark>
+          )
+        );
+        pos = span.end;
+      });
+      if (pos < line.length) parts.push(line.slice(pos));
+      return parts;
+    }
+
     if (!searchQuery || searchQuery.length < 2) return line;
 
     try {
@@ -126,9 +218,9 @@ export function LogViewer({ filePath, lineCount, fontSize, filters, searchQuery,
   };
 
   const rowRenderer = (index: number) => {
-    const line = loadedLines.get(index);
+    const entry = loadedLines.get(index);
 
-    if (line === undefined) {
+    if (entry === undefined) {
       const batchStart = Math.max(0, index - 50);
       loadMore(batchStart, 100);
       return (
@@ -141,11 +233,13 @@ export function LogViewer({ filePath, lineCount, fontSize, filters, searchQuery,
       );
     }
 
+    const line = entry.text;
     const level = getLogLevel(line);
     const levelStyle = getLevelStyle(level);
     const filterStyle = getLineStyle(line);
     const combinedStyle = { ...levelStyle, ...filterStyle };
     const isEven = index % 2 === 0;
+    const mark = marks.get(index);
 
     return (
       <div
@@ -155,6 +249,8 @@ export function LogViewer({ filePath, lineCount, fontSize, filters, searchQuery,
           hover:bg-primary/5 hover:border-l-primary/50
           ${isEven ? 'bg-black/[0.02]' : 'bg-transparent'}
           ${isHighDensity ? 'py-0 tracking-tight' : 'py-0.5'}
+          ${mark?.context ? 'opacity-60' : ''}
+          ${mark?.gap_before ? 'border-t border-t-border border-dashed' : ''}
         `}
         style={{
           fontSize: `${isHighDensity ? fontSize - 1 : fontSize}px`,
@@ -162,10 +258,21 @@ export function LogViewer({ filePath, lineCount, fontSize, filters, searchQuery,
           ...combinedStyle
         }}
       >
-        <span className="inline-block w-12 text-muted-foreground/30 select-none mr-4 text-right tabular-nums font-normal border-r border-border/20 pr-4">
-          {index + 1}
+        <span
+          className="inline-block w-12 text-muted-foreground/30 select-none mr-4 text-right tabular-nums font-normal border-r border-border/20 pr-4"
+          title={entry.byte_offset !== null ? `Byte offset ${entry.byte_offset}` : undefined}
+        >
+          {entry.line_number + 1}
         </span>
-        {renderLineContent(line || " ")}
+        {entry.process && (
+          <span
+            className="inline-block w-40 truncate align-bottom text-muted-foreground/60 select-none mr-4 font-normal"
+            title={entry.process}
+          >
+            {entry.process}
+          </span>
+        )}
+        {line ? renderLineContent(line, highlights.get(index)) : " "}
       </div>
     );
   };
@@ -176,7 +283,17 @@ export function LogViewer({ filePath, lineCount, fontSize, filters, searchQuery,
 
   return (
     <div className="w-full h-full bg-background overflow-hidden flex">
-      <div className="flex-1 min-w-0">
+      <div className="flex-1 min-w-0 relative">
+        <form onSubmit={handleGoToLine} className="absolute top-2 right-4 z-10">
+          <input
+            type="number"
+            min={1}
+            value={goToLine}
+            onChange={(e) => setGoToLine(e.target.value)}
+            placeholder="Go to line"
+            className="w-28 px-2 py-1 bg-background/90 border border-border rounded-lg text-xs focus:outline-none focus:ring-2 focus:ring-primary/50"
+          />
+        </form>
         <Virtuoso
           ref={virtuosoRef}
           totalCount={lineCount}
@@ -189,9 +306,12 @@ export function LogViewer({ filePath, lineCount, fontSize, filters, searchQuery,
       </div>
       {(filePath || lineCount > 0) && (
         <Minimap
+          documentId={documentId}
           filePath={filePath}
           filters={filters}
           searchQuery={searchQuery}
+          minLevel={minLevel}
+          query={query}
           totalLines={lineCount}
           onScrollTo={(index) => virtuosoRef.current?.scrollToIndex({ index, align: 'start', behavior: 'auto' })}
           visibleRange={visibleRange}
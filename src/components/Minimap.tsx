@@ -1,11 +1,14 @@
 import { useEffect, useRef, useState, useCallback } from "react";
 import { invoke } from "@tauri-apps/api/core";
-import { LogFilter } from "./FilterSidebar";
+import { LogFilter, LogLevel } from "./FilterSidebar";
 
 interface MinimapProps {
+    documentId: number;
     filePath: string | null;
     filters: LogFilter[];
     searchQuery: string;
+    minLevel: LogLevel | null;
+    query: string;
     totalLines: number;
     onScrollTo: (index: number) => void;
     visibleRange: { startIndex: number; endIndex: number };
@@ -16,7 +19,7 @@ interface MinimapBucket {
     color: string | null;
 }
 
-export function Minimap({ filePath, filters, searchQuery, totalLines, onScrollTo, visibleRange }: MinimapProps) {
+export function Minimap({ documentId, filePath, filters, searchQuery, minLevel, query, totalLines, onScrollTo, visibleRange }: MinimapProps) {
     const canvasRef = useRef<HTMLCanvasElement>(null);
     const [minimapData, setMinimapData] = useState<MinimapBucket[]>([]);
     const containerRef = useRef<HTMLDivElement>(null);
@@ -35,15 +38,18 @@ export function Minimap({ filePath, filters, searchQuery, totalLines, onScrollTo
         try {
             const height = containerRef.current?.clientHeight || 800;
             const data = await invoke<MinimapBucket[]>("get_minimap_data", {
+                documentId,
                 filters,
                 searchQuery,
+                minLevel,
+                query,
                 buckets: height
             });
             setMinimapData(data);
         } catch (e) {
             console.error("Failed to fetch minimap data", e);
         }
-    }, [filePath, filters, searchQuery, totalLines, isReady]);
+    }, [documentId, filePath, filters, searchQuery, minLevel, query, totalLines, isReady]);
 
     useEffect(() => {
         fetchMinimapData();